@@ -0,0 +1,83 @@
+//! Compares the multiexp with and without precomputed multiples of the
+//! bases.
+//!
+//! Run with `cargo bench --bench multiexp [log_size...]`.
+
+use std::env;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use bellperson::multicore::Worker;
+use bellperson::multiexp::{multiexp, FullDensity, PrecomputedBases};
+use ff::{Field, PrimeField, ScalarEngine};
+use groupy::CurveProjective;
+use paired::bls12_381::Bls12;
+use paired::Engine;
+
+/// The number of multiples per base for the precomputed multiexp.
+const PRECOMPUTED_MULTIPLES: usize = 4;
+
+fn time<F: FnOnce() -> R, R>(f: F) -> (R, Duration) {
+    let start = Instant::now();
+    let res = f();
+    (res, start.elapsed())
+}
+
+fn main() {
+    let log_sizes: Vec<usize> = {
+        let args: Vec<usize> = env::args().skip(1).filter_map(|a| a.parse().ok()).collect();
+        if args.is_empty() {
+            vec![12, 14, 16, 18, 20]
+        } else {
+            args
+        }
+    };
+
+    let rng = &mut rand::thread_rng();
+    let pool = Worker::new();
+
+    for log_size in log_sizes {
+        let samples = 1 << log_size;
+
+        let g = Arc::new(
+            (0..samples)
+                .map(|_| <Bls12 as Engine>::G1::random(rng).into_affine())
+                .collect::<Vec<_>>(),
+        );
+        let v = Arc::new(
+            (0..samples)
+                .map(|_| <Bls12 as ScalarEngine>::Fr::random(rng).into_repr())
+                .collect::<Vec<_>>(),
+        );
+
+        let (projective, projective_time) = time(|| {
+            multiexp(&pool, (g.clone(), 0), FullDensity, v.clone(), &mut None)
+                .wait()
+                .unwrap()
+        });
+
+        let precomputed_bases = PrecomputedBases::new(&pool, &g, PRECOMPUTED_MULTIPLES);
+        let (precomputed, precomputed_time) = time(|| {
+            multiexp(
+                &pool,
+                (precomputed_bases.clone(), 0),
+                FullDensity,
+                v.clone(),
+                &mut None,
+            )
+            .wait()
+            .unwrap()
+        });
+
+        assert_eq!(projective, precomputed);
+
+        println!(
+            "2^{} bases: {:?}, {} multiples {:?} (x{:.2})",
+            log_size,
+            projective_time,
+            PRECOMPUTED_MULTIPLES,
+            precomputed_time,
+            projective_time.as_secs_f64() / precomputed_time.as_secs_f64()
+        );
+    }
+}
@@ -218,6 +218,246 @@ impl<E: Engine, G: Group<E>> EvaluationDomain<E, G> {
     }
 }
 
+/// Polynomial arithmetic on domains of scalars, which hold the coefficients
+/// of a polynomial, lowest degree first, unless stated otherwise.
+impl<E: Engine> EvaluationDomain<E, Scalar<E>> {
+    /// Returns the degree of the polynomial, taking the zero polynomial to
+    /// have degree zero.
+    pub fn degree(&self) -> usize {
+        self.num_coeffs().saturating_sub(1)
+    }
+
+    /// The number of coefficients up to the last nonzero one.
+    fn num_coeffs(&self) -> usize {
+        self.coeffs
+            .iter()
+            .rposition(|c| !c.0.is_zero())
+            .map_or(0, |i| i + 1)
+    }
+
+    /// Evaluates the polynomial at `point`.
+    pub fn evaluate(&self, worker: &Worker, point: &E::Fr) -> E::Fr {
+        let coeffs = &self.coeffs[..self.num_coeffs()];
+        let chunk = chunk_size(worker, coeffs.len());
+        let mut partials = vec![E::Fr::zero(); worker.num_threads()];
+
+        worker.scope(0, |scope, _| {
+            for (i, (coeffs, partial)) in coeffs.chunks(chunk).zip(partials.iter_mut()).enumerate()
+            {
+                scope.spawn(move |_| {
+                    *partial = coeffs.iter().rev().fold(E::Fr::zero(), |mut acc, c| {
+                        acc.mul_assign(point);
+                        acc.add_assign(&c.0);
+                        acc
+                    });
+                    partial.mul_assign(&point.pow(&[(i * chunk) as u64]));
+                });
+            }
+        });
+
+        partials.iter().fold(E::Fr::zero(), |mut acc, partial| {
+            acc.add_assign(partial);
+            acc
+        })
+    }
+
+    /// Computes the polynomial of least degree going through the given
+    /// points with Lagrange interpolation. Fails if two points share an
+    /// abscissa.
+    pub fn interpolate(
+        worker: &Worker,
+        points: &[(E::Fr, E::Fr)],
+    ) -> Result<EvaluationDomain<E, Scalar<E>>, SynthesisError> {
+        // The polynomial vanishing on every abscissa, whose quotients by
+        // each linear factor are the Lagrange basis up to scaling
+        let z = vanishing_polynomial::<E, _>(points.iter().map(|(x, _)| x));
+
+        let chunk = chunk_size(worker, points.len());
+        let mut partials = (0..worker.num_threads())
+            .map(|_| Ok(vec![E::Fr::zero(); points.len()]))
+            .collect::<Vec<Result<_, SynthesisError>>>();
+
+        worker.scope(0, |scope, _| {
+            let z = &z;
+
+            for (points, partial) in points.chunks(chunk).zip(partials.iter_mut()) {
+                scope.spawn(move |_| {
+                    let mut quotient = vec![E::Fr::zero(); z.len()];
+
+                    for &(x, y) in points {
+                        quotient.copy_from_slice(z);
+                        divide_by_linear::<E>(&mut quotient, &x);
+
+                        // Scale the basis polynomial to y at x and zero at
+                        // every other abscissa
+                        let mut scale = match horner::<E>(&quotient, &x).inverse() {
+                            Some(inverse) => inverse,
+                            None => {
+                                *partial = Err(SynthesisError::DivisionByZero);
+                                return;
+                            }
+                        };
+                        scale.mul_assign(&y);
+
+                        if let Ok(partial) = partial {
+                            for (acc, q) in partial.iter_mut().zip(quotient.iter()) {
+                                let mut term = *q;
+                                term.mul_assign(&scale);
+                                acc.add_assign(&term);
+                            }
+                        }
+                    }
+                });
+            }
+        });
+
+        let mut coeffs = vec![E::Fr::zero(); points.len()];
+        for partial in partials {
+            for (acc, c) in coeffs.iter_mut().zip(partial?) {
+                acc.add_assign(&c);
+            }
+        }
+
+        EvaluationDomain::from_coeffs(coeffs.into_iter().map(Scalar).collect())
+    }
+
+    /// Multiplies two polynomials of any degrees, through a domain large
+    /// enough for their product.
+    pub fn mul_polynomial(
+        &self,
+        worker: &Worker,
+        other: &EvaluationDomain<E, Scalar<E>>,
+        kern: &mut Option<gpu::FFTKernel<E>>,
+    ) -> Result<EvaluationDomain<E, Scalar<E>>, SynthesisError> {
+        let (self_len, other_len) = (self.num_coeffs(), other.num_coeffs());
+        if self_len == 0 || other_len == 0 {
+            return EvaluationDomain::from_coeffs(vec![]);
+        }
+
+        let len = self_len + other_len - 1;
+        let mut a = self.coeffs[..self_len].to_vec();
+        let mut b = other.coeffs[..other_len].to_vec();
+        a.resize(len, Scalar(E::Fr::zero()));
+        b.resize(len, Scalar(E::Fr::zero()));
+
+        let mut a = EvaluationDomain::from_coeffs(a)?;
+        let mut b = EvaluationDomain::from_coeffs(b)?;
+
+        a.fft(worker, kern)?;
+        b.fft(worker, kern)?;
+        a.mul_assign(worker, &b);
+        a.ifft(worker, kern)?;
+
+        Ok(a)
+    }
+
+    /// Divides the polynomial by `X - root` in place, returning the
+    /// remainder, which is the evaluation at `root`.
+    pub fn divide_by_linear(&mut self, root: &E::Fr) -> E::Fr {
+        // Safety: `Scalar` is a transparent wrapper of the field element
+        let coeffs = unsafe { &mut *(&mut self.coeffs[..] as *mut [Scalar<E>] as *mut [E::Fr]) };
+
+        divide_by_linear::<E>(coeffs, root)
+    }
+
+    /// Evaluates the polynomial vanishing on the subgroup of order `2^exp`
+    /// at every point of the coset `shift` of this domain, in order.
+    pub fn z_on_coset(&self, worker: &Worker, exp: u32, shift: &E::Fr) -> Vec<E::Fr> {
+        // (shift * omega^i)^(2^exp) only depends on i modulo the period
+        let period = 1 << self.exp.saturating_sub(exp);
+
+        let mut shift_power = *shift;
+        let mut omega_power = self.omega;
+        for _ in 0..exp {
+            shift_power.square();
+            omega_power.square();
+        }
+
+        let mut values = Vec::with_capacity(period);
+        let mut point = shift_power;
+        for _ in 0..period {
+            let mut value = point;
+            value.sub_assign(&E::Fr::one());
+            values.push(value);
+
+            point.mul_assign(&omega_power);
+        }
+
+        let mut z = vec![E::Fr::zero(); self.coeffs.len()];
+
+        worker.scope(z.len(), |scope, chunk| {
+            let values = &values;
+
+            for (i, z) in z.chunks_mut(chunk).enumerate() {
+                scope.spawn(move |_| {
+                    for (j, z) in z.iter_mut().enumerate() {
+                        *z = values[(i * chunk + j) % period];
+                    }
+                });
+            }
+        });
+
+        z
+    }
+}
+
+/// The size of the chunks splitting `len` elements among the threads of
+/// `worker`, for computations that keep a partial result per chunk.
+fn chunk_size(worker: &Worker, len: usize) -> usize {
+    let threads = worker.num_threads();
+
+    std::cmp::max((len + threads - 1) / threads, 1)
+}
+
+/// Evaluates the polynomial with the given coefficients at `point`.
+fn horner<E: ScalarEngine>(coeffs: &[E::Fr], point: &E::Fr) -> E::Fr {
+    coeffs.iter().rev().fold(E::Fr::zero(), |mut acc, c| {
+        acc.mul_assign(point);
+        acc.add_assign(c);
+        acc
+    })
+}
+
+/// Divides the polynomial with the given coefficients by `X - root` with
+/// synthetic division, leaving the quotient in place of the coefficients
+/// and returning the remainder.
+fn divide_by_linear<E: ScalarEngine>(coeffs: &mut [E::Fr], root: &E::Fr) -> E::Fr {
+    let mut acc = E::Fr::zero();
+
+    for c in coeffs.iter_mut().rev() {
+        let coeff = *c;
+        *c = acc;
+
+        acc.mul_assign(root);
+        acc.add_assign(&coeff);
+    }
+
+    acc
+}
+
+/// Returns the coefficients of the monic polynomial vanishing at exactly the
+/// given roots, the product of `X - root` over them.
+pub(crate) fn vanishing_polynomial<'a, E, I>(roots: I) -> Vec<E::Fr>
+where
+    E: ScalarEngine,
+    I: IntoIterator<Item = &'a E::Fr>,
+{
+    let mut z = vec![E::Fr::one()];
+
+    for root in roots {
+        // z *= X - root
+        z.push(E::Fr::zero());
+        for j in (0..z.len()).rev() {
+            let mut term = z[j];
+            term.mul_assign(root);
+            z[j] = if j > 0 { z[j - 1] } else { E::Fr::zero() };
+            z[j].sub_assign(&term);
+        }
+    }
+
+    z
+}
+
 pub trait Group<E: ScalarEngine>: Sized + Copy + Clone + Send + Sync {
     fn group_zero() -> Self;
     fn group_mul_assign(&mut self, by: &E::Fr);
@@ -256,6 +496,7 @@ impl<G: CurveProjective> Group<G::Engine> for Point<G> {
     }
 }
 
+#[repr(transparent)]
 pub struct Scalar<E: ScalarEngine>(pub E::Fr);
 
 impl<E: ScalarEngine> PartialEq for Scalar<E> {
@@ -561,11 +802,120 @@ fn parallel_fft_consistency() {
     test_consistency::<Bls12, _>(rng);
 }
 
+#[cfg(feature = "groth16")]
+#[test]
+fn polynomial_toolkit() {
+    use paired::bls12_381::{Bls12, Fr};
+
+    let rng = &mut rand::thread_rng();
+    let worker = Worker::new();
+
+    let random_poly = |rng: &mut rand::rngs::ThreadRng, len: usize| {
+        let coeffs = (0..len)
+            .map(|_| Scalar::<Bls12>(Fr::random(rng)))
+            .collect::<Vec<_>>();
+        EvaluationDomain::from_coeffs(coeffs).unwrap()
+    };
+    let naive_eval = |p: &EvaluationDomain<Bls12, Scalar<Bls12>>, x: &Fr| {
+        let mut acc = Fr::zero();
+        let mut power = Fr::one();
+        for c in p.as_ref() {
+            let mut term = c.0;
+            term.mul_assign(&power);
+            acc.add_assign(&term);
+            power.mul_assign(x);
+        }
+        acc
+    };
+
+    for &(len_a, len_b) in &[(0, 5), (1, 1), (3, 17), (40, 9), (64, 65)] {
+        let a = random_poly(rng, len_a);
+        let b = random_poly(rng, len_b);
+        let x = Fr::random(rng);
+
+        assert_eq!(a.evaluate(&worker, &x), naive_eval(&a, &x));
+        assert_eq!(a.degree(), len_a.saturating_sub(1));
+
+        // Different degrees multiply into the full product
+        let product = a.mul_polynomial(&worker, &b, &mut None).unwrap();
+        let mut expected = a.evaluate(&worker, &x);
+        expected.mul_assign(&b.evaluate(&worker, &x));
+        assert_eq!(product.evaluate(&worker, &x), expected);
+        if len_a > 0 && len_b > 0 {
+            assert_eq!(product.degree(), len_a + len_b - 2);
+        }
+
+        // a = q * (X - root) + a(root)
+        let root = Fr::random(rng);
+        let mut quotient = EvaluationDomain::from_coeffs(a.as_ref().to_vec()).unwrap();
+        let remainder = quotient.divide_by_linear(&root);
+        assert_eq!(remainder, a.evaluate(&worker, &root));
+        let mut expected = x;
+        expected.sub_assign(&root);
+        expected.mul_assign(&quotient.evaluate(&worker, &x));
+        expected.add_assign(&remainder);
+        assert_eq!(expected, a.evaluate(&worker, &x));
+
+        // Interpolating len_a points recovers a
+        let points = (0..len_a)
+            .map(|_| {
+                let x = Fr::random(rng);
+                (x, a.evaluate(&worker, &x))
+            })
+            .collect::<Vec<_>>();
+        let interpolated = EvaluationDomain::interpolate(&worker, &points).unwrap();
+        assert_eq!(interpolated.num_coeffs(), a.num_coeffs());
+        assert!(interpolated
+            .as_ref()
+            .iter()
+            .zip(a.as_ref())
+            .all(|(i, a)| i == a));
+    }
+
+    let x = Fr::random(rng);
+    let duplicate = [
+        (x, Fr::one()),
+        (Fr::random(rng), Fr::one()),
+        (x, Fr::zero()),
+    ];
+    match EvaluationDomain::<Bls12, _>::interpolate(&worker, &duplicate) {
+        Err(SynthesisError::DivisionByZero) => {}
+        _ => panic!("interpolated points sharing an abscissa"),
+    }
+
+    // Vanishing polynomials of subgroups smaller and larger than the domain
+    let domain = random_poly(rng, 16);
+    let shift = Fr::multiplicative_generator();
+    for exp in 0..6 {
+        let z = domain.z_on_coset(&worker, exp, &shift);
+        assert_eq!(z.len(), 16);
+
+        let mut point = shift;
+        for z in z {
+            let mut expected = point.pow(&[1 << exp]);
+            expected.sub_assign(&Fr::one());
+            assert_eq!(z, expected);
+
+            point.mul_assign(&domain.omega);
+        }
+    }
+}
+
 pub fn create_fft_kernel<E>(log_d: u32) -> Option<gpu::FFTKernel<E>>
 where
     E: Engine,
 {
-    match gpu::FFTKernel::create(1 << log_d) {
+    create_fft_kernel_with_backend(&gpu::DefaultBackend, log_d)
+}
+
+/// Creates a kernel for FFTs over `1 << log_d` elements on the devices of
+/// the compute backend `backend`.
+pub fn create_fft_kernel_with_backend<E, B>(backend: &B, log_d: u32) -> Option<gpu::FFTKernel<E>>
+where
+    E: Engine,
+    B: gpu::ComputeBackend<E>,
+{
+    match backend.create_fft_kernel(log_d) {
         Ok(k) => {
             info!("GPU FFT kernel instantiated!");
             Some(k)
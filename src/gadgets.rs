@@ -4,9 +4,13 @@ pub mod test;
 
 pub mod blake2s;
 pub mod boolean;
+pub mod hmac;
+pub mod keccak;
 pub mod lookup;
 pub mod multieq;
 pub mod multipack;
+pub mod mux;
+pub mod nonnative;
 pub mod num;
 pub mod sha256;
 pub mod uint32;
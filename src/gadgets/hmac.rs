@@ -0,0 +1,318 @@
+//! Circuits for [HMAC-SHA256] and the [HKDF] key derivation function built
+//! on top of it.
+//!
+//! Keys, messages and outputs are bit vectors in the same big-endian bit
+//! order as the [`sha256`](super::sha256) gadgets.
+//!
+//! [HMAC-SHA256]: https://tools.ietf.org/html/rfc2104
+//! [HKDF]: https://tools.ietf.org/html/rfc5869
+
+use super::boolean::Boolean;
+use super::sha256::sha256;
+use crate::{ConstraintSystem, SynthesisError};
+use ff::ScalarEngine;
+
+/// Block size of SHA-256, in bits.
+const BLOCK_BITS: usize = 512;
+
+/// Output size of SHA-256, in bits.
+const HASH_BITS: usize = 256;
+
+const IPAD: u8 = 0x36;
+const OPAD: u8 = 0x5c;
+
+/// Computes HMAC-SHA256 of `msg` under `key`.
+///
+/// Keys longer than the SHA-256 block size are hashed first, as required
+/// by RFC 2104.
+pub fn hmac_sha256<E, CS>(
+    mut cs: CS,
+    key: &[Boolean],
+    msg: &[Boolean],
+) -> Result<Vec<Boolean>, SynthesisError>
+where
+    E: ScalarEngine,
+    CS: ConstraintSystem<E>,
+{
+    assert!(key.len() % 8 == 0);
+    assert!(msg.len() % 8 == 0);
+
+    let mut key = if key.len() > BLOCK_BITS {
+        sha256(cs.namespace(|| "key hash"), key)?
+    } else {
+        key.to_vec()
+    };
+    key.resize(BLOCK_BITS, Boolean::constant(false));
+
+    let mut inner = xor_pad(&key, IPAD);
+    inner.extend_from_slice(msg);
+    let inner_hash = sha256(cs.namespace(|| "inner hash"), &inner)?;
+
+    let mut outer = xor_pad(&key, OPAD);
+    outer.extend(inner_hash);
+
+    sha256(cs.namespace(|| "outer hash"), &outer)
+}
+
+/// The HKDF extract step, producing a pseudorandom key from the input
+/// keying material. An empty `salt` is replaced by a block of zeros.
+pub fn hkdf_sha256_extract<E, CS>(
+    cs: CS,
+    salt: &[Boolean],
+    ikm: &[Boolean],
+) -> Result<Vec<Boolean>, SynthesisError>
+where
+    E: ScalarEngine,
+    CS: ConstraintSystem<E>,
+{
+    if salt.is_empty() {
+        let salt: Vec<_> = (0..HASH_BITS).map(|_| Boolean::constant(false)).collect();
+        hmac_sha256(cs, &salt, ikm)
+    } else {
+        hmac_sha256(cs, salt, ikm)
+    }
+}
+
+/// The HKDF expand step, producing `len` bytes of output keying material
+/// from a pseudorandom key.
+pub fn hkdf_sha256_expand<E, CS>(
+    mut cs: CS,
+    prk: &[Boolean],
+    info: &[Boolean],
+    len: usize,
+) -> Result<Vec<Boolean>, SynthesisError>
+where
+    E: ScalarEngine,
+    CS: ConstraintSystem<E>,
+{
+    assert!(info.len() % 8 == 0);
+    assert!(len <= 255 * HASH_BITS / 8);
+
+    let mut okm = Vec::with_capacity(len * 8);
+    let mut t: Vec<Boolean> = vec![];
+
+    for i in 1..=((len * 8 + HASH_BITS - 1) / HASH_BITS) {
+        // T(i) = HMAC(PRK, T(i - 1) | info | i)
+        let mut msg = t;
+        msg.extend_from_slice(info);
+        msg.extend((0..8).rev().map(|b| Boolean::constant((i >> b) & 1 == 1)));
+
+        t = hmac_sha256(cs.namespace(|| format!("block {}", i)), prk, &msg)?;
+        okm.extend_from_slice(&t);
+    }
+
+    okm.truncate(len * 8);
+
+    Ok(okm)
+}
+
+/// Derives `len` bytes of keying material from `ikm` with HKDF-SHA256.
+pub fn hkdf_sha256<E, CS>(
+    mut cs: CS,
+    salt: &[Boolean],
+    ikm: &[Boolean],
+    info: &[Boolean],
+    len: usize,
+) -> Result<Vec<Boolean>, SynthesisError>
+where
+    E: ScalarEngine,
+    CS: ConstraintSystem<E>,
+{
+    let prk = hkdf_sha256_extract(cs.namespace(|| "extract"), salt, ikm)?;
+
+    hkdf_sha256_expand(cs.namespace(|| "expand"), &prk, info, len)
+}
+
+/// XORs every byte of `key` with the constant `pad`; this costs no
+/// constraints.
+fn xor_pad(key: &[Boolean], pad: u8) -> Vec<Boolean> {
+    key.iter()
+        .enumerate()
+        .map(|(i, b)| {
+            if (pad >> (7 - i % 8)) & 1 == 1 {
+                b.not()
+            } else {
+                b.clone()
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::gadgets::boolean::AllocatedBit;
+    use crate::gadgets::test::TestConstraintSystem;
+    use paired::bls12_381::Bls12;
+
+    fn constant_bits(bytes: &[u8]) -> Vec<Boolean> {
+        bytes
+            .iter()
+            .flat_map(|&byte| {
+                (0..8)
+                    .rev()
+                    .map(move |i| Boolean::constant((byte >> i) & 1u8 == 1u8))
+            })
+            .collect()
+    }
+
+    fn alloc_bits<CS: ConstraintSystem<Bls12>>(mut cs: CS, bytes: &[u8]) -> Vec<Boolean> {
+        bytes
+            .iter()
+            .enumerate()
+            .flat_map(|(byte_i, &byte)| (0..8).rev().map(move |i| (byte_i, i, byte)))
+            .map(|(byte_i, i, byte)| {
+                AllocatedBit::alloc(
+                    cs.namespace(|| format!("bit {} {}", byte_i, i)),
+                    Some((byte >> i) & 1u8 == 1u8),
+                )
+                .unwrap()
+                .into()
+            })
+            .collect()
+    }
+
+    fn assert_bits_match(bits: &[Boolean], expected: &[u8]) {
+        assert_eq!(bits.len(), expected.len() * 8);
+
+        let expected = expected
+            .iter()
+            .flat_map(|&byte| (0..8).rev().map(move |i| (byte >> i) & 1u8 == 1u8));
+
+        for (b, e) in bits.iter().zip(expected) {
+            assert_eq!(b.get_value().unwrap(), e);
+        }
+    }
+
+    #[test]
+    fn test_hmac_rfc4231_vectors() {
+        let large_key = [0xaa; 131];
+
+        let vectors: Vec<(&[u8], &[u8], [u8; 32])> = vec![
+            (
+                &[0x0b; 20],
+                b"Hi There",
+                hex!("b0344c61d8db38535ca8afceaf0bf12b881dc200c9833da726e9376c2e32cff7"),
+            ),
+            (
+                b"Jefe",
+                b"what do ya want for nothing?",
+                hex!("5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"),
+            ),
+            (
+                &[0xaa; 20],
+                &[0xdd; 50],
+                hex!("773ea91e36800e46854db8ebd09181a72959098b3ef8c122d9635514ced565fe"),
+            ),
+            (
+                &hex!("0102030405060708090a0b0c0d0e0f10111213141516171819"),
+                &[0xcd; 50],
+                hex!("82558a389a443c0ea4cc819899f2083a85f0faa3e578f8077a2e3ff46729665b"),
+            ),
+            (
+                &large_key,
+                b"Test Using Larger Than Block-Size Key - Hash Key First",
+                hex!("60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54"),
+            ),
+            (
+                &large_key,
+                b"This is a test using a larger than block-size key and a larger than \
+                  block-size data. The key needs to be hashed before being used by the \
+                  HMAC algorithm.",
+                hex!("9b09ffa71b942fcb27635fbcd5b0e944bfdc63644f0713938a7f51535c3a35e2"),
+            ),
+        ];
+
+        for (key, msg, expected) in vectors {
+            let mut cs = TestConstraintSystem::<Bls12>::new();
+            let out = hmac_sha256(&mut cs, &constant_bits(key), &constant_bits(msg)).unwrap();
+
+            assert!(cs.is_satisfied());
+            assert_eq!(cs.num_constraints(), 0);
+            assert_bits_match(&out, &expected);
+        }
+    }
+
+    #[test]
+    fn test_hmac_witness_key() {
+        let mut cs = TestConstraintSystem::<Bls12>::new();
+        let key = alloc_bits(cs.namespace(|| "key"), b"Jefe");
+        let msg = constant_bits(b"what do ya want for nothing?");
+        let before = cs.num_constraints();
+
+        let out = hmac_sha256(cs.namespace(|| "hmac"), &key, &msg).unwrap();
+
+        assert!(cs.is_satisfied());
+        assert_eq!(cs.num_constraints() - before, 93370);
+        assert_bits_match(
+            &out,
+            &hex!("5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"),
+        );
+    }
+
+    #[test]
+    fn test_hkdf_rfc5869_vectors() {
+        let long_salt: Vec<u8> = (0x60..0xb0).collect();
+        let long_ikm: Vec<u8> = (0x00..0x50).collect();
+        let long_info: Vec<u8> = (0xb0..=0xff).collect();
+
+        let vectors = vec![
+            (
+                (0x00..=0x0c).collect::<Vec<u8>>(),
+                vec![0x0b; 22],
+                (0xf0..=0xf9).collect::<Vec<u8>>(),
+                hex!("077709362c2e32df0ddc3f0dc47bba6390b6c73bb50f9c3122ec844ad7c2b3e5").to_vec(),
+                hex!("3cb25f25faacd57a90434f64d0362f2a2d2d0a90cf1a5a4c5db02d56ecc4c5bf34007208d5b887185865")
+                .to_vec(),
+            ),
+            (
+                long_salt,
+                long_ikm,
+                long_info,
+                hex!("06a6b88c5853361a06104c9ceb35b45cef760014904671014a193f40c15fc244").to_vec(),
+                hex!("b11e398dc80327a1c8e7f78c596a49344f012eda2d4efad8a050cc4c19afa97c59045a99cac7827271cb41c65e590e09da3275600c2f09b8367793a9aca3db71cc30c58179ec3e87c14c01d5c1f3434f1d87")
+                .to_vec(),
+            ),
+            (
+                vec![],
+                vec![0x0b; 22],
+                vec![],
+                hex!("19ef24a32c717b167f33a91d6f648bdf96596776afdb6377ac434c1c293ccb04").to_vec(),
+                hex!("8da4e775a563c18f715f802a063c5a31b8a11f5c5ee1879ec3454e5f3c738d2d9d201395faa4b61a96c8")
+                .to_vec(),
+            ),
+        ];
+
+        for (salt, ikm, info, prk, okm) in vectors {
+            let mut cs = TestConstraintSystem::<Bls12>::new();
+            let salt = constant_bits(&salt);
+            let ikm = constant_bits(&ikm);
+            let info = constant_bits(&info);
+
+            let out_prk = hkdf_sha256_extract(cs.namespace(|| "extract"), &salt, &ikm).unwrap();
+            assert_bits_match(&out_prk, &prk);
+
+            let out = hkdf_sha256(cs.namespace(|| "hkdf"), &salt, &ikm, &info, okm.len()).unwrap();
+
+            assert!(cs.is_satisfied());
+            assert_eq!(cs.num_constraints(), 0);
+            assert_bits_match(&out, &okm);
+        }
+    }
+
+    #[test]
+    fn test_hkdf_witness_ikm() {
+        let mut cs = TestConstraintSystem::<Bls12>::new();
+        let salt = constant_bits(&(0x00..=0x0c).collect::<Vec<u8>>());
+        let ikm = alloc_bits(cs.namespace(|| "ikm"), &[0x0b; 22]);
+        let info = constant_bits(&(0xf0..=0xf9).collect::<Vec<u8>>());
+
+        let out = hkdf_sha256(cs.namespace(|| "hkdf"), &salt, &ikm, &info, 42).unwrap();
+
+        assert!(cs.is_satisfied());
+        assert_bits_match(
+            &out,
+            &hex!("3cb25f25faacd57a90434f64d0362f2a2d2d0a90cf1a5a4c5db02d56ecc4c5bf34007208d5b887185865"),
+        );
+    }
+}
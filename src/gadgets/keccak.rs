@@ -0,0 +1,398 @@
+//! Circuits for the [Keccak-f\[1600\]] permutation and the Keccak-256 and
+//! [SHA3-256] hash functions built on top of it.
+//!
+//! Keccak-256 is the variant used by Ethereum and differs from SHA3-256 only
+//! in the domain separation bits appended during padding.
+//!
+//! All inputs and outputs are bit vectors in little-endian bit order within
+//! each byte, which is the native bit order of Keccak.
+//!
+//! [Keccak-f\[1600\]]: https://keccak.team/keccak_specs_summary.html
+//! [SHA3-256]: https://nvlpubs.nist.gov/nistpubs/FIPS/NIST.FIPS.202.pdf
+
+use super::boolean::Boolean;
+use crate::{ConstraintSystem, SynthesisError};
+use ff::ScalarEngine;
+
+#[allow(clippy::unreadable_literal)]
+const ROUND_CONSTANTS: [u64; 24] = [
+    0x0000000000000001,
+    0x0000000000008082,
+    0x800000000000808a,
+    0x8000000080008000,
+    0x000000000000808b,
+    0x0000000080000001,
+    0x8000000080008081,
+    0x8000000000008009,
+    0x000000000000008a,
+    0x0000000000000088,
+    0x0000000080008009,
+    0x000000008000000a,
+    0x000000008000808b,
+    0x800000000000008b,
+    0x8000000000008089,
+    0x8000000000008003,
+    0x8000000000008002,
+    0x8000000000000080,
+    0x000000000000800a,
+    0x800000008000000a,
+    0x8000000080008081,
+    0x8000000000008080,
+    0x0000000080000001,
+    0x8000000080008008,
+];
+
+/// Rotation offsets for the rho step, indexed by `x + 5 * y`.
+const ROTATION_OFFSETS: [usize; 25] = [
+    0, 1, 62, 28, 27, 36, 44, 6, 55, 20, 3, 10, 43, 25, 39, 41, 45, 15, 21, 8, 18, 2, 61, 56, 14,
+];
+
+/// Width of a lane in bits.
+const LANE_BITS: usize = 64;
+
+/// Rate of the sponge for 256-bit outputs, in bits.
+const RATE_256: usize = 1088;
+
+/// Domain separation suffix used by the original Keccak submission.
+const KECCAK_SUFFIX: u8 = 0x01;
+
+/// Domain separation suffix used by FIPS 202 SHA-3.
+const SHA3_SUFFIX: u8 = 0x06;
+
+/// Applies the Keccak-f[1600] permutation to a 1600-bit state.
+///
+/// The state is laid out lane by lane, lane `x + 5 * y` occupying bits
+/// `64 * (x + 5 * y)..64 * (x + 5 * y + 1)` with the least significant
+/// bit of the lane first.
+pub fn keccak_f1600<E, CS>(mut cs: CS, input: &[Boolean]) -> Result<Vec<Boolean>, SynthesisError>
+where
+    E: ScalarEngine,
+    CS: ConstraintSystem<E>,
+{
+    assert_eq!(input.len(), 25 * LANE_BITS);
+
+    let mut state: Vec<Vec<Boolean>> = input.chunks(LANE_BITS).map(|l| l.to_vec()).collect();
+
+    for (round, rc) in ROUND_CONSTANTS.iter().enumerate() {
+        state = keccak_round(cs.namespace(|| format!("round {}", round)), &state, *rc)?;
+    }
+
+    Ok(state.into_iter().flatten().collect())
+}
+
+/// Computes the Keccak-256 hash of `input`, as used by Ethereum.
+pub fn keccak256<E, CS>(cs: CS, input: &[Boolean]) -> Result<Vec<Boolean>, SynthesisError>
+where
+    E: ScalarEngine,
+    CS: ConstraintSystem<E>,
+{
+    keccak_sponge(cs, input, KECCAK_SUFFIX, RATE_256, 256)
+}
+
+/// Computes the FIPS 202 SHA3-256 hash of `input`.
+pub fn sha3_256<E, CS>(cs: CS, input: &[Boolean]) -> Result<Vec<Boolean>, SynthesisError>
+where
+    E: ScalarEngine,
+    CS: ConstraintSystem<E>,
+{
+    keccak_sponge(cs, input, SHA3_SUFFIX, RATE_256, 256)
+}
+
+fn keccak_sponge<E, CS>(
+    mut cs: CS,
+    input: &[Boolean],
+    suffix: u8,
+    rate: usize,
+    output_len: usize,
+) -> Result<Vec<Boolean>, SynthesisError>
+where
+    E: ScalarEngine,
+    CS: ConstraintSystem<E>,
+{
+    assert!(input.len() % 8 == 0);
+    assert!(output_len <= rate);
+
+    // pad10*1 at byte granularity: the domain suffix starts the padding and
+    // the most significant bit of the final byte of the block ends it
+    let rate_bytes = rate / 8;
+    let pad_len = rate_bytes - (input.len() / 8) % rate_bytes;
+    let mut pad = vec![0u8; pad_len];
+    pad[0] = suffix;
+    pad[pad_len - 1] |= 0x80;
+
+    let mut padded = input.to_vec();
+    padded.extend(
+        pad.into_iter()
+            .flat_map(|byte| (0..8).map(move |i| Boolean::constant((byte >> i) & 1u8 == 1u8))),
+    );
+    assert!(padded.len() % rate == 0);
+
+    let mut state: Vec<Boolean> = (0..25 * LANE_BITS)
+        .map(|_| Boolean::constant(false))
+        .collect();
+
+    for (i, block) in padded.chunks(rate).enumerate() {
+        let mut cs = cs.namespace(|| format!("block {}", i));
+
+        for (j, (s, b)) in state.iter_mut().zip(block.iter()).enumerate() {
+            *s = Boolean::xor(cs.namespace(|| format!("absorb bit {}", j)), s, b)?;
+        }
+
+        state = keccak_f1600(cs.namespace(|| "permutation"), &state)?;
+    }
+
+    state.truncate(output_len);
+
+    Ok(state)
+}
+
+fn keccak_round<E, CS>(
+    mut cs: CS,
+    a: &[Vec<Boolean>],
+    round_constant: u64,
+) -> Result<Vec<Vec<Boolean>>, SynthesisError>
+where
+    E: ScalarEngine,
+    CS: ConstraintSystem<E>,
+{
+    // theta
+    let mut c = Vec::with_capacity(5);
+    for x in 0..5 {
+        let mut cs = cs.namespace(|| format!("theta c {}", x));
+        let mut acc = a[x].clone();
+        for y in 1..5 {
+            acc = xor_lanes(cs.namespace(|| format!("y {}", y)), &acc, &a[x + 5 * y])?;
+        }
+        c.push(acc);
+    }
+
+    let mut d = Vec::with_capacity(5);
+    for x in 0..5 {
+        d.push(xor_lanes(
+            cs.namespace(|| format!("theta d {}", x)),
+            &c[(x + 4) % 5],
+            &rotl(&c[(x + 1) % 5], 1),
+        )?);
+    }
+
+    let mut a_theta = Vec::with_capacity(25);
+    for (i, lane) in a.iter().enumerate() {
+        a_theta.push(xor_lanes(
+            cs.namespace(|| format!("theta lane {}", i)),
+            lane,
+            &d[i % 5],
+        )?);
+    }
+
+    // rho and pi
+    let mut b = vec![vec![]; 25];
+    for x in 0..5 {
+        for y in 0..5 {
+            let i = x + 5 * y;
+            b[y + 5 * ((2 * x + 3 * y) % 5)] = rotl(&a_theta[i], ROTATION_OFFSETS[i]);
+        }
+    }
+
+    // chi
+    let mut out = Vec::with_capacity(25);
+    for y in 0..5 {
+        for x in 0..5 {
+            let mut cs = cs.namespace(|| format!("chi lane {}", x + 5 * y));
+            let lane = &b[x + 5 * y];
+            let next = &b[(x + 1) % 5 + 5 * y];
+            let next2 = &b[(x + 2) % 5 + 5 * y];
+
+            let mut res = Vec::with_capacity(LANE_BITS);
+            for z in 0..LANE_BITS {
+                let mut cs = cs.namespace(|| format!("bit {}", z));
+                let tmp = Boolean::and(cs.namespace(|| "and"), &next[z].not(), &next2[z])?;
+                res.push(Boolean::xor(cs.namespace(|| "xor"), &lane[z], &tmp)?);
+            }
+            out.push(res);
+        }
+    }
+
+    // iota
+    for (z, bit) in out[0].iter_mut().enumerate() {
+        if (round_constant >> z) & 1 == 1 {
+            *bit = bit.not();
+        }
+    }
+
+    Ok(out)
+}
+
+fn xor_lanes<E, CS>(
+    mut cs: CS,
+    a: &[Boolean],
+    b: &[Boolean],
+) -> Result<Vec<Boolean>, SynthesisError>
+where
+    E: ScalarEngine,
+    CS: ConstraintSystem<E>,
+{
+    a.iter()
+        .zip(b.iter())
+        .enumerate()
+        .map(|(i, (a, b))| Boolean::xor(cs.namespace(|| format!("xor {}", i)), a, b))
+        .collect()
+}
+
+fn rotl(lane: &[Boolean], by: usize) -> Vec<Boolean> {
+    let mut res = lane.to_vec();
+    res.rotate_right(by % LANE_BITS);
+    res
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::gadgets::boolean::AllocatedBit;
+    use crate::gadgets::test::TestConstraintSystem;
+    use paired::bls12_381::Bls12;
+    use rand_core::{RngCore, SeedableRng};
+    use rand_xorshift::XorShiftRng;
+
+    fn bytes_to_constant_bits(bytes: &[u8]) -> Vec<Boolean> {
+        bytes
+            .iter()
+            .flat_map(|&byte| (0..8).map(move |i| Boolean::constant((byte >> i) & 1u8 == 1u8)))
+            .collect()
+    }
+
+    fn assert_bits_match(bits: &[Boolean], expected: &[u8]) {
+        let expected = expected
+            .iter()
+            .flat_map(|&byte| (0..8).map(move |i| (byte >> i) & 1u8 == 1u8));
+
+        assert_eq!(bits.len(), expected.clone().count());
+        for (b, e) in bits.iter().zip(expected) {
+            assert_eq!(b.get_value().unwrap(), e);
+        }
+    }
+
+    #[test]
+    fn test_permutation_of_zero_state() {
+        let mut cs = TestConstraintSystem::<Bls12>::new();
+        let input: Vec<_> = (0..1600).map(|_| Boolean::constant(false)).collect();
+        let out = keccak_f1600(&mut cs, &input).unwrap();
+
+        assert!(cs.is_satisfied());
+        assert_eq!(cs.num_constraints(), 0);
+
+        // First lane of Keccak-f[1600] applied to the all-zero state.
+        assert_bits_match(&out[0..64], &0xf1258f7940e1dde7u64.to_le_bytes());
+    }
+
+    #[test]
+    fn test_blank_hash() {
+        let mut cs = TestConstraintSystem::<Bls12>::new();
+        let out = keccak256(&mut cs, &[]).unwrap();
+
+        assert!(cs.is_satisfied());
+        assert_eq!(cs.num_constraints(), 0);
+        assert_bits_match(
+            &out,
+            &hex!("c5d2460186f7233c927e7db2dcc703c0e500b653ca82273b7bfad8045d85a470"),
+        );
+
+        let mut cs = TestConstraintSystem::<Bls12>::new();
+        let out = sha3_256(&mut cs, &[]).unwrap();
+
+        assert!(cs.is_satisfied());
+        assert_eq!(cs.num_constraints(), 0);
+        assert_bits_match(
+            &out,
+            &hex!("a7ffc6f8bf1ed76651c14756a061d662f580ff4de43b49fa82d80a4b80f8434a"),
+        );
+    }
+
+    #[test]
+    fn test_constant_abc() {
+        let input = bytes_to_constant_bits(b"abc");
+
+        let mut cs = TestConstraintSystem::<Bls12>::new();
+        let out = keccak256(&mut cs, &input).unwrap();
+        assert_eq!(cs.num_constraints(), 0);
+        assert_bits_match(
+            &out,
+            &hex!("4e03657aea45a94fc7d47ba826c8d667c0d1e6e33a64a036ec44f58fa12d6c45"),
+        );
+
+        let mut cs = TestConstraintSystem::<Bls12>::new();
+        let out = sha3_256(&mut cs, &input).unwrap();
+        assert_eq!(cs.num_constraints(), 0);
+        assert_bits_match(
+            &out,
+            &hex!("3a985da74fe225b2045c172d6bd390bd855f086e3e9d525b46bfe24511431532"),
+        );
+    }
+
+    #[test]
+    fn test_full_block() {
+        let mut rng = XorShiftRng::from_seed([
+            0x59, 0x62, 0xbe, 0x3d, 0x76, 0x3d, 0x31, 0x8d, 0x17, 0xdb, 0x37, 0x32, 0x54, 0x06,
+            0xbc, 0xe5,
+        ]);
+
+        let mut cs = TestConstraintSystem::<Bls12>::new();
+        let input_bits: Vec<_> = (0..1600)
+            .map(|i| {
+                Boolean::from(
+                    AllocatedBit::alloc(
+                        cs.namespace(|| format!("input bit {}", i)),
+                        Some(rng.next_u32() % 2 != 0),
+                    )
+                    .unwrap(),
+                )
+            })
+            .collect();
+
+        keccak_f1600(cs.namespace(|| "keccak"), &input_bits).unwrap();
+
+        assert!(cs.is_satisfied());
+        assert_eq!(cs.num_constraints() - 1600, 153600);
+    }
+
+    #[test]
+    fn test_against_vectors() {
+        use sha3::{Digest, Keccak256, Sha3_256};
+
+        let mut rng = XorShiftRng::from_seed([
+            0x59, 0x62, 0xbe, 0x3d, 0x76, 0x3d, 0x31, 0x8d, 0x17, 0xdb, 0x37, 0x32, 0x54, 0x06,
+            0xbc, 0xe5,
+        ]);
+
+        for input_len in (0..8).chain(vec![135, 136, 137, 272, 300]) {
+            let data: Vec<u8> = (0..input_len).map(|_| rng.next_u32() as u8).collect();
+
+            let mut cs = TestConstraintSystem::<Bls12>::new();
+            let mut input_bits = vec![];
+
+            for (byte_i, input_byte) in data.iter().enumerate() {
+                for bit_i in 0..8 {
+                    let cs = cs.namespace(|| format!("input bit {} {}", byte_i, bit_i));
+
+                    input_bits.push(
+                        AllocatedBit::alloc(cs, Some((input_byte >> bit_i) & 1u8 == 1u8))
+                            .unwrap()
+                            .into(),
+                    );
+                }
+            }
+
+            let r = keccak256(cs.namespace(|| "keccak256"), &input_bits).unwrap();
+            let s = sha3_256(cs.namespace(|| "sha3_256"), &input_bits).unwrap();
+
+            assert!(cs.is_satisfied());
+
+            let mut h = Keccak256::new();
+            h.input(&data);
+            assert_bits_match(&r, h.result().as_ref());
+
+            let mut h = Sha3_256::new();
+            h.input(&data);
+            assert_bits_match(&s, h.result().as_ref());
+        }
+    }
+}
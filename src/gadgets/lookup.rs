@@ -5,7 +5,7 @@ use ff::{Field, ScalarEngine};
 use super::boolean::Boolean;
 use super::num::{AllocatedNum, Num};
 use super::*;
-use crate::ConstraintSystem;
+use crate::{ConstraintSystem, LinearCombination};
 
 // Synthesize the constants for each base pattern.
 fn synth<'a, E: ScalarEngine, I>(window_size: usize, constants: I, assignment: &mut [E::Fr])
@@ -186,6 +186,200 @@ where
     Ok((x, y.into()))
 }
 
+/// Performs a k-bit window table lookup into a table of `2^k` values.
+/// `bits` is in little-endian order.
+pub fn lookup_k<E: ScalarEngine, CS>(
+    cs: CS,
+    bits: &[Boolean],
+    table: &[E::Fr],
+) -> Result<Num<E>, SynthesisError>
+where
+    CS: ConstraintSystem<E>,
+{
+    let mut res = lookup_k_columns(cs, bits, &[table])?;
+
+    Ok(res.pop().unwrap())
+}
+
+/// Performs a k-bit window table lookup into a table of `2^k`
+/// coordinate pairs. `bits` is in little-endian order.
+pub fn lookup_k_xy<E: ScalarEngine, CS>(
+    cs: CS,
+    bits: &[Boolean],
+    coords: &[(E::Fr, E::Fr)],
+) -> Result<(Num<E>, Num<E>), SynthesisError>
+where
+    CS: ConstraintSystem<E>,
+{
+    let xs: Vec<_> = coords.iter().map(|c| c.0).collect();
+    let ys: Vec<_> = coords.iter().map(|c| c.1).collect();
+
+    let mut res = lookup_k_columns(cs, bits, &[&xs, &ys])?;
+    let y = res.pop().unwrap();
+    let x = res.pop().unwrap();
+
+    Ok((x, y))
+}
+
+/// Performs a (k+1)-bit window table lookup into a table of `2^k`
+/// coordinate pairs, where the last bit is a sign bit which
+/// conditionally negates the y-coordinate.
+pub fn lookup_k_xy_with_conditional_negation<E: ScalarEngine, CS>(
+    mut cs: CS,
+    bits: &[Boolean],
+    coords: &[(E::Fr, E::Fr)],
+) -> Result<(Num<E>, Num<E>), SynthesisError>
+where
+    CS: ConstraintSystem<E>,
+{
+    assert!(bits.len() >= 2);
+
+    let (bits, sign) = bits.split_at(bits.len() - 1);
+    let sign = &sign[0];
+
+    let (x, y) = lookup_k_xy(cs.namespace(|| "lookup"), bits, coords)?;
+
+    // Allocate the y-coordinate resulting from the conditional negation
+    let negated_y = AllocatedNum::alloc(cs.namespace(|| "y"), || {
+        let mut tmp = *y.get_value().get()?;
+        if *sign.get_value().get()? {
+            tmp.negate();
+        }
+        Ok(tmp)
+    })?;
+
+    let one = CS::one();
+
+    cs.enforce(
+        || "y-coordinate negation",
+        |lc| lc + &y.lc(E::Fr::one()) + &y.lc(E::Fr::one()),
+        |lc| lc + &sign.lc::<E>(one, E::Fr::one()),
+        |lc| lc + &y.lc(E::Fr::one()) - negated_y.get_variable(),
+    );
+
+    Ok((x, negated_y.into()))
+}
+
+/// Looks up the same index in several tables at once, choosing between
+/// two constructions:
+///
+/// * Every product of a subset of `bits` is computed, which costs
+///   `2^k - k - 1` constraints, and each result is a linear combination
+///   of those products.
+/// * The first bit is factored out, so only products of the remaining
+///   bits are needed, at `2^(k-1) - k` constraints, plus one constraint
+///   per table to multiply by the first bit.
+fn lookup_k_columns<E: ScalarEngine, CS>(
+    mut cs: CS,
+    bits: &[Boolean],
+    columns: &[&[E::Fr]],
+) -> Result<Vec<Num<E>>, SynthesisError>
+where
+    CS: ConstraintSystem<E>,
+{
+    let k = bits.len();
+    assert!(k > 0);
+    for column in columns {
+        assert_eq!(column.len(), 1 << k);
+    }
+
+    // Calculate the index into the tables
+    let i = bits.iter().enumerate().try_fold(0, |acc, (j, b)| {
+        b.get_value().map(|b| acc | ((b as usize) << j))
+    });
+
+    let one = CS::one();
+
+    // Compute the coefficients for the lookup constraints
+    let coeffs: Vec<Vec<E::Fr>> = columns
+        .iter()
+        .map(|column| {
+            let mut coeffs = vec![E::Fr::zero(); 1 << k];
+            synth::<E, _>(k, column.iter(), &mut coeffs);
+            coeffs
+        })
+        .collect();
+
+    let factor_first_bit = (1 << (k - 1)) - 1 > columns.len();
+
+    if !factor_first_bit {
+        let monomials = monomials(cs.namespace(|| "monomials"), bits)?;
+
+        return Ok(coeffs
+            .iter()
+            .map(|coeffs| {
+                monomials
+                    .iter()
+                    .zip(coeffs.iter())
+                    .fold(Num::zero(), |acc, (m, c)| {
+                        acc.add_bool_with_coeff(one, m, *c)
+                    })
+            })
+            .collect());
+    }
+
+    let monomials = monomials(cs.namespace(|| "monomials"), &bits[1..])?;
+
+    let mut res = Vec::with_capacity(columns.len());
+    for (c, (column, coeffs)) in columns.iter().zip(coeffs.iter()).enumerate() {
+        let value = AllocatedNum::alloc(cs.namespace(|| format!("result {}", c)), || {
+            Ok(column[*i.get()?])
+        })?;
+
+        // Split into the terms without and with the first bit
+        let (without, with) = monomials.iter().enumerate().fold(
+            (LinearCombination::zero(), LinearCombination::zero()),
+            |(without, with), (mask, m)| {
+                (
+                    without + &m.lc::<E>(one, coeffs[mask << 1]),
+                    with + &m.lc::<E>(one, coeffs[(mask << 1) | 1]),
+                )
+            },
+        );
+
+        cs.enforce(
+            || format!("lookup {}", c),
+            |lc| lc + &with,
+            |lc| lc + &bits[0].lc::<E>(one, E::Fr::one()),
+            |lc| lc + value.get_variable() - &without,
+        );
+
+        res.push(value.into());
+    }
+
+    Ok(res)
+}
+
+/// Computes the product of every subset of `bits`, indexed by the
+/// bitmask of the subset.
+fn monomials<E: ScalarEngine, CS>(
+    mut cs: CS,
+    bits: &[Boolean],
+) -> Result<Vec<Boolean>, SynthesisError>
+where
+    CS: ConstraintSystem<E>,
+{
+    let mut res = Vec::with_capacity(1 << bits.len());
+    res.push(Boolean::constant(true));
+
+    for (i, bit) in bits.iter().enumerate() {
+        for mask in 0..(1 << i) {
+            let m = if mask == 0 {
+                bit.clone()
+            } else {
+                Boolean::and(
+                    cs.namespace(|| format!("monomial {}", mask | (1 << i))),
+                    &res[mask],
+                    bit,
+                )?
+            };
+            res.push(m);
+        }
+    }
+
+    Ok(res)
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -286,6 +480,127 @@ mod test {
         }
     }
 
+    fn alloc_index_bits(
+        cs: &mut TestConstraintSystem<Bls12>,
+        k: usize,
+        index: usize,
+    ) -> Vec<Boolean> {
+        (0..k)
+            .map(|j| {
+                Boolean::from(
+                    AllocatedBit::alloc(
+                        cs.namespace(|| format!("bit {}", j)),
+                        Some((index >> j) & 1 == 1),
+                    )
+                    .unwrap(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_lookup_k() {
+        let mut rng = XorShiftRng::from_seed([
+            0x59, 0x62, 0xbe, 0x3d, 0x76, 0x3d, 0x31, 0x8d, 0x17, 0xdb, 0x37, 0x32, 0x54, 0x06,
+            0xbc, 0xe5,
+        ]);
+
+        // (k, constraints for a 1-D table, constraints for a 2-D table)
+        let costs = [(1, 0, 0), (2, 1, 1), (3, 2, 3), (4, 5, 6), (5, 12, 13)];
+
+        for &(k, cost_1d, cost_2d) in &costs {
+            let table: Vec<Fr> = (0..(1 << k)).map(|_| Fr::random(&mut rng)).collect();
+            let coords: Vec<(Fr, Fr)> = (0..(1 << k))
+                .map(|_| (Fr::random(&mut rng), Fr::random(&mut rng)))
+                .collect();
+
+            for index in 0..(1 << k) {
+                let mut cs = TestConstraintSystem::<Bls12>::new();
+                let bits = alloc_index_bits(&mut cs, k, index);
+                let before = cs.num_constraints();
+
+                let res = lookup_k(cs.namespace(|| "lookup"), &bits, &table).unwrap();
+
+                assert!(cs.is_satisfied());
+                assert_eq!(cs.num_constraints() - before, cost_1d);
+                assert_eq!(res.get_value().unwrap(), table[index]);
+
+                let mut cs = TestConstraintSystem::<Bls12>::new();
+                let bits = alloc_index_bits(&mut cs, k, index);
+                let before = cs.num_constraints();
+
+                let (x, y) = lookup_k_xy(cs.namespace(|| "lookup"), &bits, &coords).unwrap();
+
+                assert!(cs.is_satisfied());
+                assert_eq!(cs.num_constraints() - before, cost_2d);
+                assert_eq!(x.get_value().unwrap(), coords[index].0);
+                assert_eq!(y.get_value().unwrap(), coords[index].1);
+            }
+        }
+    }
+
+    #[test]
+    fn test_lookup_k_matches_lookup3_xy() {
+        let mut rng = XorShiftRng::from_seed([
+            0x59, 0x62, 0xbe, 0x3d, 0x76, 0x3d, 0x31, 0x8d, 0x17, 0xdb, 0x37, 0x32, 0x54, 0x06,
+            0xbc, 0xe5,
+        ]);
+
+        let coords: Vec<(Fr, Fr)> = (0..8)
+            .map(|_| (Fr::random(&mut rng), Fr::random(&mut rng)))
+            .collect();
+
+        for index in 0..8 {
+            let mut cs = TestConstraintSystem::<Bls12>::new();
+            let bits = alloc_index_bits(&mut cs, 3, index);
+
+            let (x, y) = lookup3_xy(cs.namespace(|| "lookup3"), &bits, &coords).unwrap();
+            let before = cs.num_constraints();
+            let (x_k, y_k) = lookup_k_xy(cs.namespace(|| "lookup_k"), &bits, &coords).unwrap();
+
+            assert!(cs.is_satisfied());
+            assert_eq!(cs.num_constraints() - before, 3);
+            assert_eq!(x.get_value(), x_k.get_value());
+            assert_eq!(y.get_value(), y_k.get_value());
+        }
+    }
+
+    #[test]
+    fn test_lookup_k_xy_with_conditional_negation() {
+        let mut rng = XorShiftRng::from_seed([
+            0x59, 0x62, 0xbe, 0x3d, 0x76, 0x3d, 0x31, 0x8d, 0x17, 0xdb, 0x37, 0x32, 0x54, 0x06,
+            0xbc, 0xe5,
+        ]);
+
+        for k in 1..5 {
+            let coords: Vec<(Fr, Fr)> = (0..(1 << k))
+                .map(|_| (Fr::random(&mut rng), Fr::random(&mut rng)))
+                .collect();
+
+            for index in 0..(1 << (k + 1)) {
+                let mut cs = TestConstraintSystem::<Bls12>::new();
+                let bits = alloc_index_bits(&mut cs, k + 1, index);
+
+                let (x, y) =
+                    lookup_k_xy_with_conditional_negation(&mut cs, &bits, &coords).unwrap();
+
+                assert!(cs.is_satisfied());
+
+                let point = coords[index & ((1 << k) - 1)];
+                let mut expected_y = point.1;
+                if index >> k == 1 {
+                    expected_y.negate();
+                }
+
+                assert_eq!(x.get_value().unwrap(), point.0);
+                assert_eq!(y.get_value().unwrap(), expected_y);
+
+                cs.set("y/num", point.0);
+                assert_eq!(cs.which_is_unsatisfied(), Some("y-coordinate negation"));
+            }
+        }
+    }
+
     #[test]
     fn test_synth() {
         let mut rng = XorShiftRng::from_seed([
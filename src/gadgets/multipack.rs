@@ -1,6 +1,6 @@
 //! Helpers for packing vectors of bits into scalar field elements.
 
-use super::boolean::Boolean;
+use super::boolean::{AllocatedBit, Boolean};
 use super::num::Num;
 use super::Assignment;
 use crate::{ConstraintSystem, SynthesisError};
@@ -36,6 +36,76 @@ where
     Ok(())
 }
 
+/// Allocates `num_bytes` bytes as bits, in the order of `bytes_to_bits`,
+/// and exposes them as compact public inputs. The inputs can be computed
+/// outside the circuit with `compute_multipacking_bytes`.
+pub fn pack_bytes_into_inputs<E, CS>(
+    mut cs: CS,
+    bytes: Option<&[u8]>,
+    num_bytes: usize,
+) -> Result<Vec<Boolean>, SynthesisError>
+where
+    E: ScalarEngine,
+    CS: ConstraintSystem<E>,
+{
+    let values = match bytes {
+        Some(bytes) => {
+            assert_eq!(bytes.len(), num_bytes);
+            bytes_to_bits(bytes).into_iter().map(Some).collect()
+        }
+        None => vec![None; num_bytes * 8],
+    };
+
+    let bits = alloc_bits(cs.namespace(|| "bits"), &values)?;
+    pack_into_inputs(cs.namespace(|| "pack"), &bits)?;
+
+    Ok(bits)
+}
+
+/// Allocates public inputs holding `num_bits` packed bits, as produced by
+/// `pack_into_inputs`, and returns those bits. Fails with
+/// `IncompatibleLengths` if `inputs` do not pack `num_bits` bits.
+pub fn unpack_inputs_into_bits<E, CS>(
+    mut cs: CS,
+    inputs: Option<&[E::Fr]>,
+    num_bits: usize,
+) -> Result<Vec<Boolean>, SynthesisError>
+where
+    E: ScalarEngine,
+    CS: ConstraintSystem<E>,
+{
+    let values = match inputs {
+        Some(inputs) => unpack_inputs::<E>(inputs, num_bits)
+            .ok_or(SynthesisError::IncompatibleLengths)?
+            .into_iter()
+            .map(Some)
+            .collect(),
+        None => vec![None; num_bits],
+    };
+
+    let bits = alloc_bits(cs.namespace(|| "bits"), &values)?;
+    pack_into_inputs(cs.namespace(|| "pack"), &bits)?;
+
+    Ok(bits)
+}
+
+fn alloc_bits<E, CS>(mut cs: CS, values: &[Option<bool>]) -> Result<Vec<Boolean>, SynthesisError>
+where
+    E: ScalarEngine,
+    CS: ConstraintSystem<E>,
+{
+    values
+        .iter()
+        .enumerate()
+        .map(|(i, &b)| {
+            Ok(Boolean::from(AllocatedBit::alloc(
+                cs.namespace(|| format!("bit {}", i)),
+                b,
+            )?))
+        })
+        .collect()
+}
+
 pub fn bytes_to_bits(bytes: &[u8]) -> Vec<bool> {
     bytes
         .iter()
@@ -71,6 +141,53 @@ pub fn compute_multipacking<E: ScalarEngine>(bits: &[bool]) -> Vec<E::Fr> {
     result
 }
 
+pub fn compute_multipacking_bytes<E: ScalarEngine>(bytes: &[u8]) -> Vec<E::Fr> {
+    compute_multipacking::<E>(&bytes_to_bits(bytes))
+}
+
+/// Recovers `num_bits` bits from public inputs computed by
+/// `compute_multipacking`, or returns `None` if there are not as many
+/// inputs as `num_bits` bits are packed into.
+///
+/// Any bits of an input above those it is expected to hold are ignored,
+/// so a verifier that needs to reject non-canonical inputs should check
+/// that repacking the result reproduces them.
+pub fn unpack_inputs<E: ScalarEngine>(inputs: &[E::Fr], num_bits: usize) -> Option<Vec<bool>> {
+    let capacity = E::Fr::CAPACITY as usize;
+    if inputs.len() != (num_bits + capacity - 1) / capacity {
+        return None;
+    }
+
+    let mut result = Vec::with_capacity(num_bits);
+
+    for input in inputs {
+        let repr = input.into_repr();
+        let limbs = repr.as_ref();
+
+        for i in 0..std::cmp::min(capacity, num_bits - result.len()) {
+            result.push((limbs[i / 64] >> (i % 64)) & 1 == 1);
+        }
+    }
+
+    Some(result)
+}
+
+/// Recovers `num_bytes` bytes from public inputs computed by
+/// `compute_multipacking_bytes`, or returns `None` if there are not as many
+/// inputs as `num_bytes` bytes are packed into.
+pub fn unpack_inputs_to_bytes<E: ScalarEngine>(
+    inputs: &[E::Fr],
+    num_bytes: usize,
+) -> Option<Vec<u8>> {
+    let bits = unpack_inputs::<E>(inputs, num_bytes * 8)?;
+
+    Some(
+        bits.chunks(8)
+            .map(|bits| bits.iter().fold(0, |acc, &b| (acc << 1) | b as u8))
+            .collect(),
+    )
+}
+
 #[test]
 fn test_multipacking() {
     use crate::ConstraintSystem;
@@ -109,3 +226,77 @@ fn test_multipacking() {
         assert!(cs.verify(&expected_inputs));
     }
 }
+
+#[test]
+fn test_unpack_inputs() {
+    use paired::bls12_381::Bls12;
+    use rand_core::{RngCore, SeedableRng};
+    use rand_xorshift::XorShiftRng;
+
+    let mut rng = XorShiftRng::from_seed([
+        0x59, 0x62, 0xbe, 0x3d, 0x76, 0x3d, 0x31, 0x8d, 0x17, 0xdb, 0x37, 0x32, 0x54, 0x06, 0xbc,
+        0xe5,
+    ]);
+
+    for num_bits in 0..1500 {
+        let bits: Vec<bool> = (0..num_bits).map(|_| rng.next_u32() % 2 != 0).collect();
+        let inputs = compute_multipacking::<Bls12>(&bits);
+
+        assert_eq!(unpack_inputs::<Bls12>(&inputs, num_bits), Some(bits));
+        assert_eq!(unpack_inputs::<Bls12>(&inputs, num_bits + 255), None);
+    }
+
+    for num_bytes in 0..100 {
+        let bytes: Vec<u8> = (0..num_bytes).map(|_| rng.next_u32() as u8).collect();
+        let inputs = compute_multipacking_bytes::<Bls12>(&bytes);
+
+        assert_eq!(
+            unpack_inputs_to_bytes::<Bls12>(&inputs, num_bytes),
+            Some(bytes)
+        );
+    }
+}
+
+#[test]
+fn test_multipacking_bytes() {
+    use paired::bls12_381::Bls12;
+    use rand_core::{RngCore, SeedableRng};
+    use rand_xorshift::XorShiftRng;
+
+    use crate::gadgets::test::*;
+
+    let mut rng = XorShiftRng::from_seed([
+        0x59, 0x62, 0xbe, 0x3d, 0x76, 0x3d, 0x31, 0x8d, 0x17, 0xdb, 0x37, 0x32, 0x54, 0x06, 0xbc,
+        0xe5,
+    ]);
+
+    for num_bytes in 0..100 {
+        let bytes: Vec<u8> = (0..num_bytes).map(|_| rng.next_u32() as u8).collect();
+        let expected_inputs = compute_multipacking_bytes::<Bls12>(&bytes);
+
+        let mut cs = TestConstraintSystem::<Bls12>::new();
+        let bits =
+            pack_bytes_into_inputs(cs.namespace(|| "pack"), Some(&bytes), num_bytes).unwrap();
+
+        assert!(cs.is_satisfied());
+        assert!(cs.verify(&expected_inputs));
+        for (bit, expected) in bits.iter().zip(bytes_to_bits(&bytes)) {
+            assert_eq!(bit.get_value().unwrap(), expected);
+        }
+
+        let mut cs = TestConstraintSystem::<Bls12>::new();
+        let bits = unpack_inputs_into_bits(
+            cs.namespace(|| "unpack"),
+            Some(&expected_inputs),
+            num_bytes * 8,
+        )
+        .unwrap();
+
+        assert!(cs.is_satisfied());
+        assert!(cs.verify(&expected_inputs));
+        assert_eq!(bits.len(), num_bytes * 8);
+        for (bit, expected) in bits.iter().zip(bytes_to_bits(&bytes)) {
+            assert_eq!(bit.get_value().unwrap(), expected);
+        }
+    }
+}
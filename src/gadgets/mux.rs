@@ -0,0 +1,586 @@
+//! Gadgets for indexing into arrays of allocated numbers with a witness
+//! index, and for checking the consistency of memory-like access traces.
+
+use ff::{Field, PrimeField, ScalarEngine};
+
+use super::boolean::{AllocatedBit, Boolean};
+use super::nonnative::range_check;
+use super::num::{AllocatedNum, Num};
+use super::Assignment;
+use crate::{ConstraintSystem, SynthesisError};
+
+/// Returns `items[index]`, where `index_bits` is the little-endian
+/// encoding of the index. `items` must have exactly `2^index_bits.len()`
+/// entries.
+///
+/// The selection is a binary tree of conditional selects, costing
+/// `items.len() - 1` constraints.
+pub fn select<E, CS>(
+    mut cs: CS,
+    items: &[AllocatedNum<E>],
+    index_bits: &[Boolean],
+) -> Result<AllocatedNum<E>, SynthesisError>
+where
+    E: ScalarEngine,
+    CS: ConstraintSystem<E>,
+{
+    assert_eq!(items.len(), 1 << index_bits.len());
+
+    let mut layer = items.to_vec();
+
+    for (i, bit) in index_bits.iter().enumerate() {
+        let mut cs = cs.namespace(|| format!("layer {}", i));
+
+        layer = layer
+            .chunks(2)
+            .enumerate()
+            .map(|(j, pair)| {
+                AllocatedNum::conditionally_select(
+                    cs.namespace(|| format!("pair {}", j)),
+                    bit,
+                    &pair[1],
+                    &pair[0],
+                )
+            })
+            .collect::<Result<_, _>>()?;
+    }
+
+    Ok(layer.pop().unwrap())
+}
+
+/// Returns the entry of `items` whose selector is set. Exactly one of
+/// `selectors` must be true.
+///
+/// This costs `items.len() + 1` constraints, which is cheaper than
+/// [`select`] when the one-hot selectors are already available.
+pub fn select_one_hot<E, CS>(
+    mut cs: CS,
+    items: &[AllocatedNum<E>],
+    selectors: &[Boolean],
+) -> Result<AllocatedNum<E>, SynthesisError>
+where
+    E: ScalarEngine,
+    CS: ConstraintSystem<E>,
+{
+    assert_eq!(items.len(), selectors.len());
+    assert!(!items.is_empty());
+
+    let one = CS::one();
+
+    let res = AllocatedNum::alloc(cs.namespace(|| "selected"), || {
+        let mut res = None;
+        for (item, selector) in items.iter().zip(selectors.iter()) {
+            if *selector.get_value().get()? {
+                res = item.get_value();
+            }
+        }
+        res.ok_or(SynthesisError::AssignmentMissing)
+    })?;
+
+    cs.enforce(
+        || "exactly one selector",
+        |lc| {
+            selectors
+                .iter()
+                .fold(lc, |lc, s| lc + &s.lc(one, E::Fr::one()))
+        },
+        |lc| lc + one,
+        |lc| lc + one,
+    );
+
+    for (i, (item, selector)) in items.iter().zip(selectors.iter()).enumerate() {
+        // Constrain: selector * (item - res) = 0
+        cs.enforce(
+            || format!("selection {}", i),
+            |lc| lc + &selector.lc(one, E::Fr::one()),
+            |lc| lc + item.get_variable() - res.get_variable(),
+            |lc| lc,
+        );
+    }
+
+    Ok(res)
+}
+
+/// A single read or write of a memory trace.
+pub struct MemoryAccess<E: ScalarEngine> {
+    /// The address accessed, which must fit in the `addr_bits` passed to
+    /// [`enforce_memory_consistency`].
+    pub addr: AllocatedNum<E>,
+    /// The value written, or the value claimed to be read.
+    pub value: AllocatedNum<E>,
+    pub is_write: Boolean,
+}
+
+/// Enforces that every read in `accesses` returns the value of the most
+/// recent write to the same address, or zero if there was none.
+///
+/// The accesses are witnessed a second time, sorted by address and then
+/// by time, and a grand product shows that the two traces are
+/// permutations of each other. Consistency is then checked between
+/// neighbours of the sorted trace, so the cost is logarithmic in the
+/// number of accesses and the address space per access, rather than
+/// linear in the size of the memory.
+///
+/// `alpha` and `beta` must be random challenges chosen after the trace
+/// is fixed, for example by hashing a commitment to it. A prover who can
+/// choose them can make an inconsistent trace pass.
+pub fn enforce_memory_consistency<E, CS>(
+    mut cs: CS,
+    accesses: &[MemoryAccess<E>],
+    addr_bits: usize,
+    alpha: &AllocatedNum<E>,
+    beta: &AllocatedNum<E>,
+) -> Result<(), SynthesisError>
+where
+    E: ScalarEngine,
+    CS: ConstraintSystem<E>,
+{
+    if accesses.is_empty() {
+        return Ok(());
+    }
+
+    let n = accesses.len();
+    let time_bits = (0usize.leading_zeros() - (n - 1).leading_zeros()) as usize;
+    let key_bits = addr_bits + time_bits + 1;
+    assert!(key_bits <= 64);
+    assert!(key_bits < E::Fr::CAPACITY as usize);
+
+    let one = CS::one();
+
+    // Every access is identified by a key packing its address, time and
+    // kind, in that order of significance, so that sorting by key sorts
+    // by address and then by time.
+    let mut addr_shift = E::Fr::one();
+    for _ in 0..(time_bits + 1) {
+        addr_shift.double();
+    }
+
+    let mut original = Vec::with_capacity(n);
+    for (i, access) in accesses.iter().enumerate() {
+        range_check(
+            cs.namespace(|| format!("access {} address range", i)),
+            &access.addr,
+            addr_bits,
+        )?;
+
+        let time = E::Fr::from_str(&(2 * i).to_string()).unwrap();
+        let key = Num::constant(time, one).add_bool_with_coeff(one, &access.is_write, E::Fr::one())
+            + &(Num::from(access.addr.clone()) * addr_shift);
+
+        original.push((key, access.value.clone()));
+    }
+
+    // Compute the sorted trace
+    let sorted_values = accesses
+        .iter()
+        .enumerate()
+        .map(|(time, access)| {
+            let addr = access.addr.get_value()?.into_repr().as_ref()[0];
+            let is_write = access.is_write.get_value()?;
+            let value = access.value.get_value()?;
+            Some((addr, time, is_write, value))
+        })
+        .collect::<Option<Vec<_>>>()
+        .map(|mut trace| {
+            trace.sort_by_key(|&(addr, time, _, _)| (addr, time));
+            trace
+        });
+
+    let mut sorted = Vec::with_capacity(n);
+    let mut prev: Option<(Num<E>, Num<E>, AllocatedNum<E>)> = None;
+
+    for i in 0..n {
+        let mut cs = cs.namespace(|| format!("sorted access {}", i));
+        let entry = sorted_values.as_ref().map(|trace| trace[i]);
+
+        let is_write = Boolean::from(AllocatedBit::alloc(
+            cs.namespace(|| "is write"),
+            entry.map(|e| e.2),
+        )?);
+        let time = alloc_bits(
+            cs.namespace(|| "time"),
+            entry.map(|e| e.1 as u64),
+            time_bits,
+        )?;
+        let addr = alloc_bits(cs.namespace(|| "address"), entry.map(|e| e.0), addr_bits)?;
+        let value = AllocatedNum::alloc(cs.namespace(|| "value"), || Ok(entry.get()?.3))?;
+
+        let mut coeff = E::Fr::one();
+        let mut key = Num::zero().add_bool_with_coeff(one, &is_write, coeff);
+        for bit in &time {
+            coeff.double();
+            key = key.add_bool_with_coeff(one, bit, coeff);
+        }
+
+        let mut coeff = E::Fr::one();
+        let mut addr_num = Num::zero();
+        for bit in &addr {
+            addr_num = addr_num.add_bool_with_coeff(one, bit, coeff);
+            coeff.double();
+        }
+        let key = key + &(addr_num.clone() * addr_shift);
+
+        // The value read from an address touched for the first time is
+        // zero, and otherwise it is the value of the previous access.
+        let expected = match prev {
+            None => Num::zero(),
+            Some((ref prev_key, ref prev_addr, ref prev_value)) => {
+                // Keys must be strictly increasing
+                let gap = (key.clone() - prev_key - &Num::constant(E::Fr::one(), one))
+                    .into_allocated(cs.namespace(|| "key gap"))?;
+                range_check(cs.namespace(|| "key gap range"), &gap, key_bits)?;
+
+                let same_addr = is_zero(
+                    cs.namespace(|| "same address"),
+                    &(addr_num.clone() - prev_addr),
+                )?;
+
+                same_addr
+                    .mul(cs.namespace(|| "previous value"), prev_value)?
+                    .into()
+            }
+        };
+
+        // Constrain: (1 - is_write) * (value - expected) = 0
+        cs.enforce(
+            || "read consistency",
+            |lc| lc + &is_write.not().lc(one, E::Fr::one()),
+            |lc| lc + value.get_variable() - &expected.lc(E::Fr::one()),
+            |lc| lc,
+        );
+
+        sorted.push((key.clone(), value.clone()));
+        prev = Some((key, addr_num, value));
+    }
+
+    let original = grand_product(cs.namespace(|| "original trace"), &original, alpha, beta)?;
+    let sorted = grand_product(cs.namespace(|| "sorted trace"), &sorted, alpha, beta)?;
+
+    original.enforce_equal(cs.namespace(|| "permutation"), &sorted);
+
+    Ok(())
+}
+
+/// Allocates the `len` low bits of `value`, in little-endian order.
+fn alloc_bits<E, CS>(
+    mut cs: CS,
+    value: Option<u64>,
+    len: usize,
+) -> Result<Vec<Boolean>, SynthesisError>
+where
+    E: ScalarEngine,
+    CS: ConstraintSystem<E>,
+{
+    (0..len)
+        .map(|i| {
+            AllocatedBit::alloc(
+                cs.namespace(|| format!("bit {}", i)),
+                value.map(|v| (v >> i) & 1 == 1),
+            )
+            .map(Boolean::from)
+        })
+        .collect()
+}
+
+/// Returns a number which is one if `num` is zero, and zero otherwise.
+fn is_zero<E, CS>(mut cs: CS, num: &Num<E>) -> Result<AllocatedNum<E>, SynthesisError>
+where
+    E: ScalarEngine,
+    CS: ConstraintSystem<E>,
+{
+    let inv = AllocatedNum::alloc(cs.namespace(|| "inverse"), || {
+        Ok(num.get_value().get()?.inverse().unwrap_or_else(E::Fr::zero))
+    })?;
+
+    let res = AllocatedNum::alloc(cs.namespace(|| "result"), || {
+        if num.get_value().get()?.is_zero() {
+            Ok(E::Fr::one())
+        } else {
+            Ok(E::Fr::zero())
+        }
+    })?;
+
+    // Constrain: num * inv = 1 - res
+    cs.enforce(
+        || "inverse constraint",
+        |lc| lc + &num.lc(E::Fr::one()),
+        |lc| lc + inv.get_variable(),
+        |lc| lc + CS::one() - res.get_variable(),
+    );
+
+    // Constrain: num * res = 0
+    cs.enforce(
+        || "zero constraint",
+        |lc| lc + &num.lc(E::Fr::one()),
+        |lc| lc + res.get_variable(),
+        |lc| lc,
+    );
+
+    Ok(res)
+}
+
+/// Computes the product of `beta - key - alpha * value` over all entries
+/// of a trace.
+fn grand_product<E, CS>(
+    mut cs: CS,
+    trace: &[(Num<E>, AllocatedNum<E>)],
+    alpha: &AllocatedNum<E>,
+    beta: &AllocatedNum<E>,
+) -> Result<Num<E>, SynthesisError>
+where
+    E: ScalarEngine,
+    CS: ConstraintSystem<E>,
+{
+    let mut acc = Num::constant(E::Fr::one(), CS::one());
+
+    for (i, (key, value)) in trace.iter().enumerate() {
+        let mut cs = cs.namespace(|| format!("entry {}", i));
+
+        let scaled = value.mul(cs.namespace(|| "scaled value"), alpha)?;
+        let factor = Num::from(beta.clone()) - key - &Num::from(scaled);
+
+        let product = AllocatedNum::alloc(cs.namespace(|| "product"), || {
+            let mut tmp: E::Fr = *acc.get_value().get()?;
+            tmp.mul_assign(factor.get_value().get()?);
+            Ok(tmp)
+        })?;
+
+        cs.enforce(
+            || "product constraint",
+            |lc| lc + &acc.lc(E::Fr::one()),
+            |lc| lc + &factor.lc(E::Fr::one()),
+            |lc| lc + product.get_variable(),
+        );
+
+        acc = product.into();
+    }
+
+    Ok(acc)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::gadgets::test::TestConstraintSystem;
+    use paired::bls12_381::{Bls12, Fr};
+    use rand_core::SeedableRng;
+    use rand_xorshift::XorShiftRng;
+
+    fn alloc_items(
+        cs: &mut TestConstraintSystem<Bls12>,
+        values: &[Fr],
+    ) -> Vec<AllocatedNum<Bls12>> {
+        values
+            .iter()
+            .enumerate()
+            .map(|(i, v)| {
+                AllocatedNum::alloc(cs.namespace(|| format!("item {}", i)), || Ok(*v)).unwrap()
+            })
+            .collect()
+    }
+
+    fn alloc_bool(cs: &mut TestConstraintSystem<Bls12>, name: &str, value: bool) -> Boolean {
+        Boolean::from(AllocatedBit::alloc(cs.namespace(|| name), Some(value)).unwrap())
+    }
+
+    #[test]
+    fn test_select() {
+        let mut rng = XorShiftRng::from_seed([
+            0x59, 0x62, 0xbe, 0x3d, 0x76, 0x3d, 0x31, 0x8d, 0x17, 0xdb, 0x37, 0x32, 0x54, 0x06,
+            0xbc, 0xe5,
+        ]);
+
+        for k in 0..5 {
+            let values: Vec<Fr> = (0..(1 << k)).map(|_| Fr::random(&mut rng)).collect();
+
+            for index in 0..(1 << k) {
+                let mut cs = TestConstraintSystem::<Bls12>::new();
+                let items = alloc_items(&mut cs, &values);
+                let bits: Vec<_> = (0..k)
+                    .map(|j| alloc_bool(&mut cs, &format!("bit {}", j), (index >> j) & 1 == 1))
+                    .collect();
+                let before = cs.num_constraints();
+
+                let res = select(cs.namespace(|| "select"), &items, &bits).unwrap();
+
+                assert!(cs.is_satisfied());
+                assert_eq!(cs.num_constraints() - before, (1 << k) - 1);
+                assert_eq!(res.get_value().unwrap(), values[index]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_select_one_hot() {
+        let mut rng = XorShiftRng::from_seed([
+            0x59, 0x62, 0xbe, 0x3d, 0x76, 0x3d, 0x31, 0x8d, 0x17, 0xdb, 0x37, 0x32, 0x54, 0x06,
+            0xbc, 0xe5,
+        ]);
+
+        for n in 1..7 {
+            let values: Vec<Fr> = (0..n).map(|_| Fr::random(&mut rng)).collect();
+
+            for index in 0..n {
+                let mut cs = TestConstraintSystem::<Bls12>::new();
+                let items = alloc_items(&mut cs, &values);
+                let selectors: Vec<_> = (0..n)
+                    .map(|j| alloc_bool(&mut cs, &format!("selector {}", j), j == index))
+                    .collect();
+                let before = cs.num_constraints();
+
+                let res = select_one_hot(cs.namespace(|| "select"), &items, &selectors).unwrap();
+
+                assert!(cs.is_satisfied());
+                assert_eq!(cs.num_constraints() - before, n + 1);
+                assert_eq!(res.get_value().unwrap(), values[index]);
+
+                cs.set("select/selected/num", Fr::random(&mut rng));
+                assert_eq!(
+                    cs.which_is_unsatisfied(),
+                    Some(format!("select/selection {}", index).as_str())
+                );
+            }
+        }
+
+        let mut cs = TestConstraintSystem::<Bls12>::new();
+        let items = alloc_items(&mut cs, &[Fr::one(), Fr::one()]);
+        let selectors = vec![
+            alloc_bool(&mut cs, "selector 0", true),
+            alloc_bool(&mut cs, "selector 1", true),
+        ];
+
+        select_one_hot(cs.namespace(|| "select"), &items, &selectors).unwrap();
+
+        assert_eq!(
+            cs.which_is_unsatisfied(),
+            Some("select/exactly one selector")
+        );
+    }
+
+    fn memory_trace(
+        cs: &mut TestConstraintSystem<Bls12>,
+        trace: &[(u64, u64, bool)],
+    ) -> Vec<MemoryAccess<Bls12>> {
+        trace
+            .iter()
+            .enumerate()
+            .map(|(i, &(addr, value, is_write))| {
+                let mut cs = cs.namespace(|| format!("access {}", i));
+                MemoryAccess {
+                    addr: AllocatedNum::alloc(cs.namespace(|| "addr"), || {
+                        Ok(Fr::from_str(&addr.to_string()).unwrap())
+                    })
+                    .unwrap(),
+                    value: AllocatedNum::alloc(cs.namespace(|| "value"), || {
+                        Ok(Fr::from_str(&value.to_string()).unwrap())
+                    })
+                    .unwrap(),
+                    is_write: Boolean::from(
+                        AllocatedBit::alloc(cs.namespace(|| "is write"), Some(is_write)).unwrap(),
+                    ),
+                }
+            })
+            .collect()
+    }
+
+    fn check_memory(trace: &[(u64, u64, bool)]) -> TestConstraintSystem<Bls12> {
+        let mut rng = XorShiftRng::from_seed([
+            0x59, 0x62, 0xbe, 0x3d, 0x76, 0x3d, 0x31, 0x8d, 0x17, 0xdb, 0x37, 0x32, 0x54, 0x06,
+            0xbc, 0xe5,
+        ]);
+
+        let mut cs = TestConstraintSystem::<Bls12>::new();
+        let accesses = memory_trace(&mut cs, trace);
+        let alpha =
+            AllocatedNum::alloc(cs.namespace(|| "alpha"), || Ok(Fr::random(&mut rng))).unwrap();
+        let beta =
+            AllocatedNum::alloc(cs.namespace(|| "beta"), || Ok(Fr::random(&mut rng))).unwrap();
+
+        enforce_memory_consistency(cs.namespace(|| "memory"), &accesses, 8, &alpha, &beta).unwrap();
+
+        cs
+    }
+
+    #[test]
+    fn test_memory_consistency() {
+        let trace = [
+            (3, 0, false),
+            (3, 7, true),
+            (200, 5, true),
+            (3, 7, false),
+            (3, 9, true),
+            (200, 5, false),
+            (3, 9, false),
+            (17, 0, false),
+        ];
+
+        let cs = check_memory(&trace);
+        assert!(cs.is_satisfied());
+
+        // Reading a stale value
+        let mut bad = trace;
+        bad[6].1 = 7;
+        let cs = check_memory(&bad);
+        assert_eq!(
+            cs.which_is_unsatisfied(),
+            Some("memory/sorted access 4/read consistency")
+        );
+
+        // Reading a value that was never written
+        let mut bad = trace;
+        bad[7].1 = 1;
+        let cs = check_memory(&bad);
+        assert_eq!(
+            cs.which_is_unsatisfied(),
+            Some("memory/sorted access 5/read consistency")
+        );
+
+        // Reading before the first write
+        let mut bad = trace;
+        bad[0].1 = 7;
+        let cs = check_memory(&bad);
+        assert_eq!(
+            cs.which_is_unsatisfied(),
+            Some("memory/sorted access 0/read consistency")
+        );
+    }
+
+    #[test]
+    fn test_memory_tampered_sort() {
+        let trace = [(1, 4, true), (2, 6, true), (1, 4, false), (2, 6, false)];
+
+        let mut cs = check_memory(&trace);
+        assert!(cs.is_satisfied());
+
+        // Rewrite the value stored at address 2 in the sorted trace only,
+        // keeping every local constraint satisfied
+        let five = Fr::from_str("5").unwrap();
+        let alpha = cs.get("alpha/num");
+        let mut scaled = alpha;
+        scaled.mul_assign(&five);
+
+        cs.set("memory/sorted access 2/value/num", five);
+        cs.set("memory/sorted access 3/value/num", five);
+        cs.set("memory/sorted access 3/previous value/product num", five);
+
+        let mut prev = cs.get("memory/sorted trace/entry 1/product/num");
+        let mut product = prev;
+        for i in 2..4 {
+            let entry = format!("memory/sorted trace/entry {}", i);
+            let old_product = cs.get(&format!("{}/product/num", entry));
+            let mut factor = old_product;
+            factor.mul_assign(&prev.inverse().unwrap());
+            factor.add_assign(&alpha);
+            prev = old_product;
+
+            product.mul_assign(&factor);
+            cs.set(&format!("{}/scaled value/product num", entry), scaled);
+            cs.set(&format!("{}/product/num", entry), product);
+        }
+
+        assert_eq!(
+            cs.which_is_unsatisfied(),
+            Some("memory/permutation/num equality constraint")
+        );
+    }
+}
@@ -0,0 +1,913 @@
+//! Gadgets for arithmetic on integers too large for the scalar field of the
+//! underlying curve, such as elements of a non-native prime field.
+//!
+//! A [`BigNat`] is a little-endian sequence of limbs, each an
+//! [`AllocatedNum`] holding a word of `limb_width` bits. Alongside the limbs
+//! we track an upper bound on the value of any single limb, which lets sums
+//! and products be computed limb-wise without carrying. Carries are only
+//! resolved when two representations are checked for equality, which is how
+//! modular reduction is enforced: the prover supplies a quotient `q` and a
+//! remainder `r` and we check `x == q * m + r` with `r < m`.
+//!
+//! The constraint cost of each operation, for `n` limbs of width `w` and a
+//! modulus of `k` limbs, is roughly:
+//!
+//! | operation       | constraints                                         |
+//! |-----------------|-----------------------------------------------------|
+//! | `alloc`         | `n * (w + 1)`                                       |
+//! | `add`           | `n`                                                 |
+//! | `mul`           | `2n - 1` witnessed product limbs, `2n - 1` checks   |
+//! | `reduce`        | range checks on `q` and `r`, `r < m`, one carry pass|
+//! | `enforce_equal` | one range-checked carry per limb                    |
+//!
+//! Exact figures for secp256k1 and BLS12-381 base field parameters are pinned
+//! in the tests below.
+
+use ff::{Field, PrimeField, PrimeFieldRepr, ScalarEngine};
+use num_bigint::{BigInt, BigUint};
+use num_traits::{CheckedSub, One, Zero};
+
+use super::num::AllocatedNum;
+use super::Assignment;
+use crate::{ConstraintSystem, LinearCombination, SynthesisError, Variable};
+
+/// An integer represented by limbs of `limb_width` bits in the scalar field.
+pub struct BigNat<E: ScalarEngine> {
+    limbs: Vec<AllocatedNum<E>>,
+    value: Option<BigUint>,
+    limb_width: usize,
+    max_word: BigUint,
+}
+
+impl<E: ScalarEngine> Clone for BigNat<E> {
+    fn clone(&self) -> Self {
+        BigNat {
+            limbs: self.limbs.clone(),
+            value: self.value.clone(),
+            limb_width: self.limb_width,
+            max_word: self.max_word.clone(),
+        }
+    }
+}
+
+impl<E: ScalarEngine> BigNat<E> {
+    /// Allocates a number of `n_limbs` limbs of `limb_width` bits each,
+    /// range checking every limb.
+    pub fn alloc<CS>(
+        mut cs: CS,
+        value: Option<BigUint>,
+        limb_width: usize,
+        n_limbs: usize,
+    ) -> Result<Self, SynthesisError>
+    where
+        CS: ConstraintSystem<E>,
+    {
+        assert!(limb_width > 0 && n_limbs > 0);
+        assert!((limb_width as u32) < E::Fr::CAPACITY);
+
+        if let Some(ref v) = value {
+            if v.bits() > limb_width * n_limbs {
+                return Err(SynthesisError::Unsatisfiable);
+            }
+        }
+
+        let limb_values = value.as_ref().map(|v| decompose(v, limb_width, n_limbs));
+
+        let mut limbs = Vec::with_capacity(n_limbs);
+        for i in 0..n_limbs {
+            let limb = AllocatedNum::alloc(cs.namespace(|| format!("limb {}", i)), || {
+                Ok(nat_to_f::<E::Fr>(&limb_values.get()?[i]))
+            })?;
+            range_check(
+                cs.namespace(|| format!("limb {} range check", i)),
+                &limb,
+                limb_width,
+            )?;
+            limbs.push(limb);
+        }
+
+        Ok(BigNat {
+            limbs,
+            value,
+            limb_width,
+            max_word: (BigUint::one() << limb_width) - BigUint::one(),
+        })
+    }
+
+    pub fn get_value(&self) -> Option<BigUint> {
+        self.value.clone()
+    }
+
+    pub fn get_limbs(&self) -> &[AllocatedNum<E>] {
+        &self.limbs
+    }
+
+    pub fn limb_width(&self) -> usize {
+        self.limb_width
+    }
+
+    /// Upper bound on the integer this number can hold, given the bound
+    /// on its limbs.
+    fn max_value(&self) -> BigUint {
+        self.limbs
+            .iter()
+            .enumerate()
+            .fold(BigUint::zero(), |acc, (i, _)| {
+                acc + (&self.max_word << (self.limb_width * i))
+            })
+    }
+
+    /// Limb-wise sum of two numbers. The result is not normalized; its
+    /// limbs may exceed `limb_width` bits.
+    pub fn add<CS>(&self, mut cs: CS, other: &Self) -> Result<Self, SynthesisError>
+    where
+        CS: ConstraintSystem<E>,
+    {
+        assert_eq!(self.limb_width, other.limb_width);
+
+        let n = std::cmp::max(self.limbs.len(), other.limbs.len());
+        let max_word = &self.max_word + &other.max_word;
+        assert_fits::<E>(&max_word);
+
+        let mut limbs = Vec::with_capacity(n);
+        for i in 0..n {
+            let a = self.limbs.get(i);
+            let b = other.limbs.get(i);
+
+            let limb = AllocatedNum::alloc(cs.namespace(|| format!("sum limb {}", i)), || {
+                let mut tmp = E::Fr::zero();
+                if let Some(a) = a {
+                    tmp.add_assign(a.get_value().get()?);
+                }
+                if let Some(b) = b {
+                    tmp.add_assign(b.get_value().get()?);
+                }
+                Ok(tmp)
+            })?;
+
+            cs.enforce(
+                || format!("sum limb {} constraint", i),
+                |mut lc| {
+                    if let Some(a) = a {
+                        lc = lc + a.get_variable();
+                    }
+                    if let Some(b) = b {
+                        lc = lc + b.get_variable();
+                    }
+                    lc
+                },
+                |lc| lc + CS::one(),
+                |lc| lc + limb.get_variable(),
+            );
+
+            limbs.push(limb);
+        }
+
+        Ok(BigNat {
+            limbs,
+            value: self
+                .value
+                .as_ref()
+                .and_then(|a| other.value.as_ref().map(|b| a + b)),
+            limb_width: self.limb_width,
+            max_word,
+        })
+    }
+
+    /// Product of two numbers as a polynomial in `2^limb_width`. The
+    /// product limbs are witnessed and checked by evaluating both sides
+    /// at `2n - 1` distinct points. The result is not normalized.
+    pub fn mul<CS>(&self, mut cs: CS, other: &Self) -> Result<Self, SynthesisError>
+    where
+        CS: ConstraintSystem<E>,
+    {
+        assert_eq!(self.limb_width, other.limb_width);
+
+        let n = self.limbs.len() + other.limbs.len() - 1;
+        let max_word = BigUint::from(std::cmp::min(self.limbs.len(), other.limbs.len()))
+            * &self.max_word
+            * &other.max_word;
+        assert_fits::<E>(&max_word);
+
+        let mut limbs = Vec::with_capacity(n);
+        for k in 0..n {
+            let limb = AllocatedNum::alloc(cs.namespace(|| format!("product limb {}", k)), || {
+                let mut tmp = E::Fr::zero();
+                for (i, a) in self.limbs.iter().enumerate() {
+                    if k >= i && k - i < other.limbs.len() {
+                        let mut t = *a.get_value().get()?;
+                        t.mul_assign(other.limbs[k - i].get_value().get()?);
+                        tmp.add_assign(&t);
+                    }
+                }
+                Ok(tmp)
+            })?;
+            limbs.push(limb);
+        }
+
+        let mut x = E::Fr::zero();
+        for point in 0..n {
+            let eval = |limbs: &[AllocatedNum<E>]| {
+                let mut lc = LinearCombination::<E>::zero();
+                let mut coeff = E::Fr::one();
+                for limb in limbs {
+                    lc = lc + (coeff, limb.get_variable());
+                    coeff.mul_assign(&x);
+                }
+                lc
+            };
+
+            cs.enforce(
+                || format!("product evaluation {}", point),
+                |_| eval(&self.limbs),
+                |_| eval(&other.limbs),
+                |_| eval(&limbs),
+            );
+
+            x.add_assign(&E::Fr::one());
+        }
+
+        Ok(BigNat {
+            limbs,
+            value: self
+                .value
+                .as_ref()
+                .and_then(|a| other.value.as_ref().map(|b| a * b)),
+            limb_width: self.limb_width,
+            max_word,
+        })
+    }
+
+    /// Reduces this number modulo `modulus`, returning the normalized
+    /// remainder, which is constrained to be less than `modulus`.
+    pub fn reduce<CS>(&self, mut cs: CS, modulus: &BigUint) -> Result<Self, SynthesisError>
+    where
+        CS: ConstraintSystem<E>,
+    {
+        let remainder = self.value.as_ref().map(|v| v % modulus);
+        let quotient = self.value.as_ref().map(|v| v / modulus);
+
+        let r = Self::alloc_reduced(
+            cs.namespace(|| "remainder"),
+            remainder,
+            self.limb_width,
+            modulus,
+        )?;
+        let q = Self::alloc_quotient(
+            cs.namespace(|| "quotient"),
+            quotient,
+            &(self.max_value() / modulus),
+            self.limb_width,
+        )?;
+
+        enforce_equal_when_carried(
+            cs.namespace(|| "quotient check"),
+            &Limbs::from_nat(self),
+            &Limbs::mul_by_constant(&q, modulus, CS::one()).add(&Limbs::from_nat(&r)),
+            self.limb_width,
+        )?;
+
+        Ok(r)
+    }
+
+    /// Computes `(self + other) mod modulus`.
+    pub fn add_mod<CS>(
+        &self,
+        mut cs: CS,
+        other: &Self,
+        modulus: &BigUint,
+    ) -> Result<Self, SynthesisError>
+    where
+        CS: ConstraintSystem<E>,
+    {
+        self.add(cs.namespace(|| "add"), other)?
+            .reduce(cs.namespace(|| "reduce"), modulus)
+    }
+
+    /// Computes `(self - other) mod modulus`.
+    pub fn sub_mod<CS>(
+        &self,
+        mut cs: CS,
+        other: &Self,
+        modulus: &BigUint,
+    ) -> Result<Self, SynthesisError>
+    where
+        CS: ConstraintSystem<E>,
+    {
+        assert_eq!(self.limb_width, other.limb_width);
+
+        // We check `self + k * m == other + q * m + r`, with the constant
+        // multiple `k * m` chosen to be at least `other`, so that neither
+        // side of the equation can be negative.
+        let k = other.max_value() / modulus + BigUint::one();
+        let km = &k * modulus;
+
+        let remainder = self
+            .value
+            .as_ref()
+            .and_then(|a| other.value.as_ref().map(|b| (a + &km - b) % modulus));
+        let quotient = self
+            .value
+            .as_ref()
+            .and_then(|a| other.value.as_ref().map(|b| (a + &km - b) / modulus));
+
+        let r = Self::alloc_reduced(
+            cs.namespace(|| "remainder"),
+            remainder,
+            self.limb_width,
+            modulus,
+        )?;
+        let q = Self::alloc_quotient(
+            cs.namespace(|| "quotient"),
+            quotient,
+            &((self.max_value() + &km) / modulus),
+            self.limb_width,
+        )?;
+
+        enforce_equal_when_carried(
+            cs.namespace(|| "quotient check"),
+            &Limbs::from_nat(self).add(&Limbs::constant(&km, self.limb_width, CS::one())),
+            &Limbs::from_nat(other)
+                .add(&Limbs::mul_by_constant(&q, modulus, CS::one()))
+                .add(&Limbs::from_nat(&r)),
+            self.limb_width,
+        )?;
+
+        Ok(r)
+    }
+
+    /// Computes `(self * other) mod modulus`.
+    pub fn mul_mod<CS>(
+        &self,
+        mut cs: CS,
+        other: &Self,
+        modulus: &BigUint,
+    ) -> Result<Self, SynthesisError>
+    where
+        CS: ConstraintSystem<E>,
+    {
+        self.mul(cs.namespace(|| "mul"), other)?
+            .reduce(cs.namespace(|| "reduce"), modulus)
+    }
+
+    /// Computes the inverse of this number modulo `modulus`, which must
+    /// exist.
+    pub fn inverse_mod<CS>(&self, mut cs: CS, modulus: &BigUint) -> Result<Self, SynthesisError>
+    where
+        CS: ConstraintSystem<E>,
+    {
+        let inverse = match self.value {
+            Some(ref v) => Some(mod_inverse(v, modulus).ok_or(SynthesisError::DivisionByZero)?),
+            None => None,
+        };
+
+        let inv = Self::alloc_reduced(
+            cs.namespace(|| "inverse"),
+            inverse,
+            self.limb_width,
+            modulus,
+        )?;
+        let product = self.mul(cs.namespace(|| "product"), &inv)?;
+
+        let quotient = product.value.as_ref().map(|v| v / modulus);
+        let q = Self::alloc_quotient(
+            cs.namespace(|| "quotient"),
+            quotient,
+            &(product.max_value() / modulus),
+            self.limb_width,
+        )?;
+
+        // Constrain self * inv == q * m + 1
+        enforce_equal_when_carried(
+            cs.namespace(|| "quotient check"),
+            &Limbs::from_nat(&product),
+            &Limbs::mul_by_constant(&q, modulus, CS::one()).add(&Limbs::constant(
+                &BigUint::one(),
+                self.limb_width,
+                CS::one(),
+            )),
+            self.limb_width,
+        )?;
+
+        Ok(inv)
+    }
+
+    /// Enforces that two numbers represent the same integer, regardless of
+    /// how their limbs are normalized.
+    pub fn enforce_equal<CS>(&self, cs: CS, other: &Self) -> Result<(), SynthesisError>
+    where
+        CS: ConstraintSystem<E>,
+    {
+        assert_eq!(self.limb_width, other.limb_width);
+
+        enforce_equal_when_carried(
+            cs,
+            &Limbs::from_nat(self),
+            &Limbs::from_nat(other),
+            self.limb_width,
+        )
+    }
+
+    /// Allocates a remainder modulo `modulus` and constrains it to be
+    /// less than `modulus`.
+    fn alloc_reduced<CS>(
+        mut cs: CS,
+        value: Option<BigUint>,
+        limb_width: usize,
+        modulus: &BigUint,
+    ) -> Result<Self, SynthesisError>
+    where
+        CS: ConstraintSystem<E>,
+    {
+        let n_limbs = limbs_for_bits(modulus.bits(), limb_width);
+        let bound = modulus - BigUint::one();
+
+        let r = Self::alloc(cs.namespace(|| "value"), value, limb_width, n_limbs)?;
+
+        // r < m iff m - 1 - r can be represented as a natural number.
+        let slack_value = r
+            .value
+            .as_ref()
+            .map(|r| bound.checked_sub(r).unwrap_or_default());
+        let slack = Self::alloc(cs.namespace(|| "slack"), slack_value, limb_width, n_limbs)?;
+
+        enforce_equal_when_carried(
+            cs.namespace(|| "bound check"),
+            &Limbs::from_nat(&r).add(&Limbs::from_nat(&slack)),
+            &Limbs::constant(&bound, limb_width, CS::one()),
+            limb_width,
+        )?;
+
+        Ok(r)
+    }
+
+    fn alloc_quotient<CS>(
+        cs: CS,
+        value: Option<BigUint>,
+        max_value: &BigUint,
+        limb_width: usize,
+    ) -> Result<Self, SynthesisError>
+    where
+        CS: ConstraintSystem<E>,
+    {
+        let n_limbs = std::cmp::max(limbs_for_bits(max_value.bits(), limb_width), 1);
+
+        Self::alloc(cs, value, limb_width, n_limbs)
+    }
+}
+
+/// Enforces that `num` fits in `n_bits` bits.
+pub fn range_check<E, CS>(
+    mut cs: CS,
+    num: &AllocatedNum<E>,
+    n_bits: usize,
+) -> Result<(), SynthesisError>
+where
+    E: ScalarEngine,
+    CS: ConstraintSystem<E>,
+{
+    let repr = num.get_value().map(|v| v.into_repr());
+
+    let mut lc = LinearCombination::zero();
+    let mut coeff = E::Fr::one();
+
+    for i in 0..n_bits {
+        let bit = repr.map(|r| {
+            let limbs = r.as_ref();
+            i / 64 < limbs.len() && (limbs[i / 64] >> (i % 64)) & 1 == 1
+        });
+        let bit = super::boolean::AllocatedBit::alloc(cs.namespace(|| format!("bit {}", i)), bit)?;
+
+        lc = lc + (coeff, bit.get_variable());
+
+        coeff.double();
+    }
+
+    lc = lc - num.get_variable();
+
+    cs.enforce(|| "packing constraint", |lc| lc, |lc| lc, |_| lc);
+
+    Ok(())
+}
+
+/// A number in limb form whose limbs are linear combinations, used to
+/// assemble both sides of an equation before carrying.
+struct Limbs<E: ScalarEngine> {
+    lcs: Vec<LinearCombination<E>>,
+    values: Option<Vec<BigUint>>,
+    max_word: BigUint,
+}
+
+impl<E: ScalarEngine> Limbs<E> {
+    fn from_nat(n: &BigNat<E>) -> Self {
+        Limbs {
+            lcs: n
+                .limbs
+                .iter()
+                .map(|l| LinearCombination::zero() + l.get_variable())
+                .collect(),
+            values: n
+                .limbs
+                .iter()
+                .map(|l| l.get_value().map(|v| f_to_nat(&v)))
+                .collect(),
+            max_word: n.max_word.clone(),
+        }
+    }
+
+    fn constant(value: &BigUint, limb_width: usize, one: Variable) -> Self {
+        let n_limbs = std::cmp::max(limbs_for_bits(value.bits(), limb_width), 1);
+        let values = decompose(value, limb_width, n_limbs);
+
+        Limbs {
+            lcs: values
+                .iter()
+                .map(|v| LinearCombination::zero() + (nat_to_f::<E::Fr>(v), one))
+                .collect(),
+            max_word: values.iter().max().cloned().unwrap_or_default(),
+            values: Some(values),
+        }
+    }
+
+    /// The product of `n` with a constant, which is linear in the limbs of
+    /// `n` and so needs no constraints.
+    fn mul_by_constant(n: &BigNat<E>, constant: &BigUint, one: Variable) -> Self {
+        let c = Limbs::<E>::constant(constant, n.limb_width, one);
+        let c_values = c.values.unwrap();
+        let len = n.limbs.len() + c_values.len() - 1;
+
+        let mut lcs = vec![LinearCombination::zero(); len];
+        for (i, limb) in n.limbs.iter().enumerate() {
+            for (j, cv) in c_values.iter().enumerate() {
+                lcs[i + j] = lcs[i + j].clone() + (nat_to_f::<E::Fr>(cv), limb.get_variable());
+            }
+        }
+
+        let values = n
+            .limbs
+            .iter()
+            .map(|l| l.get_value())
+            .collect::<Option<Vec<_>>>()
+            .map(|nv| {
+                let mut values = vec![BigUint::zero(); len];
+                for (i, v) in nv.iter().enumerate() {
+                    for (j, cv) in c_values.iter().enumerate() {
+                        values[i + j] += f_to_nat(v) * cv;
+                    }
+                }
+                values
+            });
+
+        Limbs {
+            lcs,
+            values,
+            max_word: BigUint::from(std::cmp::min(n.limbs.len(), c_values.len()))
+                * &n.max_word
+                * &c.max_word,
+        }
+    }
+
+    fn add(mut self, other: &Self) -> Self {
+        let n = std::cmp::max(self.lcs.len(), other.lcs.len());
+        self.lcs.resize(n, LinearCombination::zero());
+        for (a, b) in self.lcs.iter_mut().zip(other.lcs.iter()) {
+            *a = a.clone() + b;
+        }
+
+        self.values = match (self.values, other.values.as_ref()) {
+            (Some(mut a), Some(b)) => {
+                a.resize(n, BigUint::zero());
+                for (a, b) in a.iter_mut().zip(b.iter()) {
+                    *a += b;
+                }
+                Some(a)
+            }
+            _ => None,
+        };
+        self.max_word += &other.max_word;
+
+        self
+    }
+}
+
+/// Enforces that two limb representations encode the same integer by
+/// propagating range-checked carries from the least significant limb.
+///
+/// To keep every intermediate value non-negative, `max_word` is added to
+/// each limb of the difference; the carried representation of that offset
+/// is known in advance and subtracted back out.
+fn enforce_equal_when_carried<E, CS>(
+    mut cs: CS,
+    left: &Limbs<E>,
+    right: &Limbs<E>,
+    limb_width: usize,
+) -> Result<(), SynthesisError>
+where
+    E: ScalarEngine,
+    CS: ConstraintSystem<E>,
+{
+    let n = std::cmp::max(left.lcs.len(), right.lcs.len());
+    let max_word = std::cmp::max(&left.max_word, &right.max_word).clone();
+    let base = BigUint::one() << limb_width;
+    let base_f = nat_to_f::<E::Fr>(&base);
+    let max_word_f = nat_to_f::<E::Fr>(&max_word);
+
+    let mut accumulated_extra = BigUint::zero();
+    let mut carry_max = BigUint::zero();
+    let mut carry_in: Option<(AllocatedNum<E>, Option<BigUint>)> = None;
+
+    for i in 0..n {
+        let mut cs = cs.namespace(|| format!("limb {}", i));
+
+        accumulated_extra += &max_word;
+        let remainder = &accumulated_extra % &base;
+        accumulated_extra >>= limb_width;
+
+        carry_max = (&carry_max + &max_word + &max_word) >> limb_width;
+        assert_fits::<E>(&(&carry_max << limb_width));
+
+        let zero = LinearCombination::zero();
+        let left_lc = left.lcs.get(i).unwrap_or(&zero);
+        let right_lc = right.lcs.get(i).unwrap_or(&zero);
+
+        let mut lc = LinearCombination::zero() + left_lc - right_lc + (max_word_f, CS::one())
+            - (nat_to_f::<E::Fr>(&remainder), CS::one());
+        if let Some((ref carry, _)) = carry_in {
+            lc = lc + carry.get_variable();
+        }
+
+        if i == n - 1 {
+            let expected = nat_to_f::<E::Fr>(&(&accumulated_extra << limb_width));
+            cs.enforce(
+                || "final carry",
+                |_| lc,
+                |lc| lc + CS::one(),
+                |lc| lc + (expected, CS::one()),
+            );
+        } else {
+            let carry_value = match (&left.values, &right.values, &carry_in) {
+                (Some(l), Some(r), None) => {
+                    Some((l.get(i).cloned(), r.get(i).cloned(), BigUint::zero()))
+                }
+                (Some(l), Some(r), Some((_, Some(c)))) => {
+                    Some((l.get(i).cloned(), r.get(i).cloned(), c.clone()))
+                }
+                _ => None,
+            }
+            .map(|(l, r, c)| {
+                (l.unwrap_or_default() + c + &max_word)
+                    .checked_sub(&(r.unwrap_or_default() + &remainder))
+                    .unwrap_or_default()
+                    >> limb_width
+            });
+
+            let carry = AllocatedNum::alloc(cs.namespace(|| "carry"), || {
+                Ok(nat_to_f::<E::Fr>(carry_value.get()?))
+            })?;
+            range_check(
+                cs.namespace(|| "carry range check"),
+                &carry,
+                carry_max.bits(),
+            )?;
+
+            cs.enforce(
+                || "carry constraint",
+                |_| lc,
+                |lc| lc + CS::one(),
+                |lc| lc + (base_f, carry.get_variable()),
+            );
+
+            carry_in = Some((carry, carry_value));
+        }
+    }
+
+    Ok(())
+}
+
+fn assert_fits<E: ScalarEngine>(n: &BigUint) {
+    assert!(
+        n.bits() < E::Fr::CAPACITY as usize,
+        "limb values must fit in the scalar field"
+    );
+}
+
+fn limbs_for_bits(bits: usize, limb_width: usize) -> usize {
+    (bits + limb_width - 1) / limb_width
+}
+
+fn decompose(n: &BigUint, limb_width: usize, n_limbs: usize) -> Vec<BigUint> {
+    let mask = (BigUint::one() << limb_width) - BigUint::one();
+    (0..n_limbs)
+        .map(|i| (n >> (limb_width * i)) & &mask)
+        .collect()
+}
+
+fn nat_to_f<F: PrimeField>(n: &BigUint) -> F {
+    let mut repr = F::Repr::default();
+    let mut bytes = n.to_bytes_le();
+    bytes.resize(repr.as_ref().len() * 8, 0);
+    repr.read_le(&bytes[..]).expect("value fits in the repr");
+
+    F::from_repr(repr).expect("value fits in the field")
+}
+
+fn f_to_nat<F: PrimeField>(f: &F) -> BigUint {
+    let mut bytes = vec![];
+    f.into_repr()
+        .write_le(&mut bytes)
+        .expect("writing to a vec cannot fail");
+
+    BigUint::from_bytes_le(&bytes)
+}
+
+/// Computes the inverse of `a` modulo `m` with the extended Euclidean
+/// algorithm, if it exists.
+fn mod_inverse(a: &BigUint, m: &BigUint) -> Option<BigUint> {
+    let (mut t, mut new_t) = (BigInt::zero(), BigInt::one());
+    let (mut r, mut new_r) = (BigInt::from(m.clone()), BigInt::from(a % m));
+
+    while !new_r.is_zero() {
+        let q = &r / &new_r;
+
+        let tmp = &t - &q * &new_t;
+        t = std::mem::replace(&mut new_t, tmp);
+        let tmp = &r - &q * &new_r;
+        r = std::mem::replace(&mut new_r, tmp);
+    }
+
+    if !r.is_one() {
+        return None;
+    }
+    if t < BigInt::zero() {
+        t += BigInt::from(m.clone());
+    }
+
+    t.to_biguint()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::gadgets::test::TestConstraintSystem;
+    use paired::bls12_381::Bls12;
+    use rand_core::{RngCore, SeedableRng};
+    use rand_xorshift::XorShiftRng;
+
+    fn secp256k1_p() -> BigUint {
+        (BigUint::one() << 256) - (BigUint::one() << 32) - BigUint::from(977u64)
+    }
+
+    fn bls12_381_q() -> BigUint {
+        BigUint::parse_bytes(
+            b"1a0111ea397fe69a4b1ba7b6434bacd764774b84f38512bf6730d2a0f6b0f6241eabfffeb153ffffb9feffffffffaaab",
+            16,
+        )
+        .unwrap()
+    }
+
+    fn random_below<R: RngCore>(rng: &mut R, m: &BigUint) -> BigUint {
+        let mut bytes = vec![0u8; m.bits() / 8 + 16];
+        rng.fill_bytes(&mut bytes);
+        BigUint::from_bytes_le(&bytes) % m
+    }
+
+    fn alloc<CS: ConstraintSystem<Bls12>>(cs: CS, v: &BigUint, m: &BigUint) -> BigNat<Bls12> {
+        BigNat::alloc(cs, Some(v.clone()), 64, limbs_for_bits(m.bits(), 64)).unwrap()
+    }
+
+    #[test]
+    fn test_modular_ops() {
+        let mut rng = XorShiftRng::from_seed([
+            0x59, 0x62, 0xbe, 0x3d, 0x76, 0x3d, 0x31, 0x8d, 0x17, 0xdb, 0x37, 0x32, 0x54, 0x06,
+            0xbc, 0xe5,
+        ]);
+
+        for m in &[secp256k1_p(), bls12_381_q()] {
+            for _ in 0..5 {
+                let a = random_below(&mut rng, m);
+                let b = random_below(&mut rng, m);
+
+                let mut cs = TestConstraintSystem::<Bls12>::new();
+                let a_nat = alloc(cs.namespace(|| "a"), &a, m);
+                let b_nat = alloc(cs.namespace(|| "b"), &b, m);
+
+                let sum = a_nat.add_mod(cs.namespace(|| "add"), &b_nat, m).unwrap();
+                let diff = a_nat.sub_mod(cs.namespace(|| "sub"), &b_nat, m).unwrap();
+                let prod = a_nat.mul_mod(cs.namespace(|| "mul"), &b_nat, m).unwrap();
+                let inv = a_nat.inverse_mod(cs.namespace(|| "inv"), m).unwrap();
+
+                assert!(cs.is_satisfied());
+                assert_eq!(sum.get_value().unwrap(), (&a + &b) % m);
+                assert_eq!(diff.get_value().unwrap(), (&a + m - &b) % m);
+                assert_eq!(prod.get_value().unwrap(), (&a * &b) % m);
+                assert_eq!((&a * inv.get_value().unwrap()) % m, BigUint::one());
+
+                // The remainders are normalized, so they agree limb for limb
+                // with freshly allocated copies.
+                let expected = alloc(cs.namespace(|| "expected"), &((&a * &b) % m), m);
+                prod.enforce_equal(cs.namespace(|| "equal"), &expected)
+                    .unwrap();
+                assert!(cs.is_satisfied());
+            }
+        }
+    }
+
+    #[test]
+    fn test_reduce_unnormalized() {
+        let m = secp256k1_p();
+        let a = &m - BigUint::one();
+
+        let mut cs = TestConstraintSystem::<Bls12>::new();
+        let a_nat = alloc(cs.namespace(|| "a"), &a, &m);
+        let square = a_nat.mul(cs.namespace(|| "square"), &a_nat).unwrap();
+        let doubled = square.add(cs.namespace(|| "double"), &square).unwrap();
+        let reduced = doubled.reduce(cs.namespace(|| "reduce"), &m).unwrap();
+
+        assert!(cs.is_satisfied());
+        assert_eq!(doubled.get_value().unwrap(), &a * &a * BigUint::from(2u64));
+        assert_eq!(reduced.get_value().unwrap(), BigUint::from(2u64));
+    }
+
+    #[test]
+    fn test_enforce_equal_unsatisfied() {
+        let m = secp256k1_p();
+
+        let mut cs = TestConstraintSystem::<Bls12>::new();
+        let a = alloc(cs.namespace(|| "a"), &BigUint::from(5u64), &m);
+        let b = alloc(cs.namespace(|| "b"), &BigUint::from(6u64), &m);
+        a.enforce_equal(cs.namespace(|| "equal"), &b).unwrap();
+
+        assert!(!cs.is_satisfied());
+    }
+
+    #[test]
+    fn test_range_checks() {
+        let mut cs = TestConstraintSystem::<Bls12>::new();
+        let too_big = BigUint::one() << 256;
+        assert!(BigNat::alloc(&mut cs, Some(too_big), 64, 4).is_err());
+
+        // A limb that does not fit its width is rejected.
+        let mut cs = TestConstraintSystem::<Bls12>::new();
+        let n = BigNat::alloc(&mut cs, Some(BigUint::from(3u64)), 8, 1).unwrap();
+        assert!(cs.is_satisfied());
+        cs.set("limb 0/num", nat_to_f(&BigUint::from(256u64)));
+        assert!(!cs.is_satisfied());
+        assert_eq!(n.get_limbs().len(), 1);
+    }
+
+    #[test]
+    fn test_inverse_of_zero() {
+        let m = secp256k1_p();
+
+        let mut cs = TestConstraintSystem::<Bls12>::new();
+        let zero = alloc(cs.namespace(|| "zero"), &BigUint::zero(), &m);
+        match zero.inverse_mod(cs.namespace(|| "inv"), &m) {
+            Err(SynthesisError::DivisionByZero) => {}
+            _ => panic!("expected DivisionByZero"),
+        }
+    }
+
+    #[test]
+    fn test_constraint_counts() {
+        fn count<F>(m: &BigUint, f: F) -> usize
+        where
+            F: FnOnce(&mut TestConstraintSystem<Bls12>, &BigNat<Bls12>, &BigNat<Bls12>),
+        {
+            let mut cs = TestConstraintSystem::<Bls12>::new();
+            let a = alloc(cs.namespace(|| "a"), &(m - BigUint::one()), m);
+            let b = alloc(cs.namespace(|| "b"), &(m - BigUint::from(2u64)), m);
+            let before = cs.num_constraints();
+            f(&mut cs, &a, &b);
+            assert!(cs.is_satisfied());
+            cs.num_constraints() - before
+        }
+
+        for (m, expected) in &[
+            (secp256k1_p(), [4, 7, 804, 870, 1349, 1349]),
+            (bls12_381_q(), [6, 11, 1208, 1269, 2038, 2038]),
+        ] {
+            let counts = [
+                count(m, |cs, a, b| {
+                    a.add(cs.namespace(|| "add"), b).unwrap();
+                }),
+                count(m, |cs, a, b| {
+                    a.mul(cs.namespace(|| "mul"), b).unwrap();
+                }),
+                count(m, |cs, a, b| {
+                    a.add_mod(cs.namespace(|| "add_mod"), b, m).unwrap();
+                }),
+                count(m, |cs, a, b| {
+                    a.sub_mod(cs.namespace(|| "sub_mod"), b, m).unwrap();
+                }),
+                count(m, |cs, a, b| {
+                    a.mul_mod(cs.namespace(|| "mul_mod"), b, m).unwrap();
+                }),
+                count(m, |cs, a, _| {
+                    a.inverse_mod(cs.namespace(|| "inverse_mod"), m).unwrap();
+                }),
+            ];
+
+            assert_eq!(&counts, expected);
+        }
+    }
+}
@@ -1,6 +1,7 @@
 //! Gadgets representing numbers in the scalar field of the underlying curve.
 
 use ff::{BitIterator, Field, PrimeField, PrimeFieldRepr, ScalarEngine};
+use std::ops::{Add, Mul, Neg, Sub};
 
 use crate::{ConstraintSystem, LinearCombination, SynthesisError, Variable};
 
@@ -212,6 +213,70 @@ impl<E: ScalarEngine> AllocatedNum<E> {
         Ok(bits.into_iter().map(Boolean::from).collect())
     }
 
+    pub fn add<CS>(&self, mut cs: CS, other: &Self) -> Result<Self, SynthesisError>
+    where
+        CS: ConstraintSystem<E>,
+    {
+        let mut value = None;
+
+        let var = cs.alloc(
+            || "sum num",
+            || {
+                let mut tmp = *self.value.get()?;
+                tmp.add_assign(other.value.get()?);
+
+                value = Some(tmp);
+
+                Ok(tmp)
+            },
+        )?;
+
+        // Constrain: (a + b) * 1 = a + b
+        cs.enforce(
+            || "addition constraint",
+            |lc| lc + self.variable + other.variable,
+            |lc| lc + CS::one(),
+            |lc| lc + var,
+        );
+
+        Ok(AllocatedNum {
+            value,
+            variable: var,
+        })
+    }
+
+    pub fn sub<CS>(&self, mut cs: CS, other: &Self) -> Result<Self, SynthesisError>
+    where
+        CS: ConstraintSystem<E>,
+    {
+        let mut value = None;
+
+        let var = cs.alloc(
+            || "difference num",
+            || {
+                let mut tmp = *self.value.get()?;
+                tmp.sub_assign(other.value.get()?);
+
+                value = Some(tmp);
+
+                Ok(tmp)
+            },
+        )?;
+
+        // Constrain: (a - b) * 1 = a - b
+        cs.enforce(
+            || "subtraction constraint",
+            |lc| lc + self.variable - other.variable,
+            |lc| lc + CS::one(),
+            |lc| lc + var,
+        );
+
+        Ok(AllocatedNum {
+            value,
+            variable: var,
+        })
+    }
+
     pub fn mul<CS>(&self, mut cs: CS, other: &Self) -> Result<Self, SynthesisError>
     where
         CS: ConstraintSystem<E>,
@@ -306,6 +371,57 @@ impl<E: ScalarEngine> AllocatedNum<E> {
         Ok(())
     }
 
+    /// Computes the multiplicative inverse of this number, which is
+    /// constrained to be nonzero.
+    pub fn inverse<CS>(&self, mut cs: CS) -> Result<Self, SynthesisError>
+    where
+        CS: ConstraintSystem<E>,
+    {
+        let mut value = None;
+
+        let var = cs.alloc(
+            || "inverse num",
+            || {
+                let tmp = *self.value.get()?;
+
+                if tmp.is_zero() {
+                    Err(SynthesisError::DivisionByZero)
+                } else {
+                    let inv = tmp.inverse().unwrap();
+
+                    value = Some(inv);
+
+                    Ok(inv)
+                }
+            },
+        )?;
+
+        // Constrain a * inv = 1, which is only valid
+        // iff a has a multiplicative inverse.
+        cs.enforce(
+            || "inverse constraint",
+            |lc| lc + self.variable,
+            |lc| lc + var,
+            |lc| lc + CS::one(),
+        );
+
+        Ok(AllocatedNum {
+            value,
+            variable: var,
+        })
+    }
+
+    /// Computes `self / other`. The divisor is constrained to be nonzero,
+    /// so `0 / 0` is rejected rather than left unconstrained.
+    pub fn div<CS>(&self, mut cs: CS, other: &Self) -> Result<Self, SynthesisError>
+    where
+        CS: ConstraintSystem<E>,
+    {
+        let inv = other.inverse(cs.namespace(|| "divisor inverse"))?;
+
+        self.mul(cs.namespace(|| "quotient"), &inv)
+    }
+
     /// Takes two allocated numbers (a, b) and returns
     /// (b, a) if the condition is true, and (a, b)
     /// otherwise.
@@ -351,6 +467,37 @@ impl<E: ScalarEngine> AllocatedNum<E> {
         Ok((c, d))
     }
 
+    /// Takes two allocated numbers (a, b) and returns
+    /// a if the condition is true, and b
+    /// otherwise.
+    pub fn conditionally_select<CS>(
+        mut cs: CS,
+        condition: &Boolean,
+        a: &Self,
+        b: &Self,
+    ) -> Result<Self, SynthesisError>
+    where
+        CS: ConstraintSystem<E>,
+    {
+        let c = Self::alloc(cs.namespace(|| "conditional select result"), || {
+            if *condition.get_value().get()? {
+                Ok(*a.value.get()?)
+            } else {
+                Ok(*b.value.get()?)
+            }
+        })?;
+
+        // Constrain: (a - b) * condition = c - b
+        cs.enforce(
+            || "conditional select constraint",
+            |lc| lc + a.variable - b.variable,
+            |_| condition.lc(CS::one(), E::Fr::one()),
+            |lc| lc + c.variable - b.variable,
+        );
+
+        Ok(c)
+    }
+
     pub fn get_value(&self) -> Option<E::Fr> {
         self.value
     }
@@ -365,6 +512,15 @@ pub struct Num<E: ScalarEngine> {
     lc: LinearCombination<E>,
 }
 
+impl<E: ScalarEngine> Clone for Num<E> {
+    fn clone(&self) -> Self {
+        Num {
+            value: self.value,
+            lc: self.lc.clone(),
+        }
+    }
+}
+
 impl<E: ScalarEngine> From<AllocatedNum<E>> for Num<E> {
     fn from(num: AllocatedNum<E>) -> Num<E> {
         Num {
@@ -382,6 +538,14 @@ impl<E: ScalarEngine> Num<E> {
         }
     }
 
+    /// A constant, expressed as a multiple of the `one` variable.
+    pub fn constant(value: E::Fr, one: Variable) -> Self {
+        Num {
+            value: Some(value),
+            lc: LinearCombination::zero() + (value, one),
+        }
+    }
+
     pub fn get_value(&self) -> Option<E::Fr> {
         self.value
     }
@@ -390,6 +554,38 @@ impl<E: ScalarEngine> Num<E> {
         LinearCombination::zero() + (coeff, &self.lc)
     }
 
+    /// Enforces that this linear combination equals `other`.
+    pub fn enforce_equal<CS>(&self, mut cs: CS, other: &Self)
+    where
+        CS: ConstraintSystem<E>,
+    {
+        cs.enforce(
+            || "num equality constraint",
+            |_| self.lc.clone() - &other.lc,
+            |lc| lc + CS::one(),
+            |lc| lc,
+        );
+    }
+
+    /// Allocates a variable holding the value of this linear combination.
+    pub fn into_allocated<CS>(self, mut cs: CS) -> Result<AllocatedNum<E>, SynthesisError>
+    where
+        CS: ConstraintSystem<E>,
+    {
+        let num = AllocatedNum::alloc(cs.namespace(|| "allocated num"), || {
+            Ok(*self.value.get()?)
+        })?;
+
+        cs.enforce(
+            || "allocation constraint",
+            |_| self.lc,
+            |lc| lc + CS::one(),
+            |lc| lc + num.variable,
+        );
+
+        Ok(num)
+    }
+
     pub fn add_bool_with_coeff(self, one: Variable, bit: &Boolean, coeff: E::Fr) -> Self {
         let newval = match (self.value, bit.get_value()) {
             (Some(mut curval), Some(bval)) => {
@@ -409,15 +605,79 @@ impl<E: ScalarEngine> Num<E> {
     }
 }
 
+impl<'a, E: ScalarEngine> Add<&'a Num<E>> for Num<E> {
+    type Output = Num<E>;
+
+    fn add(self, other: &'a Num<E>) -> Num<E> {
+        let value = match (self.value, other.value) {
+            (Some(mut a), Some(b)) => {
+                a.add_assign(&b);
+                Some(a)
+            }
+            _ => None,
+        };
+
+        Num {
+            value,
+            lc: self.lc + &other.lc,
+        }
+    }
+}
+
+impl<'a, E: ScalarEngine> Sub<&'a Num<E>> for Num<E> {
+    type Output = Num<E>;
+
+    fn sub(self, other: &'a Num<E>) -> Num<E> {
+        let value = match (self.value, other.value) {
+            (Some(mut a), Some(b)) => {
+                a.sub_assign(&b);
+                Some(a)
+            }
+            _ => None,
+        };
+
+        Num {
+            value,
+            lc: self.lc - &other.lc,
+        }
+    }
+}
+
+impl<E: ScalarEngine> Mul<E::Fr> for Num<E> {
+    type Output = Num<E>;
+
+    #[allow(clippy::suspicious_arithmetic_impl)]
+    fn mul(self, coeff: E::Fr) -> Num<E> {
+        Num {
+            value: self.value.map(|mut v| {
+                v.mul_assign(&coeff);
+                v
+            }),
+            lc: LinearCombination::zero() + (coeff, &self.lc),
+        }
+    }
+}
+
+impl<E: ScalarEngine> Neg for Num<E> {
+    type Output = Num<E>;
+
+    fn neg(self) -> Num<E> {
+        let mut coeff = E::Fr::one();
+        coeff.negate();
+
+        self * coeff
+    }
+}
+
 #[cfg(test)]
 mod test {
-    use crate::ConstraintSystem;
+    use crate::{ConstraintSystem, SynthesisError};
     use ff::{BitIterator, Field, PrimeField};
     use paired::bls12_381::{Bls12, Fr};
     use rand_core::SeedableRng;
     use rand_xorshift::XorShiftRng;
 
-    use super::{AllocatedNum, Boolean};
+    use super::{AllocatedBit, AllocatedNum, Boolean, Num};
     use crate::gadgets::test::*;
 
     #[test]
@@ -460,6 +720,135 @@ mod test {
         assert!(!cs.is_satisfied());
     }
 
+    #[test]
+    fn test_num_addition_and_subtraction() {
+        let mut cs = TestConstraintSystem::<Bls12>::new();
+
+        let a =
+            AllocatedNum::alloc(cs.namespace(|| "a"), || Ok(Fr::from_str("12").unwrap())).unwrap();
+        let b =
+            AllocatedNum::alloc(cs.namespace(|| "b"), || Ok(Fr::from_str("10").unwrap())).unwrap();
+        let sum = a.add(cs.namespace(|| "add"), &b).unwrap();
+        let diff = a.sub(cs.namespace(|| "sub"), &b).unwrap();
+
+        assert!(cs.is_satisfied());
+        assert!(sum.value.unwrap() == Fr::from_str("22").unwrap());
+        assert!(diff.value.unwrap() == Fr::from_str("2").unwrap());
+        cs.set("add/sum num", Fr::from_str("23").unwrap());
+        assert!(cs.which_is_unsatisfied() == Some("add/addition constraint"));
+        cs.set("add/sum num", Fr::from_str("22").unwrap());
+        cs.set("sub/difference num", Fr::from_str("3").unwrap());
+        assert!(cs.which_is_unsatisfied() == Some("sub/subtraction constraint"));
+    }
+
+    #[test]
+    fn test_num_inverse_and_division() {
+        {
+            let mut cs = TestConstraintSystem::<Bls12>::new();
+
+            let a = AllocatedNum::alloc(cs.namespace(|| "a"), || Ok(Fr::from_str("12").unwrap()))
+                .unwrap();
+            let b = AllocatedNum::alloc(cs.namespace(|| "b"), || Ok(Fr::from_str("4").unwrap()))
+                .unwrap();
+            let inv = b.inverse(cs.namespace(|| "inverse")).unwrap();
+            let quotient = a.div(cs.namespace(|| "div"), &b).unwrap();
+
+            assert!(cs.is_satisfied());
+            assert!(quotient.value.unwrap() == Fr::from_str("3").unwrap());
+
+            let mut expected = inv.value.unwrap();
+            expected.mul_assign(&Fr::from_str("4").unwrap());
+            assert!(expected == Fr::one());
+
+            cs.set("inverse/inverse num", Fr::from_str("4").unwrap());
+            assert!(cs.which_is_unsatisfied() == Some("inverse/inverse constraint"));
+        }
+        {
+            let mut cs = TestConstraintSystem::<Bls12>::new();
+
+            let a = AllocatedNum::alloc(cs.namespace(|| "a"), || Ok(Fr::zero())).unwrap();
+            let b = AllocatedNum::alloc(cs.namespace(|| "b"), || Ok(Fr::zero())).unwrap();
+
+            match a.div(cs.namespace(|| "div"), &b) {
+                Err(SynthesisError::DivisionByZero) => {}
+                _ => panic!("division by zero should fail"),
+            }
+            match b.inverse(cs.namespace(|| "inverse")) {
+                Err(SynthesisError::DivisionByZero) => {}
+                _ => panic!("inverting zero should fail"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_num_conditional_select() {
+        let mut rng = XorShiftRng::from_seed([
+            0x59, 0x62, 0xbe, 0x3d, 0x76, 0x3d, 0x31, 0x8d, 0x17, 0xdb, 0x37, 0x32, 0x54, 0x06,
+            0xbc, 0xe5,
+        ]);
+
+        for &condition in &[false, true] {
+            let mut cs = TestConstraintSystem::<Bls12>::new();
+
+            let a = AllocatedNum::alloc(cs.namespace(|| "a"), || Ok(Fr::random(&mut rng))).unwrap();
+            let b = AllocatedNum::alloc(cs.namespace(|| "b"), || Ok(Fr::random(&mut rng))).unwrap();
+            let cond = Boolean::from(
+                AllocatedBit::alloc(cs.namespace(|| "condition"), Some(condition)).unwrap(),
+            );
+            let c = AllocatedNum::conditionally_select(&mut cs, &cond, &a, &b).unwrap();
+
+            assert!(cs.is_satisfied());
+
+            if condition {
+                assert_eq!(a.value.unwrap(), c.value.unwrap());
+                cs.set("conditional select result/num", b.value.unwrap());
+            } else {
+                assert_eq!(b.value.unwrap(), c.value.unwrap());
+                cs.set("conditional select result/num", a.value.unwrap());
+            }
+            assert!(cs.which_is_unsatisfied() == Some("conditional select constraint"));
+        }
+    }
+
+    #[test]
+    fn test_num_operators() {
+        let mut cs = TestConstraintSystem::<Bls12>::new();
+
+        let a =
+            AllocatedNum::alloc(cs.namespace(|| "a"), || Ok(Fr::from_str("7").unwrap())).unwrap();
+        let b =
+            AllocatedNum::alloc(cs.namespace(|| "b"), || Ok(Fr::from_str("5").unwrap())).unwrap();
+
+        // 3a - b + 1 = 17
+        let one = Num::constant(Fr::one(), TestConstraintSystem::<Bls12>::one());
+        let expr = Num::from(a.clone()) * Fr::from_str("3").unwrap() - &Num::from(b.clone()) + &one;
+        assert!(expr.get_value().unwrap() == Fr::from_str("17").unwrap());
+
+        let negated = -expr.clone();
+        let mut expected = Fr::from_str("17").unwrap();
+        expected.negate();
+        assert!(negated.get_value().unwrap() == expected);
+
+        let allocated = expr
+            .clone()
+            .into_allocated(cs.namespace(|| "expr"))
+            .unwrap();
+        assert!(allocated.value.unwrap() == Fr::from_str("17").unwrap());
+
+        let c =
+            AllocatedNum::alloc(cs.namespace(|| "c"), || Ok(Fr::from_str("17").unwrap())).unwrap();
+        expr.enforce_equal(cs.namespace(|| "equal"), &Num::from(c));
+
+        assert!(cs.is_satisfied());
+        assert_eq!(cs.num_constraints(), 2);
+
+        cs.set("c/num", Fr::from_str("18").unwrap());
+        assert!(cs.which_is_unsatisfied() == Some("equal/num equality constraint"));
+        cs.set("c/num", Fr::from_str("17").unwrap());
+        cs.set("expr/allocated num/num", Fr::from_str("18").unwrap());
+        assert!(cs.which_is_unsatisfied() == Some("expr/allocation constraint"));
+    }
+
     #[test]
     fn test_num_conditional_reversal() {
         let mut rng = XorShiftRng::from_seed([
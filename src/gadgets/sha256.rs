@@ -3,11 +3,12 @@
 //!
 //! [SHA-256]: https://tools.ietf.org/html/rfc6234
 
-use super::boolean::Boolean;
+use super::boolean::{AllocatedBit, Boolean};
 use super::multieq::MultiEq;
+use super::num::AllocatedNum;
 use super::uint32::UInt32;
-use crate::{ConstraintSystem, SynthesisError};
-use ff::ScalarEngine;
+use crate::{ConstraintSystem, LinearCombination, SynthesisError};
+use ff::{Field, PrimeField, ScalarEngine};
 
 #[allow(clippy::unreadable_literal)]
 const ROUND_CONSTANTS: [u32; 64] = [
@@ -73,6 +74,194 @@ where
     Ok(cur.into_iter().flat_map(|e| e.into_bits_be()).collect())
 }
 
+/// Computes the SHA-256 hash of the first `len` bytes of `input`, where `len`
+/// is a witness rather than fixed when the circuit is built.
+///
+/// `input` holds up to `input.len() / 8` bytes, and `len` is constrained to
+/// be no larger than that. The padding is computed in-circuit, all
+/// `max_blocks` compressions are performed, and the digest is taken from the
+/// block in which the padded message actually ends.
+pub fn sha256_var_len<E, CS>(
+    mut cs: CS,
+    input: &[Boolean],
+    len: &AllocatedNum<E>,
+    max_blocks: usize,
+) -> Result<Vec<Boolean>, SynthesisError>
+where
+    E: ScalarEngine,
+    CS: ConstraintSystem<E>,
+{
+    assert!(input.len() % 8 == 0);
+    assert!(max_blocks > 0);
+
+    let max_len = input.len() / 8;
+    // the message, the '1' bit and the 64-bit length must fit in max_blocks
+    assert!(
+        max_len + 9 <= max_blocks * 64,
+        "input cannot be padded within max_blocks"
+    );
+
+    let len_value = len.get_value().map(|v| {
+        let repr = v.into_repr();
+        let limbs = repr.as_ref();
+        if limbs[1..].iter().all(|&l| l == 0) {
+            limbs[0]
+        } else {
+            u64::max_value()
+        }
+    });
+
+    // One-hot encoding of the length: end[j] is set iff len == j.
+    let mut ends = Vec::with_capacity(max_len + 1);
+    for j in 0..=max_len {
+        ends.push(AllocatedBit::alloc(
+            cs.namespace(|| format!("end indicator {}", j)),
+            len_value.map(|l| l == j as u64),
+        )?);
+    }
+
+    cs.enforce(
+        || "exactly one end indicator",
+        |lc| ends.iter().fold(lc, |lc, e| lc + e.get_variable()),
+        |lc| lc + CS::one(),
+        |lc| lc + CS::one(),
+    );
+
+    cs.enforce(
+        || "end indicator position",
+        |lc| {
+            let mut coeff = E::Fr::zero();
+            ends.iter().fold(lc, |lc, e| {
+                let lc = lc + (coeff, e.get_variable());
+                coeff.add_assign(&E::Fr::one());
+                lc
+            })
+        },
+        |lc| lc + CS::one(),
+        |lc| lc + len.get_variable(),
+    );
+
+    // Binary decomposition of the length, for the length field.
+    let len_bit_count = (0usize.leading_zeros() - max_len.leading_zeros()) as usize;
+    let mut len_bits = Vec::with_capacity(len_bit_count);
+    for i in 0..len_bit_count {
+        len_bits.push(AllocatedBit::alloc(
+            cs.namespace(|| format!("length bit {}", i)),
+            len_value.map(|l| (l >> i) & 1 == 1),
+        )?);
+    }
+
+    cs.enforce(
+        || "length bits packing",
+        |lc| {
+            let mut coeff = E::Fr::one();
+            len_bits.iter().fold(lc, |lc, b| {
+                let lc = lc + (coeff, b.get_variable());
+                coeff.double();
+                lc
+            })
+        },
+        |lc| lc + CS::one(),
+        |lc| lc + len.get_variable(),
+    );
+
+    // included[j] is set iff byte j is part of the message, i.e. j < len,
+    // which is exactly when no end indicator at or before j is set.
+    let mut included = Vec::with_capacity(max_len);
+    let mut prefix = LinearCombination::<E>::zero();
+    for (j, end) in ends.iter().take(max_len).enumerate() {
+        prefix = prefix + end.get_variable();
+
+        let bit = AllocatedBit::alloc(
+            cs.namespace(|| format!("byte {} included", j)),
+            len_value.map(|l| (j as u64) < l),
+        )?;
+
+        cs.enforce(
+            || format!("byte {} inclusion constraint", j),
+            |lc| lc + &prefix + bit.get_variable(),
+            |lc| lc + CS::one(),
+            |lc| lc + CS::one(),
+        );
+
+        included.push(Boolean::from(bit));
+    }
+
+    // last[t] is set iff the padded message ends in block t.
+    let mut last = Vec::with_capacity(max_blocks);
+    for t in 0..max_blocks {
+        let bit = AllocatedBit::alloc(
+            cs.namespace(|| format!("block {} is last", t)),
+            len_value.map(|l| (l + 8) / 64 == t as u64),
+        )?;
+
+        cs.enforce(
+            || format!("block {} is last constraint", t),
+            |lc| {
+                ends.iter()
+                    .enumerate()
+                    .filter(|(j, _)| (j + 8) / 64 == t)
+                    .fold(lc, |lc, (_, e)| lc + e.get_variable())
+            },
+            |lc| lc + CS::one(),
+            |lc| lc + bit.get_variable(),
+        );
+
+        last.push(Boolean::from(bit));
+    }
+
+    let mut cur = get_sha256_iv();
+    let mut digest: Vec<Boolean> = (0..256).map(|_| Boolean::constant(false)).collect();
+
+    for (t, is_last) in last.iter().enumerate() {
+        let mut cs = cs.namespace(|| format!("block {}", t));
+
+        let mut block = Vec::with_capacity(512);
+        for pos in 0..512 {
+            let mut cs = cs.namespace(|| format!("padded bit {}", pos));
+            let p = 512 * t + pos;
+            let j = p / 8;
+
+            let mut bit = Boolean::constant(false);
+
+            // message bits, masked beyond the length
+            if j < max_len {
+                bit = Boolean::and(cs.namespace(|| "mask"), &input[p], &included[j])?;
+            }
+
+            // the '1' bit immediately after the message
+            if p % 8 == 0 && j <= max_len {
+                bit = Boolean::xor(
+                    cs.namespace(|| "append one"),
+                    &bit,
+                    &Boolean::from(ends[j].clone()),
+                )?;
+            }
+
+            // the message length in bits, as a 64-bit big-endian integer at
+            // the end of the last block
+            if pos >= 448 && 511 - pos >= 3 && 511 - pos - 3 < len_bit_count {
+                let len_bit = Boolean::from(len_bits[511 - pos - 3].clone());
+                let tmp = Boolean::and(cs.namespace(|| "length"), is_last, &len_bit)?;
+                bit = Boolean::xor(cs.namespace(|| "append length"), &bit, &tmp)?;
+            }
+
+            block.push(bit);
+        }
+
+        cur = sha256_compression_function(cs.namespace(|| "compression"), &block, &cur)?;
+
+        // keep the hash value of the last block
+        let bits: Vec<_> = cur.iter().flat_map(|e| e.clone().into_bits_be()).collect();
+        for (i, (d, b)) in digest.iter_mut().zip(bits.iter()).enumerate() {
+            let tmp = Boolean::and(cs.namespace(|| format!("select {}", i)), is_last, b)?;
+            *d = Boolean::xor(cs.namespace(|| format!("accumulate {}", i)), d, &tmp)?;
+        }
+    }
+
+    Ok(digest)
+}
+
 fn get_sha256_iv() -> Vec<UInt32> {
     IV.iter().map(|&v| UInt32::constant(v)).collect()
 }
@@ -273,7 +462,7 @@ mod test {
     use super::*;
     use crate::gadgets::boolean::AllocatedBit;
     use crate::gadgets::test::TestConstraintSystem;
-    use paired::bls12_381::Bls12;
+    use paired::bls12_381::{Bls12, Fr};
     use rand_core::{RngCore, SeedableRng};
     use rand_xorshift::XorShiftRng;
 
@@ -330,6 +519,99 @@ mod test {
         assert_eq!(cs.num_constraints() - 512, 25840);
     }
 
+    #[test]
+    fn test_var_len_against_vectors() {
+        use sha2::{Digest, Sha256};
+
+        let mut rng = XorShiftRng::from_seed([
+            0x59, 0x62, 0xbe, 0x3d, 0x76, 0x3d, 0x31, 0x8d, 0x17, 0xdb, 0x37, 0x32, 0x54, 0x06,
+            0xbc, 0xe5,
+        ]);
+
+        let max_blocks = 3;
+        let max_len = 3 * 64 - 9;
+        let data: Vec<u8> = (0..max_len).map(|_| rng.next_u32() as u8).collect();
+
+        for &len in &[0, 1, 55, 56, 63, 64, 119, 120, max_len] {
+            let mut h = Sha256::new();
+            h.input(&data[..len]);
+            let hash_result = h.result();
+
+            let mut cs = TestConstraintSystem::<Bls12>::new();
+            let mut input_bits = vec![];
+
+            for (byte_i, input_byte) in data.iter().enumerate() {
+                for bit_i in (0..8).rev() {
+                    let cs = cs.namespace(|| format!("input bit {} {}", byte_i, bit_i));
+
+                    input_bits.push(
+                        AllocatedBit::alloc(cs, Some((input_byte >> bit_i) & 1u8 == 1u8))
+                            .unwrap()
+                            .into(),
+                    );
+                }
+            }
+
+            let len_num = AllocatedNum::alloc(cs.namespace(|| "len"), || {
+                Ok(Fr::from_str(&len.to_string()).unwrap())
+            })
+            .unwrap();
+
+            let r = sha256_var_len(cs.namespace(|| "sha256"), &input_bits, &len_num, max_blocks)
+                .unwrap();
+
+            assert!(cs.is_satisfied());
+
+            let s = hash_result
+                .as_ref()
+                .iter()
+                .flat_map(|&byte| (0..8).rev().map(move |i| (byte >> i) & 1u8 == 1u8));
+
+            for (b, e) in r.iter().zip(s) {
+                assert_eq!(b.get_value().unwrap(), e);
+            }
+
+            // the length is bound to the digest
+            cs.set("len/num", Fr::from_str(&(len + 1).to_string()).unwrap());
+            assert!(!cs.is_satisfied());
+        }
+    }
+
+    #[test]
+    fn test_var_len_num_constraints() {
+        let mut cs = TestConstraintSystem::<Bls12>::new();
+        let input_bits: Vec<_> = (0..55 * 8)
+            .map(|i| {
+                Boolean::from(
+                    AllocatedBit::alloc(cs.namespace(|| format!("input bit {}", i)), Some(false))
+                        .unwrap(),
+                )
+            })
+            .collect();
+        let len = AllocatedNum::alloc(cs.namespace(|| "len"), || Ok(Fr::zero())).unwrap();
+        let before = cs.num_constraints();
+
+        sha256_var_len(cs.namespace(|| "sha256"), &input_bits, &len, 1).unwrap();
+
+        assert!(cs.is_satisfied());
+        assert_eq!(cs.num_constraints() - before, 26532);
+    }
+
+    #[test]
+    fn test_var_len_rejects_long_length() {
+        let mut cs = TestConstraintSystem::<Bls12>::new();
+        let input_bits: Vec<_> = (0..8).map(|_| Boolean::constant(false)).collect();
+        let len =
+            AllocatedNum::alloc(cs.namespace(|| "len"), || Ok(Fr::from_str("2").unwrap())).unwrap();
+
+        sha256_var_len(cs.namespace(|| "sha256"), &input_bits, &len, 1).unwrap();
+
+        assert_eq!(
+            cs.which_is_unsatisfied(),
+            Some("sha256/exactly one end indicator")
+        );
+    }
+
     #[test]
     fn test_against_vectors() {
         use sha2::{Digest, Sha256};
@@ -0,0 +1,309 @@
+use super::error::{GPUError, GPUResult};
+use super::locks::GPULock;
+use crate::multicore::Worker;
+use crate::multiexp::{multiexp as cpu_multiexp, FullDensity};
+use ff::PrimeField;
+use groupy::{CurveAffine, CurveProjective};
+use paired::Engine;
+use std::any::{Any, TypeId};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Arc;
+
+/// A device FFTs can be offloaded to.
+pub trait FftDevice<E: Engine>: Send {
+    /// Performs a radix-2 FFT of `a`, whose length is `1 << log_n`, in place.
+    fn radix_fft(&mut self, a: &mut [E::Fr], omega: &E::Fr, log_n: u32) -> GPUResult<()>;
+}
+
+/// A device multiexps can be offloaded to, a chunk of bases at a time.
+pub trait MultiexpDevice<E: Engine>: Send {
+    /// The most bases a single multiexp on the device can take.
+    fn chunk_size(&self) -> usize;
+
+    fn multiexp_g1(
+        &mut self,
+        bases: &[E::G1Affine],
+        exps: &[<E::Fr as PrimeField>::Repr],
+    ) -> GPUResult<E::G1>;
+
+    fn multiexp_g2(
+        &mut self,
+        bases: &[E::G2Affine],
+        exps: &[<E::Fr as PrimeField>::Repr],
+    ) -> GPUResult<E::G2>;
+}
+
+/// Creates the kernels the prover offloads FFTs and multiexps to.
+pub trait ComputeBackend<E: Engine>: Sync {
+    /// Whether the backend has any device. Without one, the prover never
+    /// tries to create kernels, nor checks the priority lock.
+    fn has_devices(&self) -> bool;
+
+    /// Creates a kernel for FFTs over `1 << log_d` elements.
+    fn create_fft_kernel(&self, log_d: u32) -> GPUResult<FFTKernel<E>>;
+
+    fn create_multiexp_kernel(&self) -> GPUResult<MultiexpKernel<E>>;
+}
+
+/// The OpenCL devices of `GPU_NVIDIA_DEVICES` when the `gpu` feature is
+/// enabled, and no device otherwise.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DefaultBackend;
+
+impl<E: Engine> ComputeBackend<E> for DefaultBackend {
+    #[cfg(feature = "gpu")]
+    fn has_devices(&self) -> bool {
+        !super::GPU_NVIDIA_DEVICES.is_empty()
+    }
+
+    #[cfg(not(feature = "gpu"))]
+    fn has_devices(&self) -> bool {
+        false
+    }
+
+    fn create_fft_kernel(&self, log_d: u32) -> GPUResult<FFTKernel<E>> {
+        FFTKernel::create(1 << log_d)
+    }
+
+    fn create_multiexp_kernel(&self) -> GPUResult<MultiexpKernel<E>> {
+        MultiexpKernel::create()
+    }
+}
+
+/// Performs FFTs on a device, which is locked for as long as the kernel
+/// lives.
+pub struct FFTKernel<E>
+where
+    E: Engine,
+{
+    device: Box<dyn FftDevice<E>>,
+    _locks: Vec<GPULock>, // RFC 1857: struct fields are dropped in the same order as they are declared.
+}
+
+impl<E> FFTKernel<E>
+where
+    E: Engine,
+{
+    pub fn new(device: Box<dyn FftDevice<E>>, locks: Vec<GPULock>) -> FFTKernel<E> {
+        FFTKernel {
+            device,
+            _locks: locks,
+        }
+    }
+
+    pub fn radix_fft(&mut self, a: &mut [E::Fr], omega: &E::Fr, log_n: u32) -> GPUResult<()> {
+        self.device.radix_fft(a, omega, log_n)
+    }
+}
+
+/// Splits multiexps between several devices, and the CPU, each of which is
+/// locked for as long as the kernel lives.
+pub struct MultiexpKernel<E>
+where
+    E: Engine,
+{
+    devices: Vec<Box<dyn MultiexpDevice<E>>>,
+    cpu_utilization: f64,
+    _locks: Vec<GPULock>, // RFC 1857: struct fields are dropped in the same order as they are declared.
+}
+
+impl<E> MultiexpKernel<E>
+where
+    E: Engine,
+{
+    /// Creates a kernel over `devices`, leaving the proportion
+    /// `cpu_utilization` of every multiexp to the CPU.
+    pub fn new(
+        devices: Vec<Box<dyn MultiexpDevice<E>>>,
+        cpu_utilization: f64,
+        locks: Vec<GPULock>,
+    ) -> MultiexpKernel<E> {
+        MultiexpKernel {
+            devices,
+            cpu_utilization: cpu_utilization.max(0f64).min(1f64),
+            _locks: locks,
+        }
+    }
+
+    pub fn num_devices(&self) -> usize {
+        self.devices.len()
+    }
+
+    pub fn multiexp<G>(
+        &mut self,
+        pool: &Worker,
+        bases: Arc<Vec<G>>,
+        exps: Arc<Vec<<E::Fr as PrimeField>::Repr>>,
+        skip: usize,
+        n: usize,
+    ) -> GPUResult<<G as CurveAffine>::Projective>
+    where
+        G: CurveAffine<Engine = E>,
+    {
+        let num_devices = self.devices.len();
+        if num_devices == 0 {
+            return Err(GPUError::Simple("No working GPUs found!"));
+        }
+
+        // Bases are skipped by `self.1` elements, when converted from (Arc<Vec<G>>, usize) to Source
+        // https://github.com/zkcrypto/bellman/blob/10c5010fd9c2ca69442dc9775ea271e286e776d8/src/multiexp.rs#L38
+        let bases = &bases[skip..(skip + n)];
+        let exps = &exps[..n];
+
+        let cpu_n = ((n as f64) * self.cpu_utilization) as usize;
+        let n = n - cpu_n;
+        let (cpu_bases, bases) = bases.split_at(cpu_n);
+        let (cpu_exps, exps) = exps.split_at(cpu_n);
+
+        let chunk_size = ((n as f64) / (num_devices as f64)).ceil() as usize;
+
+        // Every device gets a task of its own on the pool, which mostly waits
+        // on the device, while the CPU part runs alongside
+        let mut results = (0..num_devices).map(|_| None).collect::<Vec<_>>();
+        let cpu_acc = pool.scope(num_devices, |s, _| {
+            if n > 0 {
+                for (((bases, exps), device), result) in bases
+                    .chunks(chunk_size)
+                    .zip(exps.chunks(chunk_size))
+                    .zip(self.devices.iter_mut())
+                    .zip(results.iter_mut())
+                {
+                    s.spawn(move |_| {
+                        let device_acc = panic::catch_unwind(AssertUnwindSafe(
+                            || -> GPUResult<<G as CurveAffine>::Projective> {
+                                let mut acc = <G as CurveAffine>::Projective::zero();
+                                let device_chunk = device.chunk_size();
+                                for (bases, exps) in
+                                    bases.chunks(device_chunk).zip(exps.chunks(device_chunk))
+                                {
+                                    let result = device_multiexp(&mut **device, bases, exps)?;
+                                    acc.add_assign(&result);
+                                }
+                                Ok(acc)
+                            },
+                        ));
+                        *result = Some(device_acc.unwrap_or_else(|e| Err(GPUError::from(e))));
+                    });
+                }
+            }
+
+            cpu_multiexp(
+                pool,
+                (Arc::new(cpu_bases.to_vec()), 0),
+                FullDensity,
+                Arc::new(cpu_exps.to_vec()),
+                &mut None,
+            )
+            .wait()
+        });
+
+        let mut acc = <G as CurveAffine>::Projective::zero();
+        for result in results.into_iter().flatten() {
+            acc.add_assign(&result?);
+        }
+
+        let cpu_acc = cpu_acc.map_err(|_| GPUError::Simple("CPU part of the multiexp failed!"))?;
+        acc.add_assign(&cpu_acc);
+
+        Ok(acc)
+    }
+}
+
+/// Runs a multiexp over bases of either group of `E` on `device`.
+fn device_multiexp<E, G>(
+    device: &mut dyn MultiexpDevice<E>,
+    bases: &[G],
+    exps: &[<E::Fr as PrimeField>::Repr],
+) -> GPUResult<G::Projective>
+where
+    E: Engine,
+    G: CurveAffine<Engine = E>,
+{
+    let result: Box<dyn Any> = if TypeId::of::<G>() == TypeId::of::<E::G1Affine>() {
+        // Safety: `G` is `E::G1Affine`
+        let bases = unsafe { &*(bases as *const [G] as *const [E::G1Affine]) };
+        Box::new(device.multiexp_g1(bases, exps)?)
+    } else if TypeId::of::<G>() == TypeId::of::<E::G2Affine>() {
+        // Safety: `G` is `E::G2Affine`
+        let bases = unsafe { &*(bases as *const [G] as *const [E::G2Affine]) };
+        Box::new(device.multiexp_g2(bases, exps)?)
+    } else {
+        return Err(GPUError::Simple("Only E::G1 and E::G2 are supported!"));
+    };
+
+    Ok(*result
+        .downcast::<G::Projective>()
+        .expect("the projective group of `G` is the result's"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::{serial_fft, Scalar};
+    use crate::gpu::ReferenceDevice;
+    use ff::Field;
+    use paired::bls12_381::{Bls12, Fr};
+
+    #[test]
+    fn reference_kernels() {
+        let rng = &mut rand::thread_rng();
+        let pool = Worker::new();
+
+        let bases = Arc::new(
+            (0..100)
+                .map(|_| <Bls12 as Engine>::G2::random(rng).into_affine())
+                .collect::<Vec<_>>(),
+        );
+        let exps = Arc::new(
+            (0..90)
+                .map(|_| Fr::random(rng).into_repr())
+                .collect::<Vec<_>>(),
+        );
+        let expected = cpu_multiexp(
+            &pool,
+            (bases.clone(), 10),
+            FullDensity,
+            exps.clone(),
+            &mut None,
+        )
+        .wait()
+        .unwrap();
+
+        // A quarter of the 90 exponents is left to the CPU, and the rest is
+        // split between the devices in chunks they can take
+        let devices = vec![ReferenceDevice::new(7), ReferenceDevice::new(11)];
+        let mut kern = MultiexpKernel::<Bls12>::new(
+            devices
+                .iter()
+                .map(|d| Box::new(d.clone()) as Box<dyn MultiexpDevice<Bls12>>)
+                .collect(),
+            0.25,
+            vec![],
+        );
+        let result = kern.multiexp(&pool, bases.clone(), exps.clone(), 10, 90);
+        assert_eq!(result.unwrap(), expected);
+        assert_eq!(devices[0].calls(), 5);
+        assert_eq!(devices[1].calls(), 4);
+        assert_eq!(devices[0].failures() + devices[1].failures(), 0);
+
+        // Failures of any device fail the multiexp
+        let failing = ReferenceDevice::new(7).fail_after(2);
+        let mut kern = MultiexpKernel::<Bls12>::new(vec![Box::new(failing.clone())], 0f64, vec![]);
+        assert!(kern.multiexp(&pool, bases, exps, 10, 90).is_err());
+        assert!(failing.failures() > 0);
+
+        let coeffs = (0..16)
+            .map(|_| Scalar::<Bls12>(Fr::random(rng)))
+            .collect::<Vec<_>>();
+        let omega = Fr::random(rng);
+        let mut expected = coeffs.clone();
+        serial_fft::<Bls12, _>(&mut expected, &omega, 4);
+
+        let device = ReferenceDevice::new(1);
+        let mut kern = FFTKernel::<Bls12>::new(Box::new(device.clone()), vec![]);
+        let mut a = coeffs.into_iter().map(|s| s.0).collect::<Vec<_>>();
+        kern.radix_fft(&mut a, &omega, 4).unwrap();
+        assert!(a.iter().zip(expected.iter()).all(|(a, e)| *a == e.0));
+        assert_eq!(device.calls(), 1);
+    }
+}
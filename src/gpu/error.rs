@@ -1,7 +1,14 @@
+use std::io;
+use std::path::PathBuf;
+
 #[derive(thiserror::Error, Debug)]
 pub enum GPUError {
     #[error("GPUError: {0}")]
     Simple(&'static str),
+    #[error("GPU lock file {} could not be locked: {1}", .0.display())]
+    Lock(PathBuf, io::Error),
+    #[error("timed out waiting for GPU lock file {}", .0.display())]
+    LockTimeout(PathBuf),
     #[cfg(feature = "gpu")]
     #[error("Ocl Error: {0}")]
     Ocl(ocl::Error),
@@ -16,7 +23,6 @@ impl From<ocl::Error> for GPUError {
     }
 }
 
-#[cfg(feature = "gpu")]
 impl From<std::boxed::Box<dyn std::any::Any + std::marker::Send>> for GPUError {
     fn from(e: std::boxed::Box<dyn std::any::Any + std::marker::Send>) -> Self {
         match e.downcast::<Self>() {
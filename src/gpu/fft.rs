@@ -1,6 +1,6 @@
 use crate::gpu::{
     error::{GPUError, GPUResult},
-    locks, sources, structs, GPU_NVIDIA_DEVICES,
+    locks, sources, structs, FFTKernel, FftDevice, GPU_NVIDIA_DEVICES,
 };
 use ff::Field;
 use log::info;
@@ -14,7 +14,8 @@ const LOG2_MAX_ELEMENTS: usize = 32; // At most 2^32 elements is supported.
 const MAX_RADIX_DEGREE: u32 = 8; // Radix256
 const MAX_LOCAL_WORK_SIZE_DEGREE: u32 = 7; // 128
 
-pub struct FFTKernel<E>
+// FFT kernel for a single GPU
+pub struct SingleFFTKernel<E>
 where
     E: Engine,
 {
@@ -23,7 +24,6 @@ where
     fft_dst_buffer: Buffer<structs::PrimeFieldStruct<E::Fr>>,
     fft_pq_buffer: Buffer<structs::PrimeFieldStruct<E::Fr>>,
     fft_omg_buffer: Buffer<structs::PrimeFieldStruct<E::Fr>>,
-    _lock: locks::GPULock, // RFC 1857: struct fields are dropped in the same order as they are declared.
 }
 
 impl<E> FFTKernel<E>
@@ -31,8 +31,19 @@ where
     E: Engine,
 {
     pub fn create(n: u32) -> GPUResult<FFTKernel<E>> {
-        let lock = locks::GPULock::lock();
+        // FFTs run on the first device, which is the only one locked
+        let lock = locks::GPULock::lock(0)?;
+        let kernel = SingleFFTKernel::<E>::create(n)?;
 
+        Ok(FFTKernel::new(Box::new(kernel), vec![lock]))
+    }
+}
+
+impl<E> SingleFFTKernel<E>
+where
+    E: Engine,
+{
+    pub fn create(n: u32) -> GPUResult<SingleFFTKernel<E>> {
         let src = sources::kernel::<E>();
         let devices = &GPU_NVIDIA_DEVICES;
         if devices.is_empty() {
@@ -65,13 +76,12 @@ where
         info!("FFT: 1 working device(s) selected.");
         info!("FFT: Device 0: {}", pq.device().name()?);
 
-        Ok(FFTKernel {
+        Ok(SingleFFTKernel {
             proque: pq,
             fft_src_buffer: srcbuff,
             fft_dst_buffer: dstbuff,
             fft_pq_buffer: pqbuff,
             fft_omg_buffer: omgbuff,
-            _lock: lock,
         })
     }
 
@@ -184,3 +194,12 @@ where
         Ok(())
     }
 }
+
+impl<E> FftDevice<E> for SingleFFTKernel<E>
+where
+    E: Engine,
+{
+    fn radix_fft(&mut self, a: &mut [E::Fr], omega: &E::Fr, log_n: u32) -> GPUResult<()> {
+        SingleFFTKernel::radix_fft(self, a, omega, log_n)
+    }
+}
@@ -1,26 +1,110 @@
 use fs2::FileExt;
 use log::{debug, info, warn};
-use std::fs::File;
-use std::path::PathBuf;
+use std::env;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
 
-const GPU_LOCK_NAME: &str = "bellman.gpu.lock";
+use super::error::{GPUError, GPUResult};
+
+const GPU_LOCK_NAME: &str = "bellman.gpu";
 const PRIORITY_LOCK_NAME: &str = "bellman.priority.lock";
-fn tmp_path(filename: &str) -> PathBuf {
-    let mut p = std::env::temp_dir();
-    p.push(filename);
-    p
+
+/// The longest pause between attempts to take a lock with a timeout.
+const MAX_RETRY_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Returns the directory lock files are kept in, which is given by the
+/// `BELLMAN_LOCK_DIR` environment variable, or is the temporary directory.
+pub fn lock_dir() -> PathBuf {
+    match env::var_os("BELLMAN_LOCK_DIR") {
+        Some(dir) => PathBuf::from(dir),
+        None => env::temp_dir(),
+    }
+}
+
+fn open_lock_file(path: &Path) -> GPUResult<File> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|e| GPUError::Lock(path.to_path_buf(), e))?;
+    }
+
+    File::create(path).map_err(|e| GPUError::Lock(path.to_path_buf(), e))
+}
+
+/// Takes an exclusive lock on the file at `path`, waiting at most `timeout`
+/// for it if given, or for as long as it takes.
+fn lock_file(path: &Path, timeout: Option<Duration>) -> GPUResult<File> {
+    let f = open_lock_file(path)?;
+
+    let timeout = match timeout {
+        Some(timeout) => timeout,
+        None => {
+            f.lock_exclusive()
+                .map_err(|e| GPUError::Lock(path.to_path_buf(), e))?;
+            return Ok(f);
+        }
+    };
+
+    let start = Instant::now();
+    let mut interval = Duration::from_millis(1);
+    loop {
+        match f.try_lock_exclusive() {
+            Ok(()) => return Ok(f),
+            Err(ref e) if e.raw_os_error() == fs2::lock_contended_error().raw_os_error() => {}
+            Err(e) => return Err(GPUError::Lock(path.to_path_buf(), e)),
+        }
+
+        let elapsed = start.elapsed();
+        if elapsed >= timeout {
+            return Err(GPUError::LockTimeout(path.to_path_buf()));
+        }
+        thread::sleep(
+            *[interval, timeout - elapsed, MAX_RETRY_INTERVAL]
+                .iter()
+                .min()
+                .unwrap(),
+        );
+        interval *= 2;
+    }
 }
 
-/// `GPULock` prevents two kernel objects to be instantiated simultaneously.
+/// `GPULock` prevents two kernel objects to be instantiated simultaneously
+/// on the same device. Devices are identified by their index in
+/// `GPU_NVIDIA_DEVICES`, and each has a lock file of its own in `lock_dir()`.
 #[derive(Debug)]
 pub struct GPULock(File);
 impl GPULock {
-    pub fn lock() -> GPULock {
-        debug!("Acquiring GPU lock...");
-        let f = File::create(tmp_path(GPU_LOCK_NAME)).unwrap();
-        f.lock_exclusive().unwrap();
-        debug!("GPU lock acquired!");
-        GPULock(f)
+    /// Returns the path of the lock file of the `device`th device.
+    pub fn path(device: usize) -> PathBuf {
+        Self::path_in(&lock_dir(), device)
+    }
+
+    fn path_in(dir: &Path, device: usize) -> PathBuf {
+        dir.join(format!("{}.{}.lock", GPU_LOCK_NAME, device))
+    }
+
+    /// Locks the `device`th device, waiting for as long as it takes.
+    pub fn lock(device: usize) -> GPUResult<GPULock> {
+        Self::acquire(&lock_dir(), device, None)
+    }
+
+    /// Locks the `device`th device if it is not locked already, or returns
+    /// `GPUError::LockTimeout`.
+    pub fn try_lock(device: usize) -> GPUResult<GPULock> {
+        Self::acquire(&lock_dir(), device, Some(Duration::from_secs(0)))
+    }
+
+    /// Locks the `device`th device, or returns `GPUError::LockTimeout` if it
+    /// is still locked after `timeout`.
+    pub fn lock_timeout(device: usize, timeout: Duration) -> GPUResult<GPULock> {
+        Self::acquire(&lock_dir(), device, Some(timeout))
+    }
+
+    fn acquire(dir: &Path, device: usize, timeout: Option<Duration>) -> GPUResult<GPULock> {
+        debug!("Acquiring GPU lock of device {}...", device);
+        let f = lock_file(&Self::path_in(dir, device), timeout)?;
+        debug!("GPU lock of device {} acquired!", device);
+        Ok(GPULock(f))
     }
 }
 impl Drop for GPULock {
@@ -36,18 +120,53 @@ impl Drop for GPULock {
 #[derive(Debug)]
 pub struct PriorityLock(File);
 impl PriorityLock {
-    pub fn lock() -> PriorityLock {
+    pub fn path() -> PathBuf {
+        Self::path_in(&lock_dir())
+    }
+
+    fn path_in(dir: &Path) -> PathBuf {
+        dir.join(PRIORITY_LOCK_NAME)
+    }
+
+    /// Takes the priority lock, waiting for as long as it takes.
+    pub fn lock() -> GPUResult<PriorityLock> {
+        Self::acquire(&lock_dir(), None)
+    }
+
+    /// Takes the priority lock if no other process has it, or returns
+    /// `GPUError::LockTimeout`.
+    pub fn try_lock() -> GPUResult<PriorityLock> {
+        Self::acquire(&lock_dir(), Some(Duration::from_secs(0)))
+    }
+
+    /// Takes the priority lock, or returns `GPUError::LockTimeout` if
+    /// another process still has it after `timeout`.
+    pub fn lock_timeout(timeout: Duration) -> GPUResult<PriorityLock> {
+        Self::acquire(&lock_dir(), Some(timeout))
+    }
+
+    fn acquire(dir: &Path, timeout: Option<Duration>) -> GPUResult<PriorityLock> {
         debug!("Acquiring priority lock...");
-        let f = File::create(tmp_path(PRIORITY_LOCK_NAME)).unwrap();
-        f.lock_exclusive().unwrap();
+        let f = lock_file(&Self::path_in(dir), timeout)?;
         debug!("Priority lock acquired!");
-        PriorityLock(f)
+        Ok(PriorityLock(f))
     }
+
+    /// Whether another process has the priority lock. Lock files which
+    /// cannot be opened are taken not to be locked.
     pub fn is_locked() -> bool {
-        File::create(tmp_path(PRIORITY_LOCK_NAME))
-            .unwrap()
-            .try_lock_exclusive()
-            .is_err()
+        Self::is_locked_in(&lock_dir())
+    }
+
+    fn is_locked_in(dir: &Path) -> bool {
+        let path = Self::path_in(dir);
+        match open_lock_file(&path) {
+            Ok(f) => f.try_lock_exclusive().is_err(),
+            Err(e) => {
+                warn!("{}", e);
+                false
+            }
+        }
     }
 }
 impl Drop for PriorityLock {
@@ -61,6 +180,7 @@ where
     F: Fn() -> Option<K>,
 {
     priority: bool,
+    enabled: bool,
     _f: F,
     kernel: Option<K>,
 }
@@ -72,11 +192,25 @@ where
     pub fn new(f: F, priority: bool) -> LockedKernel<K, F> {
         LockedKernel::<K, F> {
             priority,
+            enabled: true,
+            _f: f,
+            kernel: None,
+        }
+    }
+    /// Never creates a kernel, for backends without any device.
+    pub fn disabled(f: F) -> LockedKernel<K, F> {
+        LockedKernel::<K, F> {
+            priority: false,
+            enabled: false,
             _f: f,
             kernel: None,
         }
     }
     pub fn get(&mut self) -> &mut Option<K> {
+        if !self.enabled {
+            return &mut self.kernel;
+        }
+
         if !self.priority && PriorityLock::is_locked() {
             if let Some(_kernel) = self.kernel.take() {
                 warn!("GPU acquired by a high priority process! Freeing up kernels...");
@@ -88,3 +222,90 @@ where
         &mut self.kernel
     }
 }
+
+/// Serializes the tests taking locks in the lock directory, which they share
+/// with every kernel the tests create.
+#[cfg(test)]
+pub(crate) fn lock_files_test_guard() -> std::sync::MutexGuard<'static, ()> {
+    lazy_static::lazy_static! {
+        static ref LOCK_FILES_TESTS: std::sync::Mutex<()> = std::sync::Mutex::new(());
+    }
+
+    LOCK_FILES_TESTS
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lock_files() {
+        // Locks are taken in a directory of the test's own, rather than the
+        // one of `BELLMAN_LOCK_DIR`, which other tests read concurrently
+        let dir = env::temp_dir().join(format!("bellperson-locks-{}", std::process::id()));
+        let try_lock = |device| GPULock::acquire(&dir, device, Some(Duration::from_secs(0)));
+        assert_eq!(GPULock::path_in(&dir, 1), dir.join("bellman.gpu.1.lock"));
+        assert_eq!(GPULock::path(1), lock_dir().join("bellman.gpu.1.lock"));
+
+        // Devices are locked separately, and the directory is created
+        let lock = try_lock(0).unwrap();
+        let other = try_lock(1).unwrap();
+        match try_lock(0) {
+            Err(GPUError::LockTimeout(path)) => assert_eq!(path, GPULock::path_in(&dir, 0)),
+            r => panic!("expected a timeout, got {:?}", r),
+        }
+
+        let start = Instant::now();
+        assert!(GPULock::acquire(&dir, 0, Some(Duration::from_millis(50))).is_err());
+        assert!(start.elapsed() >= Duration::from_millis(50));
+
+        // A blocked lock is taken once the lock is released
+        let waiter = {
+            let dir = dir.clone();
+            thread::spawn(move || GPULock::acquire(&dir, 0, Some(Duration::from_secs(10))))
+        };
+        thread::sleep(Duration::from_millis(20));
+        drop(lock);
+        waiter.join().unwrap().unwrap();
+        drop(other);
+
+        assert!(!PriorityLock::is_locked_in(&dir));
+        let priority = PriorityLock::acquire(&dir, Some(Duration::from_secs(0))).unwrap();
+        assert!(PriorityLock::is_locked_in(&dir));
+        assert!(PriorityLock::acquire(&dir, Some(Duration::from_secs(0))).is_err());
+        drop(priority);
+        assert!(!PriorityLock::is_locked_in(&dir));
+
+        // Lock files which cannot be created are reported as errors
+        let file = dir.join("file");
+        File::create(&file).unwrap();
+        match GPULock::acquire(&file, 0, Some(Duration::from_secs(0))) {
+            Err(GPUError::Lock(path, _)) => assert_eq!(path, file.join("bellman.gpu.0.lock")),
+            r => panic!("expected a lock error, got {:?}", r),
+        }
+        assert!(!PriorityLock::is_locked_in(&file));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn locked_kernels() {
+        // Kernels check the priority lock in the lock directory
+        let _guard = lock_files_test_guard();
+
+        let priority = PriorityLock::try_lock().unwrap();
+        assert!(PriorityLock::is_locked());
+        // Kernels without priority are not created, or are dropped, while
+        // another process has the priority lock
+        let mut kernel = LockedKernel::new(|| Some(()), false);
+        assert!(kernel.get().is_none());
+        let mut priority_kernel = LockedKernel::new(|| Some(()), true);
+        assert!(priority_kernel.get().is_some());
+        drop(priority);
+        assert!(!PriorityLock::is_locked());
+        assert!(kernel.get().is_some());
+        assert!(LockedKernel::disabled(|| Some(())).get().is_none());
+    }
+}
@@ -1,11 +1,15 @@
+mod device;
+pub use self::device::*;
+
 mod error;
 pub use self::error::*;
 
-#[cfg(feature = "gpu")]
 mod locks;
-#[cfg(feature = "gpu")]
 pub use self::locks::*;
 
+mod reference;
+pub use self::reference::*;
+
 #[cfg(feature = "gpu")]
 mod sources;
 #[cfg(feature = "gpu")]
@@ -33,8 +37,6 @@ pub use self::multiexp::*;
 
 #[cfg(not(feature = "gpu"))]
 mod nogpu;
-#[cfg(not(feature = "gpu"))]
-pub use self::nogpu::*;
 
 #[cfg(feature = "gpu")]
 use ocl::Device;
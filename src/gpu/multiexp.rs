@@ -3,17 +3,12 @@ use super::locks;
 use super::sources;
 use super::structs;
 use super::utils;
-use super::GPU_NVIDIA_DEVICES;
-use crate::multicore::Worker;
-use crate::multiexp::{multiexp as cpu_multiexp, FullDensity};
-use crossbeam::thread;
+use super::{MultiexpDevice, MultiexpKernel, GPU_NVIDIA_DEVICES};
 use ff::{PrimeField, ScalarEngine};
-use futures::Future;
 use groupy::{CurveAffine, CurveProjective};
 use log::{error, info};
 use ocl::{Buffer, Device, MemFlags, ProQue};
 use paired::Engine;
-use std::sync::Arc;
 
 // NOTE: Please read `structs.rs` for an explanation for unsafe transmutes of this code!
 
@@ -280,28 +275,22 @@ where
     }
 }
 
-// A struct that containts several multiexp kernels for different devices
-pub struct MultiexpKernel<E>
-where
-    E: Engine,
-{
-    kernels: Vec<SingleMultiexpKernel<E>>,
-    _lock: locks::GPULock, // RFC 1857: struct fields are dropped in the same order as they are declared.
-}
-
 impl<E> MultiexpKernel<E>
 where
     E: Engine,
 {
     pub fn create() -> GPUResult<MultiexpKernel<E>> {
-        let lock = locks::GPULock::lock();
-
-        let kernels: Vec<_> = GPU_NVIDIA_DEVICES
-            .iter()
-            .map(|d| SingleMultiexpKernel::<E>::create(*d))
-            .filter(|res| res.is_ok())
-            .map(|res| res.unwrap())
-            .collect();
+        // Devices are always locked in the same order, which cannot deadlock.
+        // A device is only kept locked if a kernel could be created on it.
+        let mut locks = Vec::new();
+        let mut kernels = Vec::new();
+        for (i, d) in GPU_NVIDIA_DEVICES.iter().enumerate() {
+            let lock = locks::GPULock::lock(i)?;
+            if let Ok(kernel) = SingleMultiexpKernel::<E>::create(*d) {
+                locks.push(lock);
+                kernels.push(kernel);
+            }
+        }
         if kernels.is_empty() {
             return Err(GPUError::Simple("No working GPUs found!"));
         }
@@ -318,81 +307,36 @@ where
                 k.n
             );
         }
-        return Ok(MultiexpKernel::<E> {
-            kernels,
-            _lock: lock,
-        });
-    }
-
-    pub fn multiexp<G>(
-        &mut self,
-        pool: &Worker,
-        bases: Arc<Vec<G>>,
-        exps: Arc<Vec<<<G::Engine as ScalarEngine>::Fr as PrimeField>::Repr>>,
-        skip: usize,
-        n: usize,
-    ) -> GPUResult<<G as CurveAffine>::Projective>
-    where
-        G: CurveAffine,
-        <G as groupy::CurveAffine>::Engine: paired::Engine,
-    {
-        let num_devices = self.kernels.len();
-        // Bases are skipped by `self.1` elements, when converted from (Arc<Vec<G>>, usize) to Source
-        // https://github.com/zkcrypto/bellman/blob/10c5010fd9c2ca69442dc9775ea271e286e776d8/src/multiexp.rs#L38
-        let bases = &bases[skip..(skip + n)];
-        let exps = &exps[..n];
 
-        let cpu_n = ((n as f64) * get_cpu_utilization()) as usize;
-        let n = n - cpu_n;
-        let (cpu_bases, bases) = bases.split_at(cpu_n);
-        let (cpu_exps, exps) = exps.split_at(cpu_n);
-
-        let chunk_size = ((n as f64) / (num_devices as f64)).ceil() as usize;
-
-        match thread::scope(|s| -> Result<<G as CurveAffine>::Projective, GPUError> {
-            let mut acc = <G as CurveAffine>::Projective::zero();
-            let mut threads = Vec::new();
-            if n > 0 {
-                for ((bases, exps), kern) in bases
-                    .chunks(chunk_size)
-                    .zip(exps.chunks(chunk_size))
-                    .zip(self.kernels.iter_mut())
-                {
-                    threads.push(s.spawn(
-                        move |_| -> Result<<G as CurveAffine>::Projective, GPUError> {
-                            let mut acc = <G as CurveAffine>::Projective::zero();
-                            for (bases, exps) in bases.chunks(kern.n).zip(exps.chunks(kern.n)) {
-                                let result = kern.multiexp(bases, exps, bases.len())?;
-                                acc.add_assign(&result);
-                            }
-                            Ok(acc)
-                        },
-                    ));
-                }
-            }
-
-            let cpu_acc = cpu_multiexp(
-                &pool,
-                (Arc::new(cpu_bases.to_vec()), 0),
-                FullDensity,
-                Arc::new(cpu_exps.to_vec()),
-                &mut None,
-            );
+        let devices = kernels
+            .into_iter()
+            .map(|k| Box::new(k) as Box<dyn MultiexpDevice<E>>)
+            .collect();
+        Ok(MultiexpKernel::new(devices, get_cpu_utilization(), locks))
+    }
+}
 
-            let mut results = vec![];
-            for t in threads {
-                results.push(t.join());
-            }
-            for r in results {
-                acc.add_assign(&r??);
-            }
+impl<E> MultiexpDevice<E> for SingleMultiexpKernel<E>
+where
+    E: Engine,
+{
+    fn chunk_size(&self) -> usize {
+        self.n
+    }
 
-            acc.add_assign(&cpu_acc.wait().unwrap());
+    fn multiexp_g1(
+        &mut self,
+        bases: &[E::G1Affine],
+        exps: &[<E::Fr as PrimeField>::Repr],
+    ) -> GPUResult<E::G1> {
+        self.multiexp(bases, exps, bases.len())
+    }
 
-            Ok(acc)
-        }) {
-            Ok(res) => res,
-            Err(e) => Err(GPUError::from(e)),
-        }
+    fn multiexp_g2(
+        &mut self,
+        bases: &[E::G2Affine],
+        exps: &[<E::Fr as PrimeField>::Repr],
+    ) -> GPUResult<E::G2> {
+        self.multiexp(bases, exps, bases.len())
     }
 }
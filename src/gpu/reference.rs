@@ -0,0 +1,170 @@
+use super::error::{GPUError, GPUResult};
+use super::{ComputeBackend, FFTKernel, FftDevice, MultiexpDevice, MultiexpKernel};
+use crate::domain::{serial_fft, Scalar};
+use ff::PrimeField;
+use groupy::{CurveAffine, CurveProjective};
+use paired::Engine;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// A device computing in pure Rust on the CPU, which stands in for a GPU to
+/// exercise the code offloading work to devices. Like a GPU, it takes
+/// multiexps of at most `chunk_size` bases, and it can be made to fail.
+/// Clones of a device are the same device, sharing its counters.
+#[derive(Clone, Debug)]
+pub struct ReferenceDevice {
+    chunk_size: usize,
+    state: Arc<ReferenceState>,
+}
+
+#[derive(Debug)]
+struct ReferenceState {
+    calls: AtomicUsize,
+    failures: AtomicUsize,
+    fail_after: AtomicUsize,
+}
+
+impl ReferenceDevice {
+    pub fn new(chunk_size: usize) -> Self {
+        assert!(chunk_size > 0, "devices take at least one base");
+
+        ReferenceDevice {
+            chunk_size,
+            state: Arc::new(ReferenceState {
+                calls: AtomicUsize::new(0),
+                failures: AtomicUsize::new(0),
+                fail_after: AtomicUsize::new(std::usize::MAX),
+            }),
+        }
+    }
+
+    /// Makes every call to the device fail once `calls` calls were made.
+    pub fn fail_after(self, calls: usize) -> Self {
+        self.state.fail_after.store(calls, Ordering::SeqCst);
+        self
+    }
+
+    /// The number of FFTs and multiexps the device was asked for, including
+    /// the failed ones.
+    pub fn calls(&self) -> usize {
+        self.state.calls.load(Ordering::SeqCst)
+    }
+
+    pub fn failures(&self) -> usize {
+        self.state.failures.load(Ordering::SeqCst)
+    }
+
+    fn call(&self, bases: usize) -> GPUResult<()> {
+        let calls = self.state.calls.fetch_add(1, Ordering::SeqCst);
+        if calls >= self.state.fail_after.load(Ordering::SeqCst) {
+            self.state.failures.fetch_add(1, Ordering::SeqCst);
+            return Err(GPUError::Simple("Injected device failure!"));
+        }
+        if bases > self.chunk_size {
+            self.state.failures.fetch_add(1, Ordering::SeqCst);
+            return Err(GPUError::Simple("Chunk is too large for the device!"));
+        }
+
+        Ok(())
+    }
+}
+
+impl<E: Engine> FftDevice<E> for ReferenceDevice {
+    fn radix_fft(&mut self, a: &mut [E::Fr], omega: &E::Fr, log_n: u32) -> GPUResult<()> {
+        self.call(0)?;
+
+        // Safety: `Scalar` is a transparent wrapper of the field element
+        let a = unsafe { &mut *(a as *mut [E::Fr] as *mut [Scalar<E>]) };
+        serial_fft::<E, _>(a, omega, log_n);
+
+        Ok(())
+    }
+}
+
+impl<E: Engine> MultiexpDevice<E> for ReferenceDevice {
+    fn chunk_size(&self) -> usize {
+        self.chunk_size
+    }
+
+    fn multiexp_g1(
+        &mut self,
+        bases: &[E::G1Affine],
+        exps: &[<E::Fr as PrimeField>::Repr],
+    ) -> GPUResult<E::G1> {
+        self.call(bases.len())?;
+
+        Ok(naive_multiexp(bases, exps))
+    }
+
+    fn multiexp_g2(
+        &mut self,
+        bases: &[E::G2Affine],
+        exps: &[<E::Fr as PrimeField>::Repr],
+    ) -> GPUResult<E::G2> {
+        self.call(bases.len())?;
+
+        Ok(naive_multiexp(bases, exps))
+    }
+}
+
+fn naive_multiexp<G: CurveAffine>(
+    bases: &[G],
+    exps: &[<G::Scalar as PrimeField>::Repr],
+) -> G::Projective {
+    let mut acc = G::Projective::zero();
+    for (base, exp) in bases.iter().zip(exps.iter()) {
+        acc.add_assign(&base.mul(*exp));
+    }
+
+    acc
+}
+
+/// A backend of reference devices, which lets the prover's GPU code paths
+/// run without a GPU. FFTs run on the first device, and multiexps are split
+/// between all of them. No lock file is taken.
+#[derive(Clone, Debug)]
+pub struct ReferenceBackend {
+    pub devices: Vec<ReferenceDevice>,
+    /// The proportion of every multiexp left to the CPU.
+    pub cpu_utilization: f64,
+}
+
+impl ReferenceBackend {
+    pub fn new(devices: Vec<ReferenceDevice>) -> Self {
+        ReferenceBackend {
+            devices,
+            cpu_utilization: 0f64,
+        }
+    }
+
+    pub fn with_cpu_utilization(mut self, cpu_utilization: f64) -> Self {
+        self.cpu_utilization = cpu_utilization;
+        self
+    }
+}
+
+impl<E: Engine> ComputeBackend<E> for ReferenceBackend {
+    fn has_devices(&self) -> bool {
+        !self.devices.is_empty()
+    }
+
+    fn create_fft_kernel(&self, _: u32) -> GPUResult<FFTKernel<E>> {
+        match self.devices.first() {
+            Some(device) => Ok(FFTKernel::new(Box::new(device.clone()), vec![])),
+            None => Err(GPUError::Simple("No working GPUs found!")),
+        }
+    }
+
+    fn create_multiexp_kernel(&self) -> GPUResult<MultiexpKernel<E>> {
+        if self.devices.is_empty() {
+            return Err(GPUError::Simple("No working GPUs found!"));
+        }
+
+        let devices = self
+            .devices
+            .iter()
+            .map(|device| Box::new(device.clone()) as Box<dyn MultiexpDevice<E>>)
+            .collect();
+        Ok(MultiexpKernel::new(devices, self.cpu_utilization, vec![]))
+    }
+}
@@ -0,0 +1,178 @@
+use groupy::CurveProjective;
+
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use super::MemoryReport;
+use crate::multicore::CancellationToken;
+use crate::multiexp::MultiexpFuture;
+use crate::SynthesisError;
+
+/// The phases of creating a proof, each reported separately.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProverPhase {
+    /// Synthesizing the circuit into its assignment.
+    Synthesis,
+    /// Computing the coefficients of the H polynomial.
+    Fft,
+    /// The multiexp over the H query.
+    MultiexpH,
+    /// The multiexp over the L query.
+    MultiexpL,
+    /// The multiexps over the A query.
+    MultiexpA,
+    /// The multiexps over the B query in G1.
+    MultiexpBG1,
+    /// The multiexps over the B query in G2.
+    MultiexpBG2,
+}
+
+type ProgressCallback = dyn Fn(ProverPhase, usize, usize) + Send + Sync;
+
+/// Controls a running batch of proofs: it can be cancelled from another
+/// thread, and reports the progress of every phase.
+///
+/// Cancellation is checked between phases, while synthesizing and between
+/// chunks of every multiexp, upon which the prover returns
+/// `SynthesisError::Cancelled`.
+///
+/// With a memory budget, a batch is proven in parts small enough for their
+/// estimated memory to fit in it, and the prover returns
+/// `SynthesisError::MemoryBudgetExceeded` before starting if a single
+/// circuit does not fit.
+#[derive(Clone, Default)]
+pub struct ProverContext {
+    cancellation: CancellationToken,
+    progress: Option<Arc<ProgressCallback>>,
+    memory_budget: Option<usize>,
+    memory_report: Arc<Mutex<Option<MemoryReport>>>,
+}
+
+impl ProverContext {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Calls `progress` with a phase, the number of circuits of the batch
+    /// done with it and the size of the batch, whenever a circuit completes
+    /// a phase. It may be called from several threads at once.
+    pub fn with_progress<F>(mut self, progress: F) -> Self
+    where
+        F: Fn(ProverPhase, usize, usize) + Send + Sync + 'static,
+    {
+        self.progress = Some(Arc::new(progress));
+        self
+    }
+
+    /// Cancels the proofs when `cancellation` is cancelled.
+    pub fn with_cancellation(mut self, cancellation: CancellationToken) -> Self {
+        self.cancellation = cancellation;
+        self
+    }
+
+    /// Limits the memory, in bytes, proving is estimated to take. Sources
+    /// which do not know the shape of their circuit are proven one circuit
+    /// at a time.
+    pub fn with_memory_budget(mut self, budget: usize) -> Self {
+        self.memory_budget = Some(budget);
+        self
+    }
+
+    pub fn memory_budget(&self) -> Option<usize> {
+        self.memory_budget
+    }
+
+    /// Returns the memory taken by the last batch proven with this context,
+    /// or any of its clones, once it is done.
+    pub fn memory_report(&self) -> Option<MemoryReport> {
+        *self.memory_report.lock().unwrap()
+    }
+
+    pub fn cancellation(&self) -> &CancellationToken {
+        &self.cancellation
+    }
+
+    pub fn cancel(&self) {
+        self.cancellation.cancel();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancellation.is_cancelled()
+    }
+
+    pub(super) fn check(&self) -> Result<(), SynthesisError> {
+        self.cancellation.check()
+    }
+
+    pub(super) fn report(&self, phase: ProverPhase, done: usize, total: usize) {
+        if let Some(ref progress) = self.progress {
+            progress(phase, done, total);
+        }
+    }
+
+    pub(super) fn set_memory_report(&self, report: MemoryReport) {
+        *self.memory_report.lock().unwrap() = Some(report);
+    }
+}
+
+/// Reports a multiexp phase for the circuits of a part of a batch, as the
+/// multiexps of each circuit complete, in whatever order they do.
+pub(super) struct PhaseProgress {
+    context: ProverContext,
+    phase: ProverPhase,
+    /// The number of circuits done with the phase before the part.
+    offset: usize,
+    total: usize,
+    done: Arc<AtomicUsize>,
+}
+
+impl PhaseProgress {
+    pub(super) fn new(
+        context: &ProverContext,
+        phase: ProverPhase,
+        offset: usize,
+        total: usize,
+    ) -> Self {
+        PhaseProgress {
+            context: context.clone(),
+            phase,
+            offset,
+            total,
+            done: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Reports one more circuit done once all of its `multiexps` for the
+    /// phase succeed.
+    pub(super) fn track<G: CurveProjective>(&self, multiexps: &[&MultiexpFuture<G>]) {
+        if self.context.progress.is_none() {
+            return;
+        }
+
+        let remaining = Arc::new(AtomicUsize::new(multiexps.len()));
+        for multiexp in multiexps {
+            let remaining = remaining.clone();
+            let context = self.context.clone();
+            let done = self.done.clone();
+            let (phase, offset, total) = (self.phase, self.offset, self.total);
+
+            multiexp.on_success(move || {
+                if remaining.fetch_sub(1, Ordering::SeqCst) == 1 {
+                    let done = done.fetch_add(1, Ordering::SeqCst) + 1;
+                    context.report(phase, offset + done, total);
+                }
+            });
+        }
+    }
+}
+
+impl fmt::Debug for ProverContext {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ProverContext")
+            .field("cancellation", &self.cancellation)
+            .field("progress", &self.progress.is_some())
+            .field("memory_budget", &self.memory_budget)
+            .finish()
+    }
+}
@@ -1,6 +1,8 @@
 use super::{create_proof_batch_priority, create_random_proof_batch_priority};
 use super::{ParameterSource, Proof};
+use crate::multicore::{Worker, WorkerFuture};
 use crate::{Circuit, SynthesisError};
+use ff::Field;
 use paired::Engine;
 use rand_core::RngCore;
 
@@ -14,8 +16,14 @@ where
     E: Engine,
     C: Circuit<E> + Send,
 {
-    let proofs =
-        create_proof_batch_priority::<E, C, P>(vec![circuit], params, vec![r], vec![s], false)?;
+    let proofs = create_proof_batch_priority::<E, C, P>(
+        &Worker::new(),
+        vec![circuit],
+        params,
+        vec![r],
+        vec![s],
+        false,
+    )?;
     Ok(proofs.into_iter().next().unwrap())
 }
 
@@ -29,8 +37,13 @@ where
     C: Circuit<E> + Send,
     R: RngCore,
 {
-    let proofs =
-        create_random_proof_batch_priority::<E, C, R, P>(vec![circuit], params, rng, false)?;
+    let proofs = create_random_proof_batch_priority::<E, C, R, P>(
+        &Worker::new(),
+        vec![circuit],
+        params,
+        rng,
+        false,
+    )?;
     Ok(proofs.into_iter().next().unwrap())
 }
 
@@ -44,7 +57,7 @@ where
     E: Engine,
     C: Circuit<E> + Send,
 {
-    create_proof_batch_priority::<E, C, P>(circuits, params, r, s, false)
+    create_proof_batch_priority::<E, C, P>(&Worker::new(), circuits, params, r, s, false)
 }
 
 pub fn create_random_proof_batch<E, C, R, P: ParameterSource<E>>(
@@ -57,7 +70,7 @@ where
     C: Circuit<E> + Send,
     R: RngCore,
 {
-    create_random_proof_batch_priority::<E, C, R, P>(circuits, params, rng, false)
+    create_random_proof_batch_priority::<E, C, R, P>(&Worker::new(), circuits, params, rng, false)
 }
 
 pub fn create_proof_in_priority<E, C, P: ParameterSource<E>>(
@@ -70,8 +83,14 @@ where
     E: Engine,
     C: Circuit<E> + Send,
 {
-    let proofs =
-        create_proof_batch_priority::<E, C, P>(vec![circuit], params, vec![r], vec![s], true)?;
+    let proofs = create_proof_batch_priority::<E, C, P>(
+        &Worker::new(),
+        vec![circuit],
+        params,
+        vec![r],
+        vec![s],
+        true,
+    )?;
     Ok(proofs.into_iter().next().unwrap())
 }
 
@@ -85,8 +104,13 @@ where
     C: Circuit<E> + Send,
     R: RngCore,
 {
-    let proofs =
-        create_random_proof_batch_priority::<E, C, R, P>(vec![circuit], params, rng, true)?;
+    let proofs = create_random_proof_batch_priority::<E, C, R, P>(
+        &Worker::new(),
+        vec![circuit],
+        params,
+        rng,
+        true,
+    )?;
     Ok(proofs.into_iter().next().unwrap())
 }
 
@@ -100,7 +124,7 @@ where
     E: Engine,
     C: Circuit<E> + Send,
 {
-    create_proof_batch_priority::<E, C, P>(circuits, params, r, s, true)
+    create_proof_batch_priority::<E, C, P>(&Worker::new(), circuits, params, r, s, true)
 }
 
 pub fn create_random_proof_batch_in_priority<E, C, R, P: ParameterSource<E>>(
@@ -113,5 +137,60 @@ where
     C: Circuit<E> + Send,
     R: RngCore,
 {
-    create_random_proof_batch_priority::<E, C, R, P>(circuits, params, rng, true)
+    create_random_proof_batch_priority::<E, C, R, P>(&Worker::new(), circuits, params, rng, true)
+}
+
+/// Creates a proof like `create_random_proof`, returning a future which
+/// does not block the thread polling it. The synthesis, FFTs and multiexps
+/// run on the pool of `worker`, driven through `Worker::compute_on_thread`
+/// by one of the few driver threads of the pool, so only a bounded number of
+/// proofs are created at a time and the others are queued. Parameters can be
+/// owned with an `Arc`.
+pub fn create_random_proof_async<E, C, R, P>(
+    worker: &Worker,
+    circuit: C,
+    params: P,
+    rng: &mut R,
+) -> WorkerFuture<Proof<E>, SynthesisError>
+where
+    E: Engine,
+    C: Circuit<E> + Send + 'static,
+    R: RngCore,
+    P: ParameterSource<E> + Send + 'static,
+{
+    let r = E::Fr::random(rng);
+    let s = E::Fr::random(rng);
+
+    let prover = worker.clone();
+    worker.compute_on_thread(move || {
+        let proofs = create_proof_batch_priority::<E, C, P>(
+            &prover,
+            vec![circuit],
+            params,
+            vec![r],
+            vec![s],
+            false,
+        )?;
+        Ok(proofs.into_iter().next().unwrap())
+    })
+}
+
+/// Creates a batch of proofs like `create_proof_batch`, returning a future
+/// like `create_random_proof_async`, driven by a driver thread of the pool.
+pub fn create_proof_batch_async<E, C, P>(
+    worker: &Worker,
+    circuits: Vec<C>,
+    params: P,
+    r: Vec<E::Fr>,
+    s: Vec<E::Fr>,
+) -> WorkerFuture<Vec<Proof<E>>, SynthesisError>
+where
+    E: Engine,
+    C: Circuit<E> + Send + 'static,
+    P: ParameterSource<E> + Send + 'static,
+{
+    let prover = worker.clone();
+    worker.compute_on_thread(move || {
+        create_proof_batch_priority::<E, C, P>(&prover, circuits, params, r, s, false)
+    })
 }
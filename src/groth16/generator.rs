@@ -1,12 +1,16 @@
 use rand_core::RngCore;
 
+use std::cmp;
+use std::io::{self, Read, Seek, SeekFrom, Write};
 use std::sync::Arc;
 
+use byteorder::{BigEndian, WriteBytesExt};
 use ff::{Field, PrimeField};
 use groupy::{CurveAffine, CurveProjective, Wnaf};
 use paired::Engine;
 
-use super::{Parameters, VerifyingKey};
+use super::header::{engine_id, DigestReader, DigestWriter};
+use super::{ParameterHeader, Parameters, SectionHeader, VerifyingKey, PARAMETERS_VERSION};
 
 use crate::{Circuit, ConstraintSystem, Index, LinearCombination, SynthesisError, Variable};
 
@@ -33,7 +37,17 @@ where
     let delta = E::Fr::random(rng);
     let tau = E::Fr::random(rng);
 
-    generate_parameters::<E, C>(circuit, g1, g2, alpha, beta, gamma, delta, tau)
+    generate_parameters::<E, C>(
+        &Worker::new(),
+        circuit,
+        g1,
+        g2,
+        alpha,
+        beta,
+        gamma,
+        delta,
+        tau,
+    )
 }
 
 /// This is our assembly structure that we'll use to synthesize the
@@ -152,17 +166,8 @@ impl<E: Engine> ConstraintSystem<E> for KeypairAssembly<E> {
     }
 }
 
-/// Create parameters for a circuit, given some toxic waste.
-pub fn generate_parameters<E, C>(
-    circuit: C,
-    g1: E::G1,
-    g2: E::G2,
-    alpha: E::Fr,
-    beta: E::Fr,
-    gamma: E::Fr,
-    delta: E::Fr,
-    tau: E::Fr,
-) -> Result<Parameters<E>, SynthesisError>
+/// Synthesizes the circuit into a QAP.
+fn synthesize_assembly<E, C>(circuit: C) -> Result<KeypairAssembly<E>, SynthesisError>
 where
     E: Engine,
     C: Circuit<E>,
@@ -191,6 +196,41 @@ where
         assembly.enforce(|| "", |lc| lc + Variable(Index::Input(i)), |lc| lc, |lc| lc);
     }
 
+    Ok(assembly)
+}
+
+/// Evaluates a QAP polynomial at tau, given the Lagrange coefficients for tau.
+fn eval_at_tau<E: Engine>(powers_of_tau: &[Scalar<E>], p: &[(E::Fr, usize)]) -> E::Fr {
+    let mut acc = E::Fr::zero();
+
+    for &(ref coeff, index) in p {
+        let mut n = powers_of_tau[index].0;
+        n.mul_assign(coeff);
+        acc.add_assign(&n);
+    }
+
+    acc
+}
+
+/// Create parameters for a circuit, given some toxic waste. All CPU work runs
+/// on the threads of `worker`.
+pub fn generate_parameters<E, C>(
+    worker: &Worker,
+    circuit: C,
+    g1: E::G1,
+    g2: E::G2,
+    alpha: E::Fr,
+    beta: E::Fr,
+    gamma: E::Fr,
+    delta: E::Fr,
+    tau: E::Fr,
+) -> Result<Parameters<E>, SynthesisError>
+where
+    E: Engine,
+    C: Circuit<E>,
+{
+    let assembly = synthesize_assembly::<E, C>(circuit)?;
+
     // Create bases for blind evaluation of polynomials at tau
     let powers_of_tau = vec![Scalar::<E>(E::Fr::zero()); assembly.num_constraints];
     let mut powers_of_tau = EvaluationDomain::from_coeffs(powers_of_tau)?;
@@ -218,8 +258,6 @@ where
     let gamma_inverse = gamma.inverse().ok_or(SynthesisError::UnexpectedIdentity)?;
     let delta_inverse = delta.inverse().ok_or(SynthesisError::UnexpectedIdentity)?;
 
-    let worker = Worker::new();
-
     let mut h = vec![E::G1::zero(); powers_of_tau.as_ref().len() - 1];
     {
         // Compute powers of tau
@@ -270,7 +308,7 @@ where
     }
 
     // Use inverse FFT to convert powers of tau to Lagrange coefficients
-    powers_of_tau.ifft(&worker, &mut None)?;
+    powers_of_tau.ifft(worker, &mut None)?;
     let powers_of_tau = powers_of_tau.into_coeffs();
 
     let mut a = vec![E::G1::zero(); assembly.num_inputs + assembly.num_aux];
@@ -340,21 +378,6 @@ where
                         .zip(bt.iter())
                         .zip(ct.iter())
                     {
-                        fn eval_at_tau<E: Engine>(
-                            powers_of_tau: &[Scalar<E>],
-                            p: &[(E::Fr, usize)],
-                        ) -> E::Fr {
-                            let mut acc = E::Fr::zero();
-
-                            for &(ref coeff, index) in p {
-                                let mut n = powers_of_tau[index].0;
-                                n.mul_assign(coeff);
-                                acc.add_assign(&n);
-                            }
-
-                            acc
-                        }
-
                         // Evaluate QAP polynomials at tau
                         let mut at = eval_at_tau(powers_of_tau, at);
                         let mut bt = eval_at_tau(powers_of_tau, bt);
@@ -408,7 +431,7 @@ where
         &gamma_inverse,
         &alpha,
         &beta,
-        &worker,
+        worker,
     );
 
     // Evaluate for auxiliary variables.
@@ -426,7 +449,7 @@ where
         &delta_inverse,
         &alpha,
         &beta,
-        &worker,
+        worker,
     );
 
     // Don't allow any elements be unconstrained, so that
@@ -476,3 +499,378 @@ where
         ),
     })
 }
+
+/// The number of bases `generate_parameters_to_writer` computes at a time.
+const PARAMETER_CHUNK_SIZE: usize = 1 << 20;
+
+/// Create parameters for a circuit like `generate_parameters`, but write them
+/// to `writer` in the layout of `Parameters::write_with_header` while they
+/// are computed, a chunk of bases at a time, so that they never are all in
+/// memory. The header is written last, once the digests of the queries are
+/// known, which is why `writer` must be seekable. It must be readable too, as
+/// the A and B queries are digested by reading them back once their lengths
+/// are patched in. A file written this way can
+/// be opened with `build_mapped_parameters`. Returns the verifying key.
+#[allow(clippy::too_many_arguments)]
+pub fn generate_parameters_to_writer<E, C, W>(
+    worker: &Worker,
+    circuit: C,
+    g1: E::G1,
+    g2: E::G2,
+    alpha: E::Fr,
+    beta: E::Fr,
+    gamma: E::Fr,
+    delta: E::Fr,
+    tau: E::Fr,
+    writer: W,
+) -> Result<VerifyingKey<E>, SynthesisError>
+where
+    E: Engine,
+    C: Circuit<E>,
+    W: Read + Write + Seek,
+{
+    generate_parameters_in_chunks(
+        worker,
+        circuit,
+        g1,
+        g2,
+        alpha,
+        beta,
+        gamma,
+        delta,
+        tau,
+        PARAMETER_CHUNK_SIZE,
+        writer,
+    )
+}
+
+/// Writes the parameters computing `chunk_size` bases at a time.
+#[allow(clippy::too_many_arguments)]
+pub(super) fn generate_parameters_in_chunks<E, C, W>(
+    worker: &Worker,
+    circuit: C,
+    g1: E::G1,
+    g2: E::G2,
+    alpha: E::Fr,
+    beta: E::Fr,
+    gamma: E::Fr,
+    delta: E::Fr,
+    tau: E::Fr,
+    chunk_size: usize,
+    mut writer: W,
+) -> Result<VerifyingKey<E>, SynthesisError>
+where
+    E: Engine,
+    C: Circuit<E>,
+    W: Read + Write + Seek,
+{
+    assert!(chunk_size > 0);
+
+    let assembly = synthesize_assembly::<E, C>(circuit)?;
+
+    let mut lagrange_coeffs =
+        EvaluationDomain::from_coeffs(vec![Scalar::<E>(E::Fr::zero()); assembly.num_constraints])?;
+    let h_len = lagrange_coeffs.as_ref().len() - 1;
+
+    // Compute the same window tables as `generate_parameters`
+    let mut g1_wnaf = Wnaf::new();
+    let g1_wnaf = g1_wnaf.base(g1, h_len + 3 * (assembly.num_inputs + assembly.num_aux));
+    let mut g2_wnaf = Wnaf::new();
+    let g2_wnaf = g2_wnaf.base(g2, assembly.num_inputs + assembly.num_aux);
+
+    let gamma_inverse = gamma.inverse().ok_or(SynthesisError::UnexpectedIdentity)?;
+    let delta_inverse = delta.inverse().ok_or(SynthesisError::UnexpectedIdentity)?;
+
+    // coeff = t(x) / delta
+    let mut h_coeff = lagrange_coeffs.z(&tau);
+    h_coeff.mul_assign(&delta_inverse);
+
+    // Use inverse FFT to convert powers of tau to Lagrange coefficients
+    {
+        let powers_of_tau = lagrange_coeffs.as_mut();
+        worker.scope(powers_of_tau.len(), |scope, chunk| {
+            for (i, powers_of_tau) in powers_of_tau.chunks_mut(chunk).enumerate() {
+                scope.spawn(move |_scope| {
+                    let mut current_tau_power = tau.pow(&[(i * chunk) as u64]);
+
+                    for p in powers_of_tau {
+                        p.0 = current_tau_power;
+                        current_tau_power.mul_assign(&tau);
+                    }
+                });
+            }
+        });
+    }
+    lagrange_coeffs.ifft(worker, &mut None)?;
+    let lagrange_coeffs = lagrange_coeffs.into_coeffs();
+
+    // The IC query is part of the verifying key, which comes first
+    let ic = exponentiate(
+        worker,
+        &g1_wnaf,
+        &evaluate_ext(
+            worker,
+            &lagrange_coeffs,
+            &assembly.at_inputs,
+            &assembly.bt_inputs,
+            &assembly.ct_inputs,
+            &gamma_inverse,
+            &alpha,
+            &beta,
+        ),
+    );
+
+    let g1 = g1.into_affine();
+    let g2 = g2.into_affine();
+
+    let vk = VerifyingKey::<E> {
+        alpha_g1: g1.mul(alpha).into_affine(),
+        beta_g1: g1.mul(beta).into_affine(),
+        beta_g2: g2.mul(beta).into_affine(),
+        gamma_g2: g2.mul(gamma).into_affine(),
+        delta_g1: g1.mul(delta).into_affine(),
+        delta_g2: g2.mul(delta).into_affine(),
+        ic: ic.into_iter().map(|e| e.into_affine()).collect(),
+    };
+
+    // Leave room for the header, and digest every section as it is written
+    let header_start = writer.seek(SeekFrom::Current(0))?;
+    writer.write_all(&[0u8; ParameterHeader::SIZE])?;
+    let mut writer = DigestWriter::new(writer);
+
+    vk.write(&mut writer)?;
+    let circuit_digest = writer.finish_section();
+
+    // H query: g1^{(tau^i * t(tau)) / delta}
+    writer.write_u32::<BigEndian>(h_len as u32)?;
+    for start in (0..h_len).step_by(chunk_size) {
+        let mut exps = vec![E::Fr::zero(); cmp::min(chunk_size, h_len - start)];
+
+        worker.scope(exps.len(), |scope, chunk| {
+            for (i, exps) in exps.chunks_mut(chunk).enumerate() {
+                scope.spawn(move |_scope| {
+                    let mut current_tau_power = tau.pow(&[(start + i * chunk) as u64]);
+
+                    for exp in exps {
+                        *exp = current_tau_power;
+                        exp.mul_assign(&h_coeff);
+                        current_tau_power.mul_assign(&tau);
+                    }
+                });
+            }
+        });
+
+        write_points(&mut writer, &exponentiate(worker, &g1_wnaf, &exps))?;
+    }
+    let h = SectionHeader {
+        len: h_len as u32,
+        digest: writer.finish_section(),
+    };
+
+    // L query
+    writer.write_u32::<BigEndian>(assembly.num_aux as u32)?;
+    for ((at, bt), ct) in assembly
+        .at_aux
+        .chunks(chunk_size)
+        .zip(assembly.bt_aux.chunks(chunk_size))
+        .zip(assembly.ct_aux.chunks(chunk_size))
+    {
+        let exps = evaluate_ext(
+            worker,
+            &lagrange_coeffs,
+            at,
+            bt,
+            ct,
+            &delta_inverse,
+            &alpha,
+            &beta,
+        );
+
+        // Don't allow any elements be unconstrained, so that
+        // the L query is always fully dense.
+        if exps.iter().any(|e| e.is_zero()) {
+            return Err(SynthesisError::UnconstrainedVariable);
+        }
+
+        write_points(&mut writer, &exponentiate(worker, &g1_wnaf, &exps))?;
+    }
+    let l = SectionHeader {
+        len: assembly.num_aux as u32,
+        digest: writer.finish_section(),
+    };
+
+    // Points at infinity are filtered away from A/B queries, so the length
+    // of each query is only known once its points are written
+    let at_chunks = || {
+        assembly
+            .at_inputs
+            .chunks(chunk_size)
+            .chain(assembly.at_aux.chunks(chunk_size))
+    };
+    let bt_chunks = || {
+        assembly
+            .bt_inputs
+            .chunks(chunk_size)
+            .chain(assembly.bt_aux.chunks(chunk_size))
+    };
+    let nonzero = |polys: &[Vec<(E::Fr, usize)>]| {
+        evaluate(worker, &lagrange_coeffs, polys)
+            .into_iter()
+            .filter(|e| !e.is_zero())
+            .collect::<Vec<_>>()
+    };
+
+    let mut writer = writer.into_inner();
+
+    // A query
+    let a = write_filtered_section(
+        &mut writer,
+        at_chunks().map(|at| exponentiate(worker, &g1_wnaf, &nonzero(at))),
+    )?;
+
+    // B query in G1 and G2
+    let b_g1 = write_filtered_section(
+        &mut writer,
+        bt_chunks().map(|bt| exponentiate(worker, &g1_wnaf, &nonzero(bt))),
+    )?;
+    let b_g2 = write_filtered_section(
+        &mut writer,
+        bt_chunks().map(|bt| exponentiate(worker, &g2_wnaf, &nonzero(bt))),
+    )?;
+
+    let header = ParameterHeader {
+        version: PARAMETERS_VERSION,
+        engine_id: engine_id::<E>(),
+        circuit_digest,
+        ic_len: vk.ic.len() as u32,
+        h,
+        l,
+        a,
+        b_g1,
+        b_g2,
+    };
+
+    let end = writer.seek(SeekFrom::Current(0))?;
+    writer.seek(SeekFrom::Start(header_start))?;
+    header.write(&mut writer)?;
+    writer.seek(SeekFrom::Start(end))?;
+
+    Ok(vk)
+}
+
+/// Evaluates QAP polynomials at tau, given the Lagrange coefficients for tau.
+fn evaluate<E: Engine>(
+    worker: &Worker,
+    lagrange_coeffs: &[Scalar<E>],
+    polys: &[Vec<(E::Fr, usize)>],
+) -> Vec<E::Fr> {
+    let mut evals = vec![E::Fr::zero(); polys.len()];
+
+    worker.scope(evals.len(), |scope, chunk| {
+        for (evals, polys) in evals.chunks_mut(chunk).zip(polys.chunks(chunk)) {
+            scope.spawn(move |_scope| {
+                for (eval, p) in evals.iter_mut().zip(polys.iter()) {
+                    *eval = eval_at_tau(lagrange_coeffs, p);
+                }
+            });
+        }
+    });
+
+    evals
+}
+
+/// Evaluates the exponents (beta * A(tau) + alpha * B(tau) + C(tau)) * inv
+/// of the IC or L query.
+#[allow(clippy::too_many_arguments)]
+fn evaluate_ext<E: Engine>(
+    worker: &Worker,
+    lagrange_coeffs: &[Scalar<E>],
+    at: &[Vec<(E::Fr, usize)>],
+    bt: &[Vec<(E::Fr, usize)>],
+    ct: &[Vec<(E::Fr, usize)>],
+    inv: &E::Fr,
+    alpha: &E::Fr,
+    beta: &E::Fr,
+) -> Vec<E::Fr> {
+    let mut exps = evaluate(worker, lagrange_coeffs, ct);
+    let at = evaluate(worker, lagrange_coeffs, at);
+    let bt = evaluate(worker, lagrange_coeffs, bt);
+
+    for ((e, mut at), mut bt) in exps.iter_mut().zip(at).zip(bt) {
+        at.mul_assign(beta);
+        bt.mul_assign(alpha);
+
+        at.add_assign(&bt);
+        e.add_assign(&at);
+        e.mul_assign(inv);
+    }
+
+    exps
+}
+
+/// Computes the base of the window table to the power of every exponent,
+/// in normalized projective form.
+fn exponentiate<G: CurveProjective>(
+    worker: &Worker,
+    wnaf: &Wnaf<usize, &[G], &mut Vec<i64>>,
+    exps: &[G::Scalar],
+) -> Vec<G> {
+    let mut points = vec![G::zero(); exps.len()];
+
+    worker.scope(points.len(), |scope, chunk| {
+        for (points, exps) in points.chunks_mut(chunk).zip(exps.chunks(chunk)) {
+            let mut wnaf = wnaf.shared();
+
+            scope.spawn(move |_scope| {
+                for (point, exp) in points.iter_mut().zip(exps.iter()) {
+                    *point = wnaf.scalar(exp.into_repr());
+                }
+
+                // Batch normalize
+                G::batch_normalization(points);
+            });
+        }
+    });
+
+    points
+}
+
+/// Writes a query of points whose length is only known once they all are,
+/// patching the length in afterwards. The section is then read back to
+/// digest it, length first.
+fn write_filtered_section<W, G, I>(writer: &mut W, chunks: I) -> io::Result<SectionHeader>
+where
+    W: Read + Write + Seek,
+    G: CurveProjective,
+    I: Iterator<Item = Vec<G>>,
+{
+    let start = writer.seek(SeekFrom::Current(0))?;
+    writer.write_u32::<BigEndian>(0)?;
+
+    let mut len = 0;
+    for points in chunks {
+        write_points(writer, &points)?;
+        len += points.len();
+    }
+
+    let end = writer.seek(SeekFrom::Current(0))?;
+    writer.seek(SeekFrom::Start(start))?;
+    writer.write_u32::<BigEndian>(len as u32)?;
+    writer.seek(SeekFrom::Start(start))?;
+
+    let mut reader = DigestReader::new(Read::take(&mut *writer, end - start));
+    io::copy(&mut reader, &mut io::sink())?;
+
+    Ok(SectionHeader {
+        len: len as u32,
+        digest: reader.finish_section(),
+    })
+}
+
+fn write_points<G: CurveProjective, W: Write>(writer: &mut W, points: &[G]) -> io::Result<()> {
+    for point in points {
+        writer.write_all(point.into_affine().into_uncompressed().as_ref())?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,332 @@
+use blake2s_simd::{Params as Blake2sParams, State as Blake2sState};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use ff::{PrimeField, PrimeFieldRepr};
+use groupy::CurveAffine;
+use paired::Engine;
+
+use std::io::{self, Read, Write};
+use std::mem;
+
+use super::Parameters;
+
+/// The bytes parameter files with a header start with. The high bit of the
+/// first byte is the compression flag of an encoded point, which is never
+/// set in the uncompressed verifying key legacy files start with.
+pub const PARAMETERS_MAGIC: [u8; 8] = *b"\x89BELLPRM";
+
+/// The latest version of the parameter file format.
+pub const PARAMETERS_VERSION: u32 = 1;
+
+const DIGEST_LEN: usize = 32;
+
+pub type Digest = [u8; DIGEST_LEN];
+
+/// The length and digest of a query of a parameter file. The digest covers
+/// the bytes of the query as written, including its length.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SectionHeader {
+    pub len: u32,
+    pub digest: Digest,
+}
+
+/// The header of a parameter file, which precedes the layout of files
+/// without one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ParameterHeader {
+    pub version: u32,
+    /// Identifies the engine, as the digest of its generators and scalar
+    /// field modulus.
+    pub engine_id: Digest,
+    /// Identifies the circuit and setup, as the digest of the verifying
+    /// key as written.
+    pub circuit_digest: Digest,
+    pub ic_len: u32,
+    pub h: SectionHeader,
+    pub l: SectionHeader,
+    pub a: SectionHeader,
+    pub b_g1: SectionHeader,
+    pub b_g2: SectionHeader,
+}
+
+impl ParameterHeader {
+    /// The size of a written header, in bytes.
+    pub const SIZE: usize = 8 + 4 + 2 * DIGEST_LEN + 4 + 5 * (4 + DIGEST_LEN);
+
+    pub fn new<E: Engine>(params: &Parameters<E>) -> Self {
+        ParameterHeader {
+            version: PARAMETERS_VERSION,
+            engine_id: engine_id::<E>(),
+            circuit_digest: digest_written(|w| params.vk.write(w)),
+            ic_len: params.vk.ic.len() as u32,
+            h: query_section(&params.h),
+            l: query_section(&params.l),
+            a: query_section(&params.a),
+            b_g1: query_section(&params.b_g1),
+            b_g2: query_section(&params.b_g2),
+        }
+    }
+
+    pub fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_all(&PARAMETERS_MAGIC)?;
+        writer.write_u32::<BigEndian>(self.version)?;
+        writer.write_all(&self.engine_id)?;
+        writer.write_all(&self.circuit_digest)?;
+        writer.write_u32::<BigEndian>(self.ic_len)?;
+        for section in &[self.h, self.l, self.a, self.b_g1, self.b_g2] {
+            writer.write_u32::<BigEndian>(section.len)?;
+            writer.write_all(&section.digest)?;
+        }
+
+        Ok(())
+    }
+
+    /// Reads the header of a parameter file, or returns `None` after
+    /// reading the first bytes of a file without one, which are returned.
+    pub fn read<R: Read>(mut reader: R) -> io::Result<(Option<Self>, [u8; 8])> {
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic)?;
+        if magic != PARAMETERS_MAGIC {
+            return Ok((None, magic));
+        }
+
+        let version = reader.read_u32::<BigEndian>()?;
+        if version == 0 || version > PARAMETERS_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unsupported parameter file version {}", version),
+            ));
+        }
+
+        let mut read_digest = || -> io::Result<Digest> {
+            let mut digest = [0u8; DIGEST_LEN];
+            reader.read_exact(&mut digest)?;
+            Ok(digest)
+        };
+        let engine_id = read_digest()?;
+        let circuit_digest = read_digest()?;
+
+        let ic_len = reader.read_u32::<BigEndian>()?;
+        let mut read_section = || -> io::Result<SectionHeader> {
+            let len = reader.read_u32::<BigEndian>()?;
+            let mut digest = [0u8; DIGEST_LEN];
+            reader.read_exact(&mut digest)?;
+
+            Ok(SectionHeader { len, digest })
+        };
+
+        Ok((
+            Some(ParameterHeader {
+                version,
+                engine_id,
+                circuit_digest,
+                ic_len,
+                h: read_section()?,
+                l: read_section()?,
+                a: read_section()?,
+                b_g1: read_section()?,
+                b_g2: read_section()?,
+            }),
+            magic,
+        ))
+    }
+
+    /// Checks that the file is for the engine `E`.
+    pub fn check_engine<E: Engine>(&self) -> io::Result<()> {
+        if self.engine_id != engine_id::<E>() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "parameter file is for another engine",
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Returns the size of the file this is the header of.
+    pub fn file_len<E: Engine>(&self) -> u64 {
+        let g1_len = mem::size_of::<<E::G1Affine as CurveAffine>::Uncompressed>() as u64;
+        let g2_len = mem::size_of::<<E::G2Affine as CurveAffine>::Uncompressed>() as u64;
+
+        let vk_len = 3 * g1_len + 3 * g2_len + 4 + u64::from(self.ic_len) * g1_len;
+        let g1_queries = u64::from(self.h.len)
+            + u64::from(self.l.len)
+            + u64::from(self.a.len)
+            + u64::from(self.b_g1.len);
+
+        Self::SIZE as u64 + vk_len + 5 * 4 + g1_queries * g1_len + u64::from(self.b_g2.len) * g2_len
+    }
+}
+
+/// Reads the header of a mapped parameter file, if it has one, checking the
+/// engine and that the file is as long as the header says.
+pub(super) fn read_mapped_header<E: Engine>(bytes: &[u8]) -> io::Result<Option<ParameterHeader>> {
+    if bytes.len() < ParameterHeader::SIZE || bytes[..8] != PARAMETERS_MAGIC {
+        return Ok(None);
+    }
+
+    let header = match ParameterHeader::read(bytes)?.0 {
+        Some(header) => header,
+        None => return Ok(None),
+    };
+    header.check_engine::<E>()?;
+    if bytes.len() as u64 != header.file_len::<E>() {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "parameter file length does not match its header",
+        ));
+    }
+
+    Ok(Some(header))
+}
+
+/// Writes a query in the layout of `Parameters::write`.
+pub(super) fn write_query<G: CurveAffine, W: Write>(
+    writer: &mut W,
+    points: &[G],
+) -> io::Result<()> {
+    writer.write_u32::<BigEndian>(points.len() as u32)?;
+    for g in points {
+        writer.write_all(g.into_uncompressed().as_ref())?;
+    }
+
+    Ok(())
+}
+
+fn query_section<G: CurveAffine>(points: &[G]) -> SectionHeader {
+    SectionHeader {
+        len: points.len() as u32,
+        digest: digest_written(|w| write_query(w, points)),
+    }
+}
+
+/// Returns the digest of what `write` writes.
+pub(super) fn digest_written<F>(write: F) -> Digest
+where
+    F: FnOnce(&mut DigestWriter<io::Sink>) -> io::Result<()>,
+{
+    let mut writer = DigestWriter::new(io::sink());
+    write(&mut writer).expect("writing to a sink cannot fail");
+
+    writer.finish_section()
+}
+
+/// Checks the length of a query read from a file against its header.
+pub(super) fn check_len(expected: Option<&SectionHeader>, len: usize) -> io::Result<()> {
+    match expected {
+        Some(section) if section.len as usize != len => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "query length does not match the header",
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Checks the digest of bytes read from a file against its header.
+pub(super) fn check_digest(expected: Option<&Digest>, digest: &Digest) -> io::Result<()> {
+    match expected {
+        Some(expected) if expected != digest => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "parameter file does not match the digest in its header",
+        )),
+        _ => Ok(()),
+    }
+}
+
+pub(super) fn digest(bytes: &[u8]) -> Digest {
+    let mut state = new_state();
+    state.update(bytes);
+
+    finalize(&state)
+}
+
+fn new_state() -> Blake2sState {
+    Blake2sParams::new().hash_length(DIGEST_LEN).to_state()
+}
+
+fn finalize(state: &Blake2sState) -> Digest {
+    let mut digest = [0u8; DIGEST_LEN];
+    digest.copy_from_slice(state.finalize().as_bytes());
+
+    digest
+}
+
+pub(super) fn engine_id<E: Engine>() -> Digest {
+    let mut state = new_state();
+    state.update(E::G1Affine::one().into_uncompressed().as_ref());
+    state.update(E::G2Affine::one().into_uncompressed().as_ref());
+    E::Fr::char()
+        .write_be(&mut state)
+        .expect("writing to a hash cannot fail");
+
+    finalize(&state)
+}
+
+/// A writer digesting the bytes written through it.
+pub(super) struct DigestWriter<W> {
+    inner: W,
+    state: Blake2sState,
+}
+
+impl<W: Write> DigestWriter<W> {
+    pub(super) fn new(inner: W) -> Self {
+        DigestWriter {
+            inner,
+            state: new_state(),
+        }
+    }
+
+    /// Returns the digest of what was written since the last section.
+    pub(super) fn finish_section(&mut self) -> Digest {
+        let digest = finalize(&self.state);
+        self.state = new_state();
+
+        digest
+    }
+
+    pub(super) fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: Write> Write for DigestWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.state.update(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// A reader digesting the bytes read through it, a section at a time.
+pub(super) struct DigestReader<R> {
+    inner: R,
+    state: Blake2sState,
+}
+
+impl<R: Read> DigestReader<R> {
+    pub(super) fn new(inner: R) -> Self {
+        DigestReader {
+            inner,
+            state: new_state(),
+        }
+    }
+
+    /// Returns the digest of what was read since the last section.
+    pub(super) fn finish_section(&mut self) -> Digest {
+        let digest = finalize(&self.state);
+        self.state = new_state();
+
+        digest
+    }
+}
+
+impl<R: Read> Read for DigestReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.state.update(&buf[..n]);
+        Ok(n)
+    }
+}
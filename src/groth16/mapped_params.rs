@@ -1,18 +1,23 @@
 use groupy::{CurveAffine, EncodedPoint};
 use paired::Engine;
 
+use crate::multicore::Worker;
 use crate::SynthesisError;
 
 use memmap::{Mmap, MmapOptions};
 
-use std::fs::File;
+use std::any::Any;
+use std::collections::HashMap;
+use std::fs::{self, File};
 use std::io;
 use std::marker::PhantomData;
+use std::mem;
 use std::ops::Range;
-use std::path::PathBuf;
-use std::sync::Arc;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
 
-use super::{ParameterSource, VerifyingKey};
+use super::{CircuitShape, ParameterSource, VerifyingKey};
 
 #[derive(Clone)]
 pub struct MappedParameters<E: Engine> {
@@ -21,7 +26,8 @@ pub struct MappedParameters<E: Engine> {
     // be clever and keeping a persistent memory map around.  This is
     // a much safer way to go (as mmap life-times and consistency
     // guarantees are difficult), and the cost of the mappings should
-    // not outweigh the benefits of lazy-loading parameters.
+    // not outweigh the benefits of lazy-loading parameters. A
+    // persistent map is only kept when opting into the cache.
     pub param_file: PathBuf,
 
     // This is always loaded (i.e. not lazily loaded).
@@ -50,10 +56,316 @@ pub struct MappedParameters<E: Engine> {
     pub checked: bool,
 
     pub _e: PhantomData<E>,
+
+    // Decoded queries kept across proofs, shared by clones. Only used
+    // when opted into with `with_cache`.
+    pub(super) cache: Option<Arc<QueryCache>>,
+
+    // The pool queries are decoded on, shared by clones.
+    pub(super) worker: Worker,
 }
 
 unsafe impl<E: Engine> Sync for MappedParameters<E> {}
 
+/// How the cache of `MappedParameters` picks the queries to evict when
+/// caching another one would exceed its memory budget.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EvictionPolicy {
+    /// Evicts the queries used least recently first.
+    LeastRecentlyUsed,
+    /// Evicts the largest queries first, which frees the budget with the
+    /// fewest evictions.
+    LargestFirst,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CacheConfig {
+    /// The memory, in bytes, the decoded queries may take.
+    pub memory_budget: usize,
+    pub eviction: EvictionPolicy,
+}
+
+impl<E: Engine> MappedParameters<E> {
+    /// Keeps a long-lived memory map of the parameter file, and caches the
+    /// queries decoded (and checked, if `checked` is set) from it across
+    /// proofs. A query larger than the whole budget is never cached. The
+    /// cache is dropped whenever the size or modification time of the file
+    /// changes, and is shared by clones of the parameters.
+    pub fn with_cache(mut self, config: CacheConfig) -> Self {
+        self.cache = Some(Arc::new(QueryCache::new(config)));
+        self
+    }
+
+    /// Returns the memory, in bytes, taken by the cached queries. Evicted
+    /// queries still used by a proof are freed once it is done with them.
+    pub fn cached_bytes(&self) -> usize {
+        self.cache.as_ref().map_or(0, |cache| cache.used())
+    }
+
+    fn get_query<G, F>(&self, query: Query, decode: F) -> io::Result<Arc<Vec<G>>>
+    where
+        G: Send + Sync + 'static,
+        F: FnOnce(&Mmap) -> io::Result<Vec<G>>,
+    {
+        match self.cache {
+            Some(ref cache) => cache.get(&self.param_file, query, decode),
+            None => {
+                let params = File::open(self.param_file.clone())?;
+                // Safety: this operation is safe, because we are
+                // intentionally memory mapping this file.
+                let mmap = unsafe { MmapOptions::new().map(&params)? };
+
+                Ok(Arc::new(decode(&mmap)?))
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum Query {
+    H,
+    L,
+    A,
+    BG1,
+    BG2,
+}
+
+/// The size and modification time of the file a mapping was made of.
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct FileStamp {
+    len: u64,
+    modified: Option<SystemTime>,
+}
+
+impl FileStamp {
+    fn of(path: &Path) -> io::Result<Self> {
+        let metadata = fs::metadata(path)?;
+
+        Ok(FileStamp {
+            len: metadata.len(),
+            modified: metadata.modified().ok(),
+        })
+    }
+}
+
+struct CacheEntry {
+    bases: Arc<dyn Any + Send + Sync>,
+    size: usize,
+    last_used: u64,
+}
+
+#[derive(Default)]
+struct CacheState {
+    mapping: Option<(FileStamp, Arc<Mmap>)>,
+    entries: HashMap<Query, CacheEntry>,
+    used: usize,
+    clock: u64,
+}
+
+pub(super) struct QueryCache {
+    config: CacheConfig,
+    state: Mutex<CacheState>,
+}
+
+impl QueryCache {
+    fn new(config: CacheConfig) -> Self {
+        QueryCache {
+            config,
+            state: Mutex::new(CacheState::default()),
+        }
+    }
+
+    fn used(&self) -> usize {
+        self.state.lock().unwrap().used
+    }
+
+    fn get<G, F>(&self, path: &Path, query: Query, decode: F) -> io::Result<Arc<Vec<G>>>
+    where
+        G: Send + Sync + 'static,
+        F: FnOnce(&Mmap) -> io::Result<Vec<G>>,
+    {
+        let (stamp, mmap) = {
+            let mut state = self.state.lock().unwrap();
+            state.clock += 1;
+            let clock = state.clock;
+
+            // Drop everything decoded from a file that has since changed
+            let stamp = FileStamp::of(path)?;
+            if state.mapping.as_ref().map(|&(s, _)| s) != Some(stamp) {
+                *state = CacheState::default();
+
+                let params = File::open(path)?;
+                // Safety: this operation is safe, because we are
+                // intentionally memory mapping this file.
+                let mmap = unsafe { MmapOptions::new().map(&params)? };
+                state.mapping = Some((stamp, Arc::new(mmap)));
+            }
+
+            if let Some(entry) = state.entries.get_mut(&query) {
+                if let Ok(bases) = entry.bases.clone().downcast::<Vec<G>>() {
+                    entry.last_used = clock;
+                    return Ok(bases);
+                }
+            }
+
+            (stamp, state.mapping.as_ref().unwrap().1.clone())
+        };
+
+        // Decoding takes long, so other queries are served meanwhile
+        let bases = Arc::new(decode(&mmap)?);
+
+        let size = bases.len() * mem::size_of::<G>();
+        if size <= self.config.memory_budget {
+            let mut state = self.state.lock().unwrap();
+
+            // Bases decoded from a file which has changed since are not kept,
+            // and neither are those of a query decoded meanwhile
+            if state.mapping.as_ref().map(|&(s, _)| s) == Some(stamp)
+                && !state.entries.contains_key(&query)
+            {
+                self.evict(&mut state, size);
+
+                state.clock += 1;
+                let clock = state.clock;
+                state.used += size;
+                state.entries.insert(
+                    query,
+                    CacheEntry {
+                        bases: bases.clone(),
+                        size,
+                        last_used: clock,
+                    },
+                );
+            }
+        }
+
+        Ok(bases)
+    }
+
+    /// Evicts queries until `size` more bytes fit in the budget.
+    fn evict(&self, state: &mut CacheState, size: usize) {
+        while state.used + size > self.config.memory_budget {
+            let victim = match self.config.eviction {
+                EvictionPolicy::LeastRecentlyUsed => state
+                    .entries
+                    .iter()
+                    .min_by_key(|(_, entry)| entry.last_used)
+                    .map(|(&query, _)| query),
+                EvictionPolicy::LargestFirst => state
+                    .entries
+                    .iter()
+                    .max_by_key(|(_, entry)| entry.size)
+                    .map(|(&query, _)| query),
+            };
+
+            match victim.and_then(|query| state.entries.remove(&query)) {
+                Some(entry) => state.used -= entry.size,
+                None => break,
+            }
+        }
+    }
+}
+
+/// Decodes the points of a query from the mapped file.
+fn read_query<G: CurveAffine>(
+    worker: &Worker,
+    mmap: &Mmap,
+    ranges: &[Range<usize>],
+    checked: bool,
+    query: &str,
+) -> io::Result<Vec<G>> {
+    if let Some(range) = ranges.last() {
+        if range.end > mmap.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "parameter file is too short",
+            ));
+        }
+    }
+
+    decode_points(worker, 0..ranges.len(), checked, query, |i| {
+        &mmap[ranges[i].clone()]
+    })
+}
+
+/// Decodes the uncompressed points of a query at `indices` in parallel, where
+/// `encoding` returns the bytes of the point at an index. When `checked`,
+/// every point is checked to be on the curve and in the subgroup. Fails with
+/// the index of the first invalid point, and `query`, the name of the query.
+pub(super) fn decode_points<'a, G, F>(
+    worker: &Worker,
+    indices: Range<usize>,
+    checked: bool,
+    query: &str,
+    encoding: F,
+) -> io::Result<Vec<G>>
+where
+    G: CurveAffine,
+    F: Fn(usize) -> &'a [u8] + Sync,
+{
+    let first = indices.start;
+    if indices.end == first {
+        return Ok(vec![]);
+    }
+
+    let mut points = vec![G::zero(); indices.end - first];
+    // The first invalid point, by index
+    let invalid: Mutex<Option<(usize, String)>> = Mutex::new(None);
+
+    worker.scope(points.len(), |scope, chunk| {
+        for (i, points) in points.chunks_mut(chunk).enumerate() {
+            let encoding = &encoding;
+            let invalid = &invalid;
+
+            scope.spawn(move |_scope| {
+                for (j, point) in points.iter_mut().enumerate() {
+                    let index = first + i * chunk + j;
+                    match decode_point(encoding(index), checked) {
+                        Ok(p) => *point = p,
+                        Err(e) => {
+                            let mut invalid = invalid.lock().unwrap();
+                            match *invalid {
+                                Some((first, _)) if first < index => {}
+                                _ => *invalid = Some((index, e)),
+                            }
+                            return;
+                        }
+                    }
+                }
+            });
+        }
+    });
+
+    match invalid.into_inner().unwrap() {
+        Some((index, e)) => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("invalid point {} of the {} query: {}", index, query, e),
+        )),
+        None => Ok(points),
+    }
+}
+
+fn decode_point<G: CurveAffine>(bytes: &[u8], checked: bool) -> Result<G, String> {
+    let mut repr = G::Uncompressed::empty();
+    if bytes.len() != repr.as_ref().len() {
+        return Err("wrong encoding length".into());
+    }
+    repr.as_mut().copy_from_slice(bytes);
+
+    let point = if checked {
+        repr.into_affine()
+    } else {
+        repr.into_affine_unchecked()
+    }
+    .map_err(|e| e.to_string())?;
+
+    if point.is_zero() {
+        Err("point at infinity".into())
+    } else {
+        Ok(point)
+    }
+}
+
 impl<'a, E: Engine> ParameterSource<E> for &'a MappedParameters<E> {
     type G1Builder = (Arc<Vec<E::G1Affine>>, usize);
     type G2Builder = (Arc<Vec<E::G2Affine>>, usize);
@@ -63,41 +375,19 @@ impl<'a, E: Engine> ParameterSource<E> for &'a MappedParameters<E> {
     }
 
     fn get_h(&mut self, _num_h: usize) -> Result<Self::G1Builder, SynthesisError> {
-        let params = File::open(self.param_file.clone())?;
-        // Safety: this operation is safe, because we are
-        // intentionally memory mapping this file.
-        let mmap = unsafe { MmapOptions::new().map(&params)? };
-
-        let mut h = vec![];
-        for i in 0..self.h.len() {
-            h.push(read_g1::<E>(
-                &mmap,
-                self.h[i].start,
-                self.h[i].end,
-                self.checked,
-            )?);
-        }
+        let h = self.get_query(Query::H, |mmap| {
+            read_query(&self.worker, mmap, &self.h, self.checked, "h")
+        })?;
 
-        Ok((Arc::new(h), 0))
+        Ok((h, 0))
     }
 
     fn get_l(&mut self, _num_l: usize) -> Result<Self::G1Builder, SynthesisError> {
-        let params = File::open(self.param_file.clone())?;
-        // Safety: this operation is safe, because we are
-        // intentionally memory mapping this file.
-        let mmap = unsafe { MmapOptions::new().map(&params)? };
-
-        let mut l = vec![];
-        for i in 0..self.l.len() {
-            l.push(read_g1::<E>(
-                &mmap,
-                self.l[i].start,
-                self.l[i].end,
-                self.checked,
-            )?);
-        }
+        let l = self.get_query(Query::L, |mmap| {
+            read_query(&self.worker, mmap, &self.l, self.checked, "l")
+        })?;
 
-        Ok((Arc::new(l), 0))
+        Ok((l, 0))
     }
 
     fn get_a(
@@ -105,22 +395,11 @@ impl<'a, E: Engine> ParameterSource<E> for &'a MappedParameters<E> {
         num_inputs: usize,
         _num_a: usize,
     ) -> Result<(Self::G1Builder, Self::G1Builder), SynthesisError> {
-        let params = File::open(self.param_file.clone())?;
-        // Safety: this operation is safe, because we are
-        // intentionally memory mapping this file.
-        let mmap = unsafe { MmapOptions::new().map(&params)? };
-
-        let mut a = vec![];
-        for i in 0..self.a.len() {
-            a.push(read_g1::<E>(
-                &mmap,
-                self.a[i].start,
-                self.a[i].end,
-                self.checked,
-            )?);
-        }
+        let a = self.get_query(Query::A, |mmap| {
+            read_query(&self.worker, mmap, &self.a, self.checked, "a")
+        })?;
 
-        Ok(((Arc::new(a.clone()), 0), (Arc::new(a), num_inputs)))
+        Ok(((a.clone(), 0), (a, num_inputs)))
     }
 
     fn get_b_g1(
@@ -128,22 +407,11 @@ impl<'a, E: Engine> ParameterSource<E> for &'a MappedParameters<E> {
         num_inputs: usize,
         _num_b_g1: usize,
     ) -> Result<(Self::G1Builder, Self::G1Builder), SynthesisError> {
-        let params = File::open(self.param_file.clone())?;
-        // Safety: this operation is safe, because we are
-        // intentionally memory mapping this file.
-        let mmap = unsafe { MmapOptions::new().map(&params)? };
-
-        let mut b_g1 = vec![];
-        for i in 0..self.b_g1.len() {
-            b_g1.push(read_g1::<E>(
-                &mmap,
-                self.b_g1[i].start,
-                self.b_g1[i].end,
-                self.checked,
-            )?);
-        }
+        let b_g1 = self.get_query(Query::BG1, |mmap| {
+            read_query(&self.worker, mmap, &self.b_g1, self.checked, "b_g1")
+        })?;
 
-        Ok(((Arc::new(b_g1.clone()), 0), (Arc::new(b_g1), num_inputs)))
+        Ok(((b_g1.clone(), 0), (b_g1, num_inputs)))
     }
 
     fn get_b_g2(
@@ -151,22 +419,25 @@ impl<'a, E: Engine> ParameterSource<E> for &'a MappedParameters<E> {
         num_inputs: usize,
         _num_b_g2: usize,
     ) -> Result<(Self::G2Builder, Self::G2Builder), SynthesisError> {
-        let params = File::open(self.param_file.clone())?;
-        // Safety: this operation is safe, because we are
-        // intentionally memory mapping this file.
-        let mmap = unsafe { MmapOptions::new().map(&params)? };
-
-        let mut b_g2 = vec![];
-        for i in 0..self.b_g2.len() {
-            b_g2.push(read_g2::<E>(
-                &mmap,
-                self.b_g2[i].start,
-                self.b_g2[i].end,
-                self.checked,
-            )?);
-        }
+        let b_g2 = self.get_query(Query::BG2, |mmap| {
+            read_query(&self.worker, mmap, &self.b_g2, self.checked, "b_g2")
+        })?;
+
+        Ok(((b_g2.clone(), 0), (b_g2, num_inputs)))
+    }
+
+    fn shape(&self) -> Option<CircuitShape> {
+        Some(CircuitShape {
+            constraints: self.h.len() + 1,
+            inputs: self.vk.ic.len(),
+            aux: self.l.len(),
+        })
+    }
 
-        Ok(((Arc::new(b_g2.clone()), 0), (Arc::new(b_g2), num_inputs)))
+    // Cached queries are shared, but are only known to be cached once they
+    // fit in the budget of the cache
+    fn loads_queries(&self) -> bool {
+        true
     }
 }
 
@@ -0,0 +1,157 @@
+use ff::PrimeField;
+use paired::Engine;
+
+use std::cmp;
+use std::mem;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use super::ParameterSource;
+
+/// The sizes of a circuit which determine the memory taken to prove it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CircuitShape {
+    /// The number of constraints, including the one the prover adds for
+    /// every input.
+    pub constraints: usize,
+    /// The number of inputs, including the constant one.
+    pub inputs: usize,
+    /// The number of auxiliary variables.
+    pub aux: usize,
+}
+
+impl CircuitShape {
+    /// The size of the domain the FFTs of the prover run over.
+    pub fn domain_size(&self) -> usize {
+        self.constraints.next_power_of_two()
+    }
+}
+
+/// An estimate of the memory, in bytes, taken by each circuit of a batch
+/// while it is proven, on top of the parameters held by the source. The
+/// circuits of a batch are all synthesized before their FFTs, and all their
+/// multiexps are started before any is waited for, so a batch takes
+/// `peak(batch_size)` at its peak.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MemoryEstimate {
+    /// The evaluations of the A, B and C polynomials, over the whole domain
+    /// once their FFTs start.
+    pub evaluations: usize,
+    /// The assignment, the coefficients of H and the exponents of the
+    /// sparse multiexps.
+    pub exponents: usize,
+    /// The queries loaded for the multiexps, by sources which load them
+    /// whenever the prover asks for them.
+    pub queries: usize,
+}
+
+impl MemoryEstimate {
+    pub fn new<E: Engine>(shape: CircuitShape, loads_queries: bool) -> Self {
+        let fr_len = mem::size_of::<E::Fr>();
+        let repr_len = mem::size_of::<<E::Fr as PrimeField>::Repr>();
+        let g1_len = mem::size_of::<E::G1Affine>();
+        let g2_len = mem::size_of::<E::G2Affine>();
+
+        let domain_size = shape.domain_size();
+        let variables = shape.inputs + shape.aux;
+
+        // The synthesized assignment is converted to its representation,
+        // which is then compacted for the A query and both B queries if a
+        // GPU proves them. The estimate counts the copies either way.
+        let exponents = variables * fr_len
+            + (domain_size + variables + shape.inputs + 2 * shape.aux) * repr_len
+            + 3 * variables / 8;
+
+        let queries = if loads_queries {
+            (domain_size + shape.aux + 2 * variables) * g1_len + variables * g2_len
+        } else {
+            0
+        };
+
+        MemoryEstimate {
+            evaluations: 3 * domain_size * fr_len,
+            exponents,
+            queries,
+        }
+    }
+
+    /// Returns the estimate for proving with `params`, if it knows the shape
+    /// of its circuit.
+    pub fn for_params<E: Engine, P: ParameterSource<E>>(params: &P) -> Option<Self> {
+        params
+            .shape()
+            .map(|shape| Self::new::<E>(shape, params.loads_queries()))
+    }
+
+    pub fn per_circuit(&self) -> usize {
+        self.evaluations + self.exponents + self.queries
+    }
+
+    /// The memory taken by a batch of `batch_size` circuits at its peak.
+    pub fn peak(&self, batch_size: usize) -> usize {
+        self.per_circuit().saturating_mul(batch_size)
+    }
+
+    /// Returns the size of the largest batch which fits in `budget`, which
+    /// is zero if not even a single circuit does.
+    pub fn max_batch_size(&self, budget: usize) -> usize {
+        budget / cmp::max(1, self.per_circuit())
+    }
+}
+
+/// The memory taken by the last batch proven with a `ProverContext`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MemoryReport {
+    /// The estimate the batch was split by, if the source knows the shape
+    /// of its circuit.
+    pub estimate: Option<MemoryEstimate>,
+    /// The number of circuits proven at once. Batches are proven in parts
+    /// of this size to fit the memory budget.
+    pub parallelism: usize,
+    /// The most memory, in bytes, taken at once by the buffers the prover
+    /// allocates and the queries it loads, as measured while proving.
+    pub high_water_mark: usize,
+}
+
+/// Measures the memory taken by the buffers of the prover as they are
+/// allocated and freed.
+#[derive(Debug, Default)]
+pub(super) struct MemoryMeter {
+    current: AtomicUsize,
+    peak: AtomicUsize,
+}
+
+impl MemoryMeter {
+    pub(super) fn alloc(&self, bytes: usize) {
+        let current = self.current.fetch_add(bytes, Ordering::SeqCst) + bytes;
+
+        let mut peak = self.peak.load(Ordering::SeqCst);
+        while current > peak {
+            match self
+                .peak
+                .compare_exchange(peak, current, Ordering::SeqCst, Ordering::SeqCst)
+            {
+                Ok(_) => break,
+                Err(actual) => peak = actual,
+            }
+        }
+    }
+
+    pub(super) fn free(&self, bytes: usize) {
+        self.current.fetch_sub(bytes, Ordering::SeqCst);
+    }
+
+    /// Frees everything measured, once the buffers of a part of a batch are
+    /// dropped.
+    pub(super) fn free_all(&self) {
+        self.current.store(0, Ordering::SeqCst);
+    }
+
+    pub(super) fn peak(&self) -> usize {
+        self.peak.load(Ordering::SeqCst)
+    }
+}
+
+/// Returns the bytes allocated by `v`.
+pub(super) fn vec_bytes<T>(v: &[T]) -> usize {
+    v.len() * mem::size_of::<T>()
+}
@@ -5,11 +5,13 @@
 use groupy::{CurveAffine, EncodedPoint};
 use paired::{Engine, PairingCurveAffine};
 
+use crate::multicore::Worker;
 use crate::multiexp::SourceBuilder;
 use crate::SynthesisError;
 
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 use memmap::{Mmap, MmapOptions};
+use std::cmp;
 use std::fs::File;
 use std::io::{self, Read, Write};
 use std::mem;
@@ -20,16 +22,26 @@ use std::sync::Arc;
 #[cfg(test)]
 mod tests;
 
+mod context;
 mod ext;
 mod generator;
+mod header;
 mod mapped_params;
+mod memory;
+mod precomputed_params;
 mod prover;
+mod sharded_params;
 mod verifier;
 
+pub use self::context::*;
 pub use self::ext::*;
 pub use self::generator::*;
+pub use self::header::*;
 pub use self::mapped_params::*;
+pub use self::memory::*;
+pub use self::precomputed_params::*;
 pub use self::prover::*;
+pub use self::sharded_params::*;
 pub use self::verifier::*;
 
 #[derive(Clone, Debug)]
@@ -348,43 +360,44 @@ impl<E: Engine> PartialEq for Parameters<E> {
 }
 
 impl<E: Engine> Parameters<E> {
+    /// Writes the parameters in the layout of files without a header, which
+    /// every reader accepts.
     pub fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
         self.vk.write(&mut writer)?;
 
-        writer.write_u32::<BigEndian>(self.h.len() as u32)?;
-        for g in &self.h[..] {
-            writer.write_all(g.into_uncompressed().as_ref())?;
-        }
-
-        writer.write_u32::<BigEndian>(self.l.len() as u32)?;
-        for g in &self.l[..] {
-            writer.write_all(g.into_uncompressed().as_ref())?;
-        }
-
-        writer.write_u32::<BigEndian>(self.a.len() as u32)?;
-        for g in &self.a[..] {
-            writer.write_all(g.into_uncompressed().as_ref())?;
-        }
-
-        writer.write_u32::<BigEndian>(self.b_g1.len() as u32)?;
-        for g in &self.b_g1[..] {
-            writer.write_all(g.into_uncompressed().as_ref())?;
-        }
-
-        writer.write_u32::<BigEndian>(self.b_g2.len() as u32)?;
-        for g in &self.b_g2[..] {
-            writer.write_all(g.into_uncompressed().as_ref())?;
-        }
+        write_query(&mut writer, &self.h)?;
+        write_query(&mut writer, &self.l)?;
+        write_query(&mut writer, &self.a)?;
+        write_query(&mut writer, &self.b_g1)?;
+        write_query(&mut writer, &self.b_g2)?;
 
         Ok(())
     }
 
+    /// Writes the parameters after a header identifying the engine and
+    /// circuit, with the length and digest of every query, which readers
+    /// check them against.
+    pub fn write_with_header<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        ParameterHeader::new(self).write(&mut writer)?;
+        self.write(writer)
+    }
+
     // Quickly iterates through the parameter file, recording all
     // parameter offsets and caches the verifying key (vk) for quick
     // access via reference.
     pub fn build_mapped_parameters(
         param_file: PathBuf,
         checked: bool,
+    ) -> io::Result<MappedParameters<E>> {
+        Self::build_mapped_parameters_with_worker(param_file, checked, &Worker::new())
+    }
+
+    /// Like `build_mapped_parameters`, but the queries are decoded on the
+    /// pool of `worker`.
+    pub fn build_mapped_parameters_with_worker(
+        param_file: PathBuf,
+        checked: bool,
+        worker: &Worker,
     ) -> io::Result<MappedParameters<E>> {
         let mut offset: usize = 0;
         let params = File::open(&param_file)?;
@@ -407,9 +420,16 @@ impl<E: Engine> Parameters<E> {
         let get_offsets = |mmap: &Mmap,
                            offset: &mut usize,
                            param: &mut Vec<Range<usize>>,
-                           range_len: usize|
+                           range_len: usize,
+                           section: Option<&SectionHeader>|
          -> Result<(), std::io::Error> {
+            let start = *offset;
             let len = read_length(&mmap, &mut *offset)?;
+            check_len(section, len)?;
+            if checked && section.is_some() {
+                let end = start + u32_len + len * range_len;
+                check_digest(section.map(|s| &s.digest), &digest(&mmap[start..end]))?;
+            }
             for _ in 0..len {
                 (*param).push(Range {
                     start: *offset,
@@ -421,7 +441,19 @@ impl<E: Engine> Parameters<E> {
             Ok(())
         };
 
+        let header = read_mapped_header::<E>(&mmap)?;
+        if header.is_some() {
+            offset = ParameterHeader::SIZE;
+        }
+
+        let vk_start = offset;
         let vk = VerifyingKey::<E>::read_mmap(&mmap, &mut offset)?;
+        if let (true, Some(header)) = (checked, &header) {
+            check_digest(
+                Some(&header.circuit_digest),
+                &digest(&mmap[vk_start..offset]),
+            )?;
+        }
 
         let mut h = vec![];
         let mut l = vec![];
@@ -429,11 +461,24 @@ impl<E: Engine> Parameters<E> {
         let mut b_g1 = vec![];
         let mut b_g2 = vec![];
 
-        get_offsets(&mmap, &mut offset, &mut h, g1_len)?;
-        get_offsets(&mmap, &mut offset, &mut l, g1_len)?;
-        get_offsets(&mmap, &mut offset, &mut a, g1_len)?;
-        get_offsets(&mmap, &mut offset, &mut b_g1, g1_len)?;
-        get_offsets(&mmap, &mut offset, &mut b_g2, g2_len)?;
+        let header = header.as_ref();
+        get_offsets(&mmap, &mut offset, &mut h, g1_len, header.map(|h| &h.h))?;
+        get_offsets(&mmap, &mut offset, &mut l, g1_len, header.map(|h| &h.l))?;
+        get_offsets(&mmap, &mut offset, &mut a, g1_len, header.map(|h| &h.a))?;
+        get_offsets(
+            &mmap,
+            &mut offset,
+            &mut b_g1,
+            g1_len,
+            header.map(|h| &h.b_g1),
+        )?;
+        get_offsets(
+            &mmap,
+            &mut offset,
+            &mut b_g2,
+            g2_len,
+            header.map(|h| &h.b_g2),
+        )?;
 
         Ok(MappedParameters {
             param_file,
@@ -445,6 +490,8 @@ impl<E: Engine> Parameters<E> {
             b_g2,
             checked,
             _e: Default::default(),
+            cache: None,
+            worker: worker.clone(),
         })
     }
 
@@ -453,114 +500,43 @@ impl<E: Engine> Parameters<E> {
     // rust-fil-proofs repo).  It's equivalent to the existing read
     // method, in that it loads all parameters to RAM.
     pub fn read_mmap(mmap: &Mmap, checked: bool) -> io::Result<Self> {
-        let u32_len = mem::size_of::<u32>();
-        let g1_len = mem::size_of::<<E::G1Affine as CurveAffine>::Uncompressed>();
-        let g2_len = mem::size_of::<<E::G2Affine as CurveAffine>::Uncompressed>();
-
-        let read_g1 = |mmap: &Mmap, offset: &mut usize| -> io::Result<E::G1Affine> {
-            let ptr = &mmap[*offset..*offset + g1_len];
-            *offset += g1_len;
-            // Safety: this operation is safe, because it's simply
-            // casting to a known struct at the correct offset, given
-            // the structure of the on-disk data.
-            let repr: <E::G1Affine as CurveAffine>::Uncompressed = unsafe {
-                *(ptr as *const [u8] as *const <E::G1Affine as CurveAffine>::Uncompressed)
-            };
-
-            if checked {
-                repr.into_affine()
-            } else {
-                repr.into_affine_unchecked()
-            }
-            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
-            .and_then(|e| {
-                if e.is_zero() {
-                    Err(io::Error::new(
-                        io::ErrorKind::InvalidData,
-                        "point at infinity",
-                    ))
-                } else {
-                    Ok(e)
-                }
-            })
-        };
-
-        let read_g2 = |mmap: &Mmap, offset: &mut usize| -> io::Result<E::G2Affine> {
-            let ptr = &mmap[*offset..*offset + g2_len];
-            *offset += g2_len;
-            // Safety: this operation is safe, because it's simply
-            // casting to a known struct at the correct offset, given
-            // the structure of the on-disk data.
-            let repr: <E::G2Affine as CurveAffine>::Uncompressed = unsafe {
-                *(ptr as *const [u8] as *const <E::G2Affine as CurveAffine>::Uncompressed)
-            };
-
-            if checked {
-                repr.into_affine()
-            } else {
-                repr.into_affine_unchecked()
-            }
-            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
-            .and_then(|e| {
-                if e.is_zero() {
-                    Err(io::Error::new(
-                        io::ErrorKind::InvalidData,
-                        "point at infinity",
-                    ))
-                } else {
-                    Ok(e)
-                }
-            })
-        };
-
-        let read_length = |mmap: &Mmap, offset: &mut usize| -> Result<usize, std::io::Error> {
-            let mut raw_len = &mmap[*offset..*offset + u32_len];
-            *offset += u32_len;
-
-            match raw_len.read_u32::<BigEndian>() {
-                Ok(len) => Ok(len as usize),
-                Err(err) => Err(err),
-            }
-        };
-
-        let get_g1s = |mmap: &Mmap,
-                       offset: &mut usize,
-                       param: &mut Vec<E::G1Affine>|
-         -> Result<(), std::io::Error> {
-            let len = read_length(&mmap, &mut *offset)?;
-            for _ in 0..len {
-                (*param).push(read_g1(&mmap, &mut *offset)?);
-            }
-
-            Ok(())
-        };
-
-        let get_g2s = |mmap: &Mmap,
-                       offset: &mut usize,
-                       param: &mut Vec<E::G2Affine>|
-         -> Result<(), std::io::Error> {
-            let len = read_length(&mmap, &mut *offset)?;
-            for _ in 0..len {
-                (*param).push(read_g2(&mmap, &mut *offset)?);
-            }
+        Self::read_mmap_with_worker(mmap, checked, &Worker::new())
+    }
 
-            Ok(())
+    /// Like `read_mmap`, but the queries are decoded on the pool of
+    /// `worker`.
+    pub fn read_mmap_with_worker(mmap: &Mmap, checked: bool, worker: &Worker) -> io::Result<Self> {
+        let header = read_mapped_header::<E>(mmap)?;
+        let mut offset: usize = if header.is_some() {
+            ParameterHeader::SIZE
+        } else {
+            0
         };
 
-        let mut offset: usize = 0;
+        let vk_start = offset;
         let vk = VerifyingKey::<E>::read_mmap(&mmap, &mut offset)?;
+        if let Some(ref header) = header {
+            check_digest(
+                Some(&header.circuit_digest),
+                &digest(&mmap[vk_start..offset]),
+            )?;
+        }
 
-        let mut h = vec![];
-        let mut l = vec![];
-        let mut a = vec![];
-        let mut b_g1 = vec![];
-        let mut b_g2 = vec![];
-
-        get_g1s(&mmap, &mut offset, &mut h)?;
-        get_g1s(&mmap, &mut offset, &mut l)?;
-        get_g1s(&mmap, &mut offset, &mut a)?;
-        get_g1s(&mmap, &mut offset, &mut b_g1)?;
-        get_g2s(&mmap, &mut offset, &mut b_g2)?;
+        let header = header.as_ref();
+        let mut read =
+            |query, section| read_mapped_query(worker, mmap, &mut offset, checked, query, section);
+        let h = read("h", header.map(|h| &h.h))?;
+        let l = read("l", header.map(|h| &h.l))?;
+        let a = read("a", header.map(|h| &h.a))?;
+        let b_g1 = read("b_g1", header.map(|h| &h.b_g1))?;
+        let b_g2 = read_mapped_query(
+            worker,
+            mmap,
+            &mut offset,
+            checked,
+            "b_g2",
+            header.map(|h| &h.b_g2),
+        )?;
 
         Ok(Parameters {
             vk,
@@ -572,93 +548,53 @@ impl<E: Engine> Parameters<E> {
         })
     }
 
-    pub fn read<R: Read>(mut reader: R, checked: bool) -> io::Result<Self> {
-        let read_g1 = |reader: &mut R| -> io::Result<E::G1Affine> {
-            let mut repr = <E::G1Affine as CurveAffine>::Uncompressed::empty();
-            reader.read_exact(repr.as_mut())?;
-
-            if checked {
-                repr.into_affine()
-            } else {
-                repr.into_affine_unchecked()
-            }
-            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
-            .and_then(|e| {
-                if e.is_zero() {
-                    Err(io::Error::new(
-                        io::ErrorKind::InvalidData,
-                        "point at infinity",
-                    ))
-                } else {
-                    Ok(e)
-                }
-            })
-        };
-
-        let read_g2 = |reader: &mut R| -> io::Result<E::G2Affine> {
-            let mut repr = <E::G2Affine as CurveAffine>::Uncompressed::empty();
-            reader.read_exact(repr.as_mut())?;
-
-            if checked {
-                repr.into_affine()
-            } else {
-                repr.into_affine_unchecked()
-            }
-            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
-            .and_then(|e| {
-                if e.is_zero() {
-                    Err(io::Error::new(
-                        io::ErrorKind::InvalidData,
-                        "point at infinity",
-                    ))
-                } else {
-                    Ok(e)
-                }
-            })
-        };
-
-        let vk = VerifyingKey::<E>::read(&mut reader)?;
-
-        let mut h = vec![];
-        let mut l = vec![];
-        let mut a = vec![];
-        let mut b_g1 = vec![];
-        let mut b_g2 = vec![];
-
-        {
-            let len = reader.read_u32::<BigEndian>()? as usize;
-            for _ in 0..len {
-                h.push(read_g1(&mut reader)?);
-            }
-        }
-
-        {
-            let len = reader.read_u32::<BigEndian>()? as usize;
-            for _ in 0..len {
-                l.push(read_g1(&mut reader)?);
-            }
-        }
-
-        {
-            let len = reader.read_u32::<BigEndian>()? as usize;
-            for _ in 0..len {
-                a.push(read_g1(&mut reader)?);
-            }
-        }
+    /// Reads parameters, checking them against the header of the file if it
+    /// has one. Files written without a header are read as before.
+    pub fn read<R: Read>(reader: R, checked: bool) -> io::Result<Self> {
+        Self::read_with_worker(reader, checked, &Worker::new())
+    }
 
-        {
-            let len = reader.read_u32::<BigEndian>()? as usize;
-            for _ in 0..len {
-                b_g1.push(read_g1(&mut reader)?);
+    /// Like `read`, but the queries are decoded on the pool of `worker`.
+    pub fn read_with_worker<R: Read>(
+        mut reader: R,
+        checked: bool,
+        worker: &Worker,
+    ) -> io::Result<Self> {
+        match ParameterHeader::read(&mut reader)? {
+            (Some(header), _) => {
+                header.check_engine::<E>()?;
+                Self::read_body(worker, DigestReader::new(reader), Some(&header), checked)
             }
+            (None, magic) => Self::read_body(
+                worker,
+                DigestReader::new((&magic[..]).chain(reader)),
+                None,
+                checked,
+            ),
         }
+    }
 
-        {
-            let len = reader.read_u32::<BigEndian>()? as usize;
-            for _ in 0..len {
-                b_g2.push(read_g2(&mut reader)?);
-            }
-        }
+    fn read_body<R: Read>(
+        worker: &Worker,
+        mut reader: DigestReader<R>,
+        header: Option<&ParameterHeader>,
+        checked: bool,
+    ) -> io::Result<Self> {
+        let vk = VerifyingKey::<E>::read(&mut reader)?;
+        check_digest(header.map(|h| &h.circuit_digest), &reader.finish_section())?;
+
+        let mut read = |query, section| read_query(worker, &mut reader, checked, query, section);
+        let h = read("h", header.map(|h| &h.h))?;
+        let l = read("l", header.map(|h| &h.l))?;
+        let a = read("a", header.map(|h| &h.a))?;
+        let b_g1 = read("b_g1", header.map(|h| &h.b_g1))?;
+        let b_g2 = read_query(
+            worker,
+            &mut reader,
+            checked,
+            "b_g2",
+            header.map(|h| &h.b_g2),
+        )?;
 
         Ok(Parameters {
             vk,
@@ -671,6 +607,74 @@ impl<E: Engine> Parameters<E> {
     }
 }
 
+/// The number of points `Parameters::read` decodes at a time.
+const READ_CHUNK_SIZE: usize = 1 << 16;
+
+/// Reads a query written by `Parameters::write`, decoding its points in
+/// parallel a chunk at a time, and checks it against its section of the
+/// header.
+fn read_query<G: CurveAffine, R: Read>(
+    worker: &Worker,
+    reader: &mut DigestReader<R>,
+    checked: bool,
+    query: &str,
+    section: Option<&SectionHeader>,
+) -> io::Result<Vec<G>> {
+    let point_len = mem::size_of::<G::Uncompressed>();
+
+    let len = reader.read_u32::<BigEndian>()? as usize;
+    check_len(section, len)?;
+
+    let mut points = vec![];
+    let mut bytes = vec![];
+    for start in (0..len).step_by(READ_CHUNK_SIZE) {
+        let end = cmp::min(start + READ_CHUNK_SIZE, len);
+        bytes.resize((end - start) * point_len, 0);
+        reader.read_exact(&mut bytes)?;
+
+        let bytes = &bytes;
+        points.extend(decode_points::<G, _>(
+            worker,
+            start..end,
+            checked,
+            query,
+            |i| &bytes[(i - start) * point_len..(i - start + 1) * point_len],
+        )?);
+    }
+    check_digest(section.map(|s| &s.digest), &reader.finish_section())?;
+
+    Ok(points)
+}
+
+/// Reads a query like `read_query`, from a mapped file at `offset`.
+fn read_mapped_query<G: CurveAffine>(
+    worker: &Worker,
+    mmap: &[u8],
+    offset: &mut usize,
+    checked: bool,
+    query: &str,
+    section: Option<&SectionHeader>,
+) -> io::Result<Vec<G>> {
+    let point_len = mem::size_of::<G::Uncompressed>();
+    let too_short = || io::Error::new(io::ErrorKind::UnexpectedEof, "parameter file is too short");
+
+    let start = *offset;
+    let mut raw_len = mmap.get(start..start + 4).ok_or_else(too_short)?;
+    let len = raw_len.read_u32::<BigEndian>()? as usize;
+    check_len(section, len)?;
+
+    let end = start + 4 + len * point_len;
+    let bytes = mmap.get(start + 4..end).ok_or_else(too_short)?;
+    if let Some(section) = section {
+        check_digest(Some(&section.digest), &digest(&mmap[start..end]))?;
+    }
+    *offset = end;
+
+    decode_points(worker, 0..len, checked, query, |i| {
+        &bytes[i * point_len..(i + 1) * point_len]
+    })
+}
+
 pub struct PreparedVerifyingKey<E: Engine> {
     /// Pairing result of alpha*beta
     alpha_g1_beta_g2: E::Fqk,
@@ -715,6 +719,18 @@ pub trait ParameterSource<E: Engine> {
         num_inputs: usize,
         num_aux: usize,
     ) -> Result<(Self::G2Builder, Self::G2Builder), SynthesisError>;
+
+    /// Returns the shape of the circuit the parameters are for, if known,
+    /// from which the memory taken by proving is estimated.
+    fn shape(&self) -> Option<CircuitShape> {
+        None
+    }
+
+    /// Whether the queries are loaded into memory whenever the prover asks
+    /// for them, rather than shared with the source.
+    fn loads_queries(&self) -> bool {
+        false
+    }
 }
 
 impl<'a, E: Engine> ParameterSource<E> for &'a Parameters<E> {
@@ -756,6 +772,61 @@ impl<'a, E: Engine> ParameterSource<E> for &'a Parameters<E> {
     ) -> Result<(Self::G2Builder, Self::G2Builder), SynthesisError> {
         Ok(((self.b_g2.clone(), 0), (self.b_g2.clone(), num_inputs)))
     }
+
+    fn shape(&self) -> Option<CircuitShape> {
+        Some(CircuitShape {
+            constraints: self.h.len() + 1,
+            inputs: self.vk.ic.len(),
+            aux: self.l.len(),
+        })
+    }
+}
+
+/// Lets owned parameters be moved into a proof which outlives the caller,
+/// as for `create_random_proof_async`.
+impl<E: Engine> ParameterSource<E> for Arc<Parameters<E>> {
+    type G1Builder = (Arc<Vec<E::G1Affine>>, usize);
+    type G2Builder = (Arc<Vec<E::G2Affine>>, usize);
+
+    fn get_vk(&mut self, num_ic: usize) -> Result<VerifyingKey<E>, SynthesisError> {
+        (&**self).get_vk(num_ic)
+    }
+
+    fn get_h(&mut self, num_h: usize) -> Result<Self::G1Builder, SynthesisError> {
+        (&**self).get_h(num_h)
+    }
+
+    fn get_l(&mut self, num_l: usize) -> Result<Self::G1Builder, SynthesisError> {
+        (&**self).get_l(num_l)
+    }
+
+    fn get_a(
+        &mut self,
+        num_inputs: usize,
+        num_a: usize,
+    ) -> Result<(Self::G1Builder, Self::G1Builder), SynthesisError> {
+        (&**self).get_a(num_inputs, num_a)
+    }
+
+    fn get_b_g1(
+        &mut self,
+        num_inputs: usize,
+        num_b_g1: usize,
+    ) -> Result<(Self::G1Builder, Self::G1Builder), SynthesisError> {
+        (&**self).get_b_g1(num_inputs, num_b_g1)
+    }
+
+    fn get_b_g2(
+        &mut self,
+        num_inputs: usize,
+        num_b_g2: usize,
+    ) -> Result<(Self::G2Builder, Self::G2Builder), SynthesisError> {
+        (&**self).get_b_g2(num_inputs, num_b_g2)
+    }
+
+    fn shape(&self) -> Option<CircuitShape> {
+        (&**self).shape()
+    }
 }
 
 #[cfg(test)]
@@ -847,4 +918,1214 @@ mod test_with_bls12_381 {
             assert!(!verify_proof(&pvk, &proof, &[a]).unwrap());
         }
     }
+
+    #[test]
+    fn parameter_header() {
+        use std::fs;
+
+        struct MySillyCircuit<E: Engine> {
+            a: Option<E::Fr>,
+            b: Option<E::Fr>,
+        }
+
+        impl<E: Engine> Circuit<E> for MySillyCircuit<E> {
+            fn synthesize<CS: ConstraintSystem<E>>(
+                self,
+                cs: &mut CS,
+            ) -> Result<(), SynthesisError> {
+                let a = cs.alloc(|| "a", || self.a.ok_or(SynthesisError::AssignmentMissing))?;
+                let b = cs.alloc(|| "b", || self.b.ok_or(SynthesisError::AssignmentMissing))?;
+                let c = cs.alloc_input(
+                    || "c",
+                    || {
+                        let mut a = self.a.ok_or(SynthesisError::AssignmentMissing)?;
+                        let b = self.b.ok_or(SynthesisError::AssignmentMissing)?;
+
+                        a.mul_assign(&b);
+                        Ok(a)
+                    },
+                )?;
+
+                cs.enforce(|| "a*b=c", |lc| lc + a, |lc| lc + b, |lc| lc + c);
+
+                Ok(())
+            }
+        }
+
+        let rng = &mut thread_rng();
+
+        let params =
+            generate_random_parameters::<Bls12, _, _>(MySillyCircuit { a: None, b: None }, rng)
+                .unwrap();
+
+        let mut v = vec![];
+        params.write_with_header(&mut v).unwrap();
+
+        let header = ParameterHeader::read(&v[..]).unwrap().0.unwrap();
+        assert_eq!(header, ParameterHeader::new(&params));
+        assert_eq!(header.file_len::<Bls12>(), v.len() as u64);
+        assert_eq!(header.h.len as usize, params.h.len());
+
+        // Files without a header are still read
+        let mut legacy = vec![];
+        params.write(&mut legacy).unwrap();
+        assert_eq!(&legacy[..], &v[ParameterHeader::SIZE..]);
+        let legacy = &legacy[..];
+        assert!(ParameterHeader::read(legacy).unwrap().0.is_none());
+        assert!(Parameters::<Bls12>::read(legacy, true).unwrap() == params);
+
+        let corrupt = |offset: usize| {
+            let mut v = v.clone();
+            v[offset] ^= 1;
+            v
+        };
+
+        // The version, engine, circuit digest and query digests in the
+        // header, a byte of the verifying key and the last byte of the B
+        // query in G2
+        let offsets = [11, 12, 12 + 32, 84, ParameterHeader::SIZE + 1, v.len() - 1];
+        for &offset in &offsets {
+            let corrupted = corrupt(offset);
+            assert!(Parameters::<Bls12>::read(&corrupted[..], false).is_err());
+        }
+
+        // A truncated file
+        assert!(Parameters::<Bls12>::read(&v[..v.len() - 1], false).is_err());
+
+        let path = std::env::temp_dir().join(format!(
+            "bellperson-parameter-header-{}",
+            std::process::id()
+        ));
+        let map = |bytes: &[u8]| {
+            fs::write(&path, bytes).unwrap();
+            unsafe { MmapOptions::new().map(&File::open(&path).unwrap()).unwrap() }
+        };
+
+        assert!(Parameters::<Bls12>::read_mmap(&map(&v), true).unwrap() == params);
+        assert!(Parameters::<Bls12>::read_mmap(&map(legacy), true).unwrap() == params);
+        assert!(Parameters::<Bls12>::build_mapped_parameters(path.clone(), true).is_ok());
+
+        for &offset in &offsets {
+            let corrupted = corrupt(offset);
+            assert!(Parameters::<Bls12>::read_mmap(&map(&corrupted), false).is_err());
+            assert!(Parameters::<Bls12>::build_mapped_parameters(path.clone(), true).is_err());
+        }
+
+        // Digests are only checked when building checked mapped parameters
+        map(&corrupt(v.len() - 1));
+        assert!(Parameters::<Bls12>::build_mapped_parameters(path.clone(), false).is_ok());
+
+        let truncated = &v[..v.len() - 1];
+        assert!(Parameters::<Bls12>::read_mmap(&map(truncated), false).is_err());
+        assert!(Parameters::<Bls12>::build_mapped_parameters(path.clone(), false).is_err());
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn parallel_checked_loading() {
+        use std::fs;
+
+        struct MySillyCircuit<E: Engine> {
+            a: Option<E::Fr>,
+            b: Option<E::Fr>,
+        }
+
+        impl<E: Engine> Circuit<E> for MySillyCircuit<E> {
+            fn synthesize<CS: ConstraintSystem<E>>(
+                self,
+                cs: &mut CS,
+            ) -> Result<(), SynthesisError> {
+                let a = cs.alloc(|| "a", || self.a.ok_or(SynthesisError::AssignmentMissing))?;
+                let b = cs.alloc(|| "b", || self.b.ok_or(SynthesisError::AssignmentMissing))?;
+                let c = cs.alloc_input(
+                    || "c",
+                    || {
+                        let mut a = self.a.ok_or(SynthesisError::AssignmentMissing)?;
+                        let b = self.b.ok_or(SynthesisError::AssignmentMissing)?;
+
+                        a.mul_assign(&b);
+                        Ok(a)
+                    },
+                )?;
+
+                cs.enforce(|| "a*b=c", |lc| lc + a, |lc| lc + b, |lc| lc + c);
+
+                Ok(())
+            }
+        }
+
+        let rng = &mut thread_rng();
+
+        let params =
+            generate_random_parameters::<Bls12, _, _>(MySillyCircuit { a: None, b: None }, rng)
+                .unwrap();
+        assert!(params.h.len() >= 3);
+
+        // Without a header, so that only the points themselves are checked
+        let mut v = vec![];
+        params.write(&mut v).unwrap();
+
+        let mut vk = vec![];
+        params.vk.write(&mut vk).unwrap();
+        let g1_len = mem::size_of::<<<Bls12 as Engine>::G1Affine as CurveAffine>::Uncompressed>();
+
+        // Move the x coordinates of the first and third points of the H
+        // query off the curve
+        for &i in &[2, 0] {
+            v[vk.len() + 4 + i * g1_len + 47] ^= 1;
+        }
+        let expected = "invalid point 0 of the h query";
+
+        let err = Parameters::<Bls12>::read(&v[..], true).err().unwrap();
+        assert!(err.to_string().starts_with(expected));
+
+        let path =
+            std::env::temp_dir().join(format!("bellperson-checked-loading-{}", std::process::id()));
+        fs::write(&path, &v).unwrap();
+        let mmap = unsafe { MmapOptions::new().map(&File::open(&path).unwrap()).unwrap() };
+
+        let err = Parameters::<Bls12>::read_mmap(&mmap, true).err().unwrap();
+        assert!(err.to_string().starts_with(expected));
+
+        let mapped = Parameters::<Bls12>::build_mapped_parameters(path.clone(), true).unwrap();
+        let err = (&mapped).get_h(0).err().unwrap();
+        assert!(err.to_string().contains(expected));
+        assert!((&mapped).get_l(0).is_ok());
+
+        // The same points are found invalid on the pool of a given worker
+        let worker = Worker::with_num_threads(2);
+        let err = Parameters::<Bls12>::read_with_worker(&v[..], true, &worker)
+            .err()
+            .unwrap();
+        assert!(err.to_string().starts_with(expected));
+        let err = Parameters::<Bls12>::read_mmap_with_worker(&mmap, true, &worker)
+            .err()
+            .unwrap();
+        assert!(err.to_string().starts_with(expected));
+        let mapped =
+            Parameters::<Bls12>::build_mapped_parameters_with_worker(path.clone(), true, &worker)
+                .unwrap();
+        let err = (&mapped).get_h(0).err().unwrap();
+        assert!(err.to_string().contains(expected));
+        assert!((&mapped).get_l(0).is_ok());
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn sharded_parameters() {
+        use std::fs;
+
+        struct MySillyCircuit<E: Engine> {
+            a: Option<E::Fr>,
+            b: Option<E::Fr>,
+        }
+
+        impl<E: Engine> Circuit<E> for MySillyCircuit<E> {
+            fn synthesize<CS: ConstraintSystem<E>>(
+                self,
+                cs: &mut CS,
+            ) -> Result<(), SynthesisError> {
+                let a = cs.alloc(|| "a", || self.a.ok_or(SynthesisError::AssignmentMissing))?;
+                let b = cs.alloc(|| "b", || self.b.ok_or(SynthesisError::AssignmentMissing))?;
+                let c = cs.alloc_input(
+                    || "c",
+                    || {
+                        let mut a = self.a.ok_or(SynthesisError::AssignmentMissing)?;
+                        let b = self.b.ok_or(SynthesisError::AssignmentMissing)?;
+
+                        a.mul_assign(&b);
+                        Ok(a)
+                    },
+                )?;
+
+                cs.enforce(|| "a*b=c", |lc| lc + a, |lc| lc + b, |lc| lc + c);
+
+                Ok(())
+            }
+        }
+
+        let rng = &mut thread_rng();
+
+        let params =
+            generate_random_parameters::<Bls12, _, _>(MySillyCircuit { a: None, b: None }, rng)
+                .unwrap();
+        let pvk = prepare_verifying_key::<Bls12>(&params.vk);
+
+        let dir = std::env::temp_dir().join(format!("bellperson-sharded-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let param_file = dir.join("params");
+        params.write(File::create(&param_file).unwrap()).unwrap();
+
+        // Two points of G1 to a shard
+        let g1_len = mem::size_of::<<<Bls12 as Engine>::G1Affine as CurveAffine>::Uncompressed>();
+        let manifest = dir.join("params.manifest");
+        let split = split_parameters::<Bls12>(&param_file, &manifest, 2 * g1_len + 1).unwrap();
+        assert_eq!(split.h.len(), (params.h.len() + 1) / 2);
+        assert!(split.h.iter().all(|shard| shard.len <= 2));
+        assert_eq!(split.b_g2.len(), params.b_g2.len());
+
+        let mut sharded = ShardedParameters::<Bls12>::open(&manifest, true).unwrap();
+        assert!(sharded.vk == params.vk);
+        assert_eq!(sharded.h, split.h);
+        assert!((&sharded).get_h(0).unwrap().0 == params.h);
+        let (b_g2, _) = (&sharded).get_b_g2(0, 0).unwrap();
+        assert!(b_g2.0 == params.b_g2);
+
+        let a = Fr::random(rng);
+        let b = Fr::random(rng);
+        let mut c = a;
+        c.mul_assign(&b);
+        let proof = create_random_proof(
+            MySillyCircuit {
+                a: Some(a),
+                b: Some(b),
+            },
+            &sharded,
+            rng,
+        )
+        .unwrap();
+        assert!(verify_proof(&pvk, &proof, &[c]).unwrap());
+
+        // Shards can be moved, as long as the manifest follows them
+        let moved = dir.join("moved");
+        fs::create_dir_all(&moved).unwrap();
+        for shard in &mut sharded.l {
+            let path = moved.join(shard.path.file_name().unwrap());
+            fs::rename(&shard.path, &path).unwrap();
+            shard.path = path;
+        }
+        sharded
+            .write_manifest(File::create(&manifest).unwrap())
+            .unwrap();
+        let sharded = ShardedParameters::<Bls12>::open(&manifest, true).unwrap();
+        assert!((&sharded).get_l(0).unwrap().0 == params.l);
+
+        // Queries are read the same on the pool of a given worker
+        let worker = Worker::with_num_threads(2);
+        let on_worker =
+            ShardedParameters::<Bls12>::open_with_worker(&manifest, true, &worker).unwrap();
+        assert!((&on_worker).get_l(0).unwrap().0 == params.l);
+
+        // A corrupted shard fails its digest
+        let mut bytes = fs::read(&sharded.a[0].path).unwrap();
+        bytes[0] ^= 1;
+        fs::write(&sharded.a[0].path, &bytes).unwrap();
+        assert!((&sharded).get_a(0, 0).is_err());
+
+        // As does a missing one
+        fs::remove_file(&sharded.b_g1[0].path).unwrap();
+        assert!((&sharded).get_b_g1(0, 0).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn precomputed_parameters() {
+        struct MySillyCircuit<E: Engine> {
+            a: Option<E::Fr>,
+            b: Option<E::Fr>,
+        }
+
+        impl<E: Engine> Circuit<E> for MySillyCircuit<E> {
+            fn synthesize<CS: ConstraintSystem<E>>(
+                self,
+                cs: &mut CS,
+            ) -> Result<(), SynthesisError> {
+                let a = cs.alloc(|| "a", || self.a.ok_or(SynthesisError::AssignmentMissing))?;
+                let b = cs.alloc(|| "b", || self.b.ok_or(SynthesisError::AssignmentMissing))?;
+                let c = cs.alloc_input(
+                    || "c",
+                    || {
+                        let mut a = self.a.ok_or(SynthesisError::AssignmentMissing)?;
+                        let b = self.b.ok_or(SynthesisError::AssignmentMissing)?;
+
+                        a.mul_assign(&b);
+                        Ok(a)
+                    },
+                )?;
+
+                cs.enforce(|| "a*b=c", |lc| lc + a, |lc| lc + b, |lc| lc + c);
+
+                Ok(())
+            }
+        }
+
+        let rng = &mut thread_rng();
+        let pool = crate::multicore::Worker::new();
+
+        let params =
+            generate_random_parameters::<Bls12, _, _>(MySillyCircuit { a: None, b: None }, rng)
+                .unwrap();
+        let pvk = prepare_verifying_key::<Bls12>(&params.vk);
+
+        let config = PrecomputationConfig {
+            h: 4,
+            l: 1,
+            a: 2,
+            b_g1: 3,
+            b_g2: 8,
+        };
+        let precomputed = PrecomputedParameters::new(&pool, &params, config);
+
+        let mut v = vec![];
+        precomputed.write(&mut v).unwrap();
+
+        let de_precomputed =
+            PrecomputedParameters::read(&pool, params.vk.clone(), &v[..], true).unwrap();
+        assert_eq!(de_precomputed.config(), config);
+        assert_eq!(de_precomputed.h.table(), precomputed.h.table());
+        assert_eq!(de_precomputed.b_g2.table(), precomputed.b_g2.table());
+        assert_eq!(de_precomputed.a.bases(), &params.a[..]);
+
+        // A truncated file is rejected
+        assert!(
+            PrecomputedParameters::read(&pool, params.vk.clone(), &v[..v.len() - 1], false)
+                .is_err()
+        );
+
+        // An unsupported number of multiples is rejected before any point
+        // is read
+        for &multiples in &[0u8, crate::multiexp::MAX_PRECOMPUTED_MULTIPLES as u8 + 1] {
+            let mut header = v[..32 + 8].to_vec();
+            header[32 + 3] = multiples;
+            match PrecomputedParameters::read(&pool, params.vk.clone(), &header[..], false) {
+                Err(e) => assert_eq!(e.kind(), std::io::ErrorKind::InvalidData),
+                Ok(_) => panic!("expected the number of multiples to be rejected"),
+            }
+        }
+
+        // Points are checked when reading checked multiples
+        let mut corrupted = v.clone();
+        corrupted[32 + 8 + 47] ^= 1;
+        let err = PrecomputedParameters::read(&pool, params.vk.clone(), &corrupted[..], true)
+            .err()
+            .unwrap();
+        assert!(err
+            .to_string()
+            .starts_with("invalid point 0 of the h query"));
+
+        // So are multiples computed from other parameters
+        let other_params =
+            generate_random_parameters::<Bls12, _, _>(MySillyCircuit { a: None, b: None }, rng)
+                .unwrap();
+        match PrecomputedParameters::read(&pool, other_params.vk, &v[..], false) {
+            Err(e) => assert_eq!(e.kind(), std::io::ErrorKind::InvalidData),
+            Ok(_) => panic!("expected the multiples to be rejected"),
+        }
+
+        for _ in 0..10 {
+            let a = Fr::random(rng);
+            let b = Fr::random(rng);
+            let mut c = a;
+            c.mul_assign(&b);
+
+            let proof = create_random_proof(
+                MySillyCircuit {
+                    a: Some(a),
+                    b: Some(b),
+                },
+                &de_precomputed,
+                rng,
+            )
+            .unwrap();
+
+            assert!(verify_proof(&pvk, &proof, &[c]).unwrap());
+            assert!(!verify_proof(&pvk, &proof, &[a]).unwrap());
+        }
+    }
+
+    #[test]
+    fn prover_context() {
+        use crate::multicore::Worker;
+        use std::sync::Mutex;
+
+        #[derive(Clone)]
+        struct MySillyCircuit<E: Engine> {
+            a: Option<E::Fr>,
+            b: Option<E::Fr>,
+        }
+
+        impl<E: Engine> Circuit<E> for MySillyCircuit<E> {
+            fn synthesize<CS: ConstraintSystem<E>>(
+                self,
+                cs: &mut CS,
+            ) -> Result<(), SynthesisError> {
+                let a = cs.alloc(|| "a", || self.a.ok_or(SynthesisError::AssignmentMissing))?;
+                let b = cs.alloc(|| "b", || self.b.ok_or(SynthesisError::AssignmentMissing))?;
+                let c = cs.alloc_input(
+                    || "c",
+                    || {
+                        let mut a = self.a.ok_or(SynthesisError::AssignmentMissing)?;
+                        let b = self.b.ok_or(SynthesisError::AssignmentMissing)?;
+
+                        a.mul_assign(&b);
+                        Ok(a)
+                    },
+                )?;
+
+                cs.enforce(|| "a*b=c", |lc| lc + a, |lc| lc + b, |lc| lc + c);
+
+                Ok(())
+            }
+        }
+
+        let rng = &mut thread_rng();
+        let worker = Worker::new();
+
+        let params =
+            generate_random_parameters::<Bls12, _, _>(MySillyCircuit { a: None, b: None }, rng)
+                .unwrap();
+        let pvk = prepare_verifying_key::<Bls12>(&params.vk);
+
+        let a = Fr::random(rng);
+        let b = Fr::random(rng);
+        let mut c = a;
+        c.mul_assign(&b);
+        let circuits = vec![
+            MySillyCircuit {
+                a: Some(a),
+                b: Some(b)
+            };
+            3
+        ];
+
+        // Every phase is reported for every circuit
+        let reports = Arc::new(Mutex::new(vec![]));
+        let context = {
+            let reports = reports.clone();
+            ProverContext::new().with_progress(move |phase, done, total| {
+                reports.lock().unwrap().push((phase, done, total));
+            })
+        };
+
+        let proofs = create_random_proof_batch_with_context(
+            &context,
+            &worker,
+            circuits.clone(),
+            &params,
+            rng,
+            false,
+        )
+        .unwrap();
+        for proof in &proofs {
+            assert!(verify_proof(&pvk, proof, &[c]).unwrap());
+        }
+
+        let reports = reports.lock().unwrap();
+        for &phase in &[
+            ProverPhase::Synthesis,
+            ProverPhase::Fft,
+            ProverPhase::MultiexpH,
+            ProverPhase::MultiexpL,
+            ProverPhase::MultiexpA,
+            ProverPhase::MultiexpBG1,
+            ProverPhase::MultiexpBG2,
+        ] {
+            let mut done = reports
+                .iter()
+                .filter(|report| report.0 == phase)
+                .map(|report| {
+                    assert_eq!(report.2, 3);
+                    report.1
+                })
+                .collect::<Vec<_>>();
+            done.sort();
+            assert_eq!(done, vec![1, 2, 3]);
+        }
+
+        // A cancelled context stops the prover before it starts
+        let context = ProverContext::new();
+        context.cancel();
+        match create_random_proof_batch_with_context(
+            &context,
+            &worker,
+            circuits.clone(),
+            &params,
+            rng,
+            false,
+        ) {
+            Err(SynthesisError::Cancelled) => {}
+            _ => panic!("cancelled prover did not stop"),
+        }
+
+        // As does cancelling it while proving
+        let cancellation = crate::multicore::CancellationToken::new();
+        let context = {
+            let cancellation = cancellation.clone();
+            ProverContext::new()
+                .with_cancellation(cancellation.clone())
+                .with_progress(move |phase, _, _| {
+                    if phase == ProverPhase::Fft {
+                        cancellation.cancel();
+                    }
+                })
+        };
+        match create_random_proof_batch_with_context(
+            &context, &worker, circuits, &params, rng, false,
+        ) {
+            Err(SynthesisError::Cancelled) => {}
+            _ => panic!("cancelled prover did not stop"),
+        }
+        assert!(context.is_cancelled());
+    }
+
+    #[test]
+    fn streamed_parameters() {
+        use crate::multicore::Worker;
+        use groupy::CurveProjective;
+        use std::fs::{self, OpenOptions};
+
+        struct SquaringChain<E: Engine> {
+            x: Option<E::Fr>,
+            length: usize,
+        }
+
+        impl<E: Engine> Circuit<E> for SquaringChain<E> {
+            fn synthesize<CS: ConstraintSystem<E>>(
+                self,
+                cs: &mut CS,
+            ) -> Result<(), SynthesisError> {
+                let mut x_val = self.x;
+                let mut x = cs.alloc(|| "x", || x_val.ok_or(SynthesisError::AssignmentMissing))?;
+
+                for i in 0..self.length {
+                    let y_val = x_val.map(|mut x| {
+                        x.square();
+                        x
+                    });
+                    let y = cs.alloc(
+                        || format!("y {}", i),
+                        || y_val.ok_or(SynthesisError::AssignmentMissing),
+                    )?;
+                    cs.enforce(
+                        || format!("square {}", i),
+                        |lc| lc + x,
+                        |lc| lc + x,
+                        |lc| lc + y,
+                    );
+
+                    // A variable only in C, leaving holes in the A and B
+                    // queries
+                    let z = cs.alloc(
+                        || format!("z {}", i),
+                        || {
+                            let mut z = x_val.ok_or(SynthesisError::AssignmentMissing)?;
+                            z.add_assign(&y_val.ok_or(SynthesisError::AssignmentMissing)?);
+                            Ok(z)
+                        },
+                    )?;
+                    cs.enforce(
+                        || format!("sum {}", i),
+                        |lc| lc + x + y,
+                        |lc| lc + CS::one(),
+                        |lc| lc + z,
+                    );
+
+                    x = y;
+                    x_val = y_val;
+                }
+
+                let out =
+                    cs.alloc_input(|| "out", || x_val.ok_or(SynthesisError::AssignmentMissing))?;
+                cs.enforce(|| "out", |lc| lc + x, |lc| lc + CS::one(), |lc| lc + out);
+
+                Ok(())
+            }
+        }
+
+        let rng = &mut thread_rng();
+        let worker = Worker::new();
+
+        let g1 = <Bls12 as Engine>::G1::random(rng);
+        let g2 = <Bls12 as Engine>::G2::random(rng);
+        let alpha = Fr::random(rng);
+        let beta = Fr::random(rng);
+        let gamma = Fr::random(rng);
+        let delta = Fr::random(rng);
+        let tau = Fr::random(rng);
+
+        let params = generate_parameters::<Bls12, _>(
+            &worker,
+            SquaringChain {
+                x: None,
+                length: 10,
+            },
+            g1,
+            g2,
+            alpha,
+            beta,
+            gamma,
+            delta,
+            tau,
+        )
+        .unwrap();
+        let mut expected = vec![];
+        params.write_with_header(&mut expected).unwrap();
+
+        // The streamed file is the same as the one written from memory,
+        // whatever the size of the chunks
+        for &chunk_size in &[1, 3, 8, 1 << 10] {
+            let mut v = io::Cursor::new(vec![]);
+            let vk = generator::generate_parameters_in_chunks::<Bls12, _, _>(
+                &worker,
+                SquaringChain {
+                    x: None,
+                    length: 10,
+                },
+                g1,
+                g2,
+                alpha,
+                beta,
+                gamma,
+                delta,
+                tau,
+                chunk_size,
+                &mut v,
+            )
+            .unwrap();
+
+            assert!(vk == params.vk);
+            assert!(v.into_inner() == expected);
+        }
+
+        let path = std::env::temp_dir().join(format!(
+            "bellperson-streamed-parameters-{}",
+            std::process::id()
+        ));
+        let vk = generate_parameters_to_writer::<Bls12, _, _>(
+            &worker,
+            SquaringChain {
+                x: None,
+                length: 10,
+            },
+            g1,
+            g2,
+            alpha,
+            beta,
+            gamma,
+            delta,
+            tau,
+            OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(&path)
+                .unwrap(),
+        )
+        .unwrap();
+        let mapped = Parameters::build_mapped_parameters(path.clone(), true).unwrap();
+        let pvk = prepare_verifying_key::<Bls12>(&vk);
+
+        let x = Fr::random(rng);
+        let mut out = x;
+        for _ in 0..10 {
+            out.square();
+        }
+
+        let proof = create_random_proof(
+            SquaringChain {
+                x: Some(x),
+                length: 10,
+            },
+            &mapped,
+            rng,
+        )
+        .unwrap();
+        assert!(verify_proof(&pvk, &proof, &[out]).unwrap());
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn cached_mapped_parameters() {
+        use std::fs::{self, OpenOptions};
+        use std::mem;
+
+        struct MySillyCircuit<E: Engine> {
+            a: Option<E::Fr>,
+            b: Option<E::Fr>,
+        }
+
+        impl<E: Engine> Circuit<E> for MySillyCircuit<E> {
+            fn synthesize<CS: ConstraintSystem<E>>(
+                self,
+                cs: &mut CS,
+            ) -> Result<(), SynthesisError> {
+                let a = cs.alloc(|| "a", || self.a.ok_or(SynthesisError::AssignmentMissing))?;
+                let b = cs.alloc(|| "b", || self.b.ok_or(SynthesisError::AssignmentMissing))?;
+                let c = cs.alloc_input(
+                    || "c",
+                    || {
+                        let mut a = self.a.ok_or(SynthesisError::AssignmentMissing)?;
+                        let b = self.b.ok_or(SynthesisError::AssignmentMissing)?;
+
+                        a.mul_assign(&b);
+                        Ok(a)
+                    },
+                )?;
+
+                cs.enforce(|| "a*b=c", |lc| lc + a, |lc| lc + b, |lc| lc + c);
+
+                Ok(())
+            }
+        }
+
+        let rng = &mut thread_rng();
+
+        let params =
+            generate_random_parameters::<Bls12, _, _>(MySillyCircuit { a: None, b: None }, rng)
+                .unwrap();
+        let pvk = prepare_verifying_key::<Bls12>(&params.vk);
+
+        let path = std::env::temp_dir().join(format!(
+            "bellperson-cached-parameters-{}",
+            std::process::id()
+        ));
+        params.write(File::create(&path).unwrap()).unwrap();
+
+        let g1_size = mem::size_of::<<Bls12 as Engine>::G1Affine>();
+        let g2_size = mem::size_of::<<Bls12 as Engine>::G2Affine>();
+        let total = (params.h.len() + params.l.len() + params.a.len() + params.b_g1.len())
+            * g1_size
+            + params.b_g2.len() * g2_size;
+
+        let prove = |params: &MappedParameters<Bls12>, rng: &mut _| {
+            let a = Fr::random(rng);
+            let b = Fr::random(rng);
+            let mut c = a;
+            c.mul_assign(&b);
+
+            let proof = create_random_proof(
+                MySillyCircuit {
+                    a: Some(a),
+                    b: Some(b),
+                },
+                params,
+                rng,
+            )
+            .unwrap();
+            assert!(verify_proof(&pvk, &proof, &[c]).unwrap());
+        };
+
+        let mapped = Parameters::<Bls12>::build_mapped_parameters(path.clone(), true).unwrap();
+        assert_eq!(mapped.cached_bytes(), 0);
+
+        // Every query fits in the budget
+        let cached = mapped.clone().with_cache(CacheConfig {
+            memory_budget: total,
+            eviction: EvictionPolicy::LeastRecentlyUsed,
+        });
+        for _ in 0..3 {
+            prove(&cached, rng);
+            assert_eq!(cached.cached_bytes(), total);
+        }
+
+        // The queries are shared across proofs
+        let h = (&cached).get_h(0).unwrap().0;
+        assert!(Arc::ptr_eq(&h, &(&cached).get_h(0).unwrap().0));
+        assert_eq!(&h[..], &params.h[..]);
+
+        // Only some of the queries fit in a smaller budget
+        for &eviction in &[
+            EvictionPolicy::LeastRecentlyUsed,
+            EvictionPolicy::LargestFirst,
+        ] {
+            let budget = params.b_g2.len() * g2_size + g1_size;
+            let cached = mapped.clone().with_cache(CacheConfig {
+                memory_budget: budget,
+                eviction,
+            });
+            for _ in 0..3 {
+                prove(&cached, rng);
+                assert!(cached.cached_bytes() > 0);
+                assert!(cached.cached_bytes() <= budget);
+            }
+        }
+
+        // Queries over the whole budget are not cached
+        let uncached = mapped.clone().with_cache(CacheConfig {
+            memory_budget: g1_size - 1,
+            eviction: EvictionPolicy::LargestFirst,
+        });
+        prove(&uncached, rng);
+        assert_eq!(uncached.cached_bytes(), 0);
+
+        // Changing the file drops the cache
+        OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(&[0])
+            .unwrap();
+        (&cached).get_h(0).unwrap();
+        assert_eq!(cached.cached_bytes(), params.h.len() * g1_size);
+        prove(&cached, rng);
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn async_proving() {
+        struct MySillyCircuit<E: Engine> {
+            a: Option<E::Fr>,
+            b: Option<E::Fr>,
+        }
+
+        impl<E: Engine> Circuit<E> for MySillyCircuit<E> {
+            fn synthesize<CS: ConstraintSystem<E>>(
+                self,
+                cs: &mut CS,
+            ) -> Result<(), SynthesisError> {
+                let a = cs.alloc(|| "a", || self.a.ok_or(SynthesisError::AssignmentMissing))?;
+                let b = cs.alloc(|| "b", || self.b.ok_or(SynthesisError::AssignmentMissing))?;
+                let c = cs.alloc_input(
+                    || "c",
+                    || {
+                        let mut a = self.a.ok_or(SynthesisError::AssignmentMissing)?;
+                        let b = self.b.ok_or(SynthesisError::AssignmentMissing)?;
+
+                        a.mul_assign(&b);
+                        Ok(a)
+                    },
+                )?;
+
+                cs.enforce(|| "a*b=c", |lc| lc + a, |lc| lc + b, |lc| lc + c);
+
+                Ok(())
+            }
+        }
+
+        let rng = &mut thread_rng();
+
+        let params = Arc::new(
+            generate_random_parameters::<Bls12, _, _>(MySillyCircuit { a: None, b: None }, rng)
+                .unwrap(),
+        );
+        let pvk = prepare_verifying_key::<Bls12>(&params.vk);
+
+        let inputs = (0..3)
+            .map(|_| (Fr::random(rng), Fr::random(rng)))
+            .collect::<Vec<_>>();
+        let circuits = || {
+            inputs
+                .iter()
+                .map(|&(a, b)| MySillyCircuit {
+                    a: Some(a),
+                    b: Some(b),
+                })
+                .collect::<Vec<_>>()
+        };
+        let product = |&(a, b): &(Fr, Fr)| {
+            let mut c = a;
+            c.mul_assign(&b);
+            c
+        };
+
+        let worker = Worker::new();
+        let proof = crate::multicore::block_on(create_random_proof_async(
+            &worker,
+            circuits().remove(0),
+            params.clone(),
+            rng,
+        ))
+        .unwrap();
+        assert!(verify_proof(&pvk, &proof, &[product(&inputs[0])]).unwrap());
+
+        let r = inputs.iter().map(|_| Fr::random(rng)).collect();
+        let s = inputs.iter().map(|_| Fr::random(rng)).collect();
+        let proofs =
+            crate::multicore::block_on(create_proof_batch_async(&worker, circuits(), params, r, s))
+                .unwrap();
+        assert_eq!(proofs.len(), inputs.len());
+        for (proof, input) in proofs.iter().zip(inputs.iter()) {
+            assert!(verify_proof(&pvk, proof, &[product(input)]).unwrap());
+        }
+    }
+
+    #[test]
+    fn memory_budget() {
+        struct MySillyCircuit<E: Engine> {
+            a: Option<E::Fr>,
+            b: Option<E::Fr>,
+        }
+
+        impl<E: Engine> Circuit<E> for MySillyCircuit<E> {
+            fn synthesize<CS: ConstraintSystem<E>>(
+                self,
+                cs: &mut CS,
+            ) -> Result<(), SynthesisError> {
+                let a = cs.alloc(|| "a", || self.a.ok_or(SynthesisError::AssignmentMissing))?;
+                let b = cs.alloc(|| "b", || self.b.ok_or(SynthesisError::AssignmentMissing))?;
+                let c = cs.alloc_input(
+                    || "c",
+                    || {
+                        let mut a = self.a.ok_or(SynthesisError::AssignmentMissing)?;
+                        let b = self.b.ok_or(SynthesisError::AssignmentMissing)?;
+
+                        a.mul_assign(&b);
+                        Ok(a)
+                    },
+                )?;
+
+                cs.enforce(|| "a*b=c", |lc| lc + a, |lc| lc + b, |lc| lc + c);
+
+                Ok(())
+            }
+        }
+
+        let rng = &mut thread_rng();
+
+        let params =
+            generate_random_parameters::<Bls12, _, _>(MySillyCircuit { a: None, b: None }, rng)
+                .unwrap();
+        let pvk = prepare_verifying_key::<Bls12>(&params.vk);
+
+        let estimate = MemoryEstimate::for_params::<Bls12, _>(&&params).unwrap();
+        assert_eq!(estimate.queries, 0);
+        assert!(estimate.per_circuit() > 0);
+
+        let inputs = (0..5)
+            .map(|_| (Fr::random(rng), Fr::random(rng)))
+            .collect::<Vec<_>>();
+        let circuits = || {
+            inputs
+                .iter()
+                .map(|&(a, b)| MySillyCircuit {
+                    a: Some(a),
+                    b: Some(b),
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // Two circuits fit in the budget, so the batch is proven in three parts
+        let context = ProverContext::new().with_memory_budget(estimate.peak(2) + 1);
+        let proofs = create_random_proof_batch_with_context(
+            &context,
+            &Worker::new(),
+            circuits(),
+            &params,
+            rng,
+            false,
+        )
+        .unwrap();
+        assert_eq!(proofs.len(), inputs.len());
+        for (proof, &(a, b)) in proofs.iter().zip(inputs.iter()) {
+            let mut c = a;
+            c.mul_assign(&b);
+            assert!(verify_proof(&pvk, proof, &[c]).unwrap());
+        }
+
+        let report = context.memory_report().unwrap();
+        assert_eq!(report.estimate, Some(estimate));
+        assert_eq!(report.parallelism, 2);
+        assert!(report.high_water_mark > 0);
+        assert!(report.high_water_mark <= estimate.peak(2));
+
+        // Without a budget, the whole batch is proven at once
+        let context = ProverContext::new();
+        create_random_proof_batch_with_context(
+            &context,
+            &Worker::new(),
+            circuits(),
+            &params,
+            rng,
+            false,
+        )
+        .unwrap();
+        assert_eq!(context.memory_report().unwrap().parallelism, inputs.len());
+
+        // Not even a single circuit fits, which fails before proving
+        let budget = estimate.per_circuit() - 1;
+        let context = ProverContext::new().with_memory_budget(budget);
+        match create_random_proof_batch_with_context(
+            &context,
+            &Worker::new(),
+            circuits(),
+            &params,
+            rng,
+            false,
+        ) {
+            Err(SynthesisError::MemoryBudgetExceeded { needed, budget: b }) => {
+                assert_eq!(needed, estimate.per_circuit());
+                assert_eq!(b, budget);
+            }
+            _ => panic!("expected the memory budget to be exceeded"),
+        }
+        assert!(context.memory_report().is_none());
+    }
+
+    #[test]
+    fn reference_backend() {
+        use crate::gpu::{lock_files_test_guard, ReferenceBackend, ReferenceDevice};
+
+        struct MySillyCircuit<E: Engine> {
+            a: Option<E::Fr>,
+            b: Option<E::Fr>,
+        }
+
+        impl<E: Engine> Circuit<E> for MySillyCircuit<E> {
+            fn synthesize<CS: ConstraintSystem<E>>(
+                self,
+                cs: &mut CS,
+            ) -> Result<(), SynthesisError> {
+                let a = cs.alloc(|| "a", || self.a.ok_or(SynthesisError::AssignmentMissing))?;
+                let b = cs.alloc(|| "b", || self.b.ok_or(SynthesisError::AssignmentMissing))?;
+                let c = cs.alloc_input(
+                    || "c",
+                    || {
+                        let mut a = self.a.ok_or(SynthesisError::AssignmentMissing)?;
+                        let b = self.b.ok_or(SynthesisError::AssignmentMissing)?;
+
+                        a.mul_assign(&b);
+                        Ok(a)
+                    },
+                )?;
+
+                cs.enforce(|| "a*b=c", |lc| lc + a, |lc| lc + b, |lc| lc + c);
+
+                Ok(())
+            }
+        }
+
+        // Priority proofs take the priority lock when there are devices
+        let _guard = lock_files_test_guard();
+        let rng = &mut thread_rng();
+
+        let params =
+            generate_random_parameters::<Bls12, _, _>(MySillyCircuit { a: None, b: None }, rng)
+                .unwrap();
+        let pvk = prepare_verifying_key::<Bls12>(&params.vk);
+
+        let inputs = (0..3)
+            .map(|_| (Fr::random(rng), Fr::random(rng)))
+            .collect::<Vec<_>>();
+
+        let mut prove = |backend: &ReferenceBackend| {
+            let circuits = inputs
+                .iter()
+                .map(|&(a, b)| MySillyCircuit {
+                    a: Some(a),
+                    b: Some(b),
+                })
+                .collect::<Vec<_>>();
+            let r_s = inputs.iter().map(|_| Fr::random(rng)).collect();
+            let s_s = inputs.iter().map(|_| Fr::random(rng)).collect();
+
+            let proofs = create_proof_batch_with_backend(
+                backend,
+                &ProverContext::new(),
+                &Worker::new(),
+                circuits,
+                &params,
+                r_s,
+                s_s,
+                true,
+            )
+            .unwrap();
+            assert_eq!(proofs.len(), inputs.len());
+            for (proof, &(a, b)) in proofs.iter().zip(inputs.iter()) {
+                let mut c = a;
+                c.mul_assign(&b);
+                assert!(verify_proof(&pvk, proof, &[c]).unwrap());
+            }
+        };
+
+        // The multiexps are split in chunks the devices can take
+        let backend = ReferenceBackend::new(vec![ReferenceDevice::new(2), ReferenceDevice::new(3)])
+            .with_cpu_utilization(0.3);
+        prove(&backend);
+        assert!(backend.devices.iter().all(|device| device.calls() > 0));
+        assert!(backend.devices.iter().all(|device| device.failures() == 0));
+
+        // Failing devices fall back to the CPU
+        let backend = ReferenceBackend::new(vec![
+            ReferenceDevice::new(2).fail_after(0),
+            ReferenceDevice::new(3).fail_after(0),
+        ]);
+        prove(&backend);
+        assert!(backend.devices.iter().any(|device| device.failures() > 0));
+    }
+
+    #[test]
+    fn batch_randomness_lengths() {
+        use crate::multicore::Worker;
+
+        #[derive(Clone)]
+        struct MySillyCircuit<E: Engine> {
+            a: Option<E::Fr>,
+            b: Option<E::Fr>,
+        }
+
+        impl<E: Engine> Circuit<E> for MySillyCircuit<E> {
+            fn synthesize<CS: ConstraintSystem<E>>(
+                self,
+                cs: &mut CS,
+            ) -> Result<(), SynthesisError> {
+                let a = cs.alloc(|| "a", || self.a.ok_or(SynthesisError::AssignmentMissing))?;
+                let b = cs.alloc(|| "b", || self.b.ok_or(SynthesisError::AssignmentMissing))?;
+                let c = cs.alloc_input(
+                    || "c",
+                    || {
+                        let mut a = self.a.ok_or(SynthesisError::AssignmentMissing)?;
+                        let b = self.b.ok_or(SynthesisError::AssignmentMissing)?;
+
+                        a.mul_assign(&b);
+                        Ok(a)
+                    },
+                )?;
+
+                cs.enforce(|| "a*b=c", |lc| lc + a, |lc| lc + b, |lc| lc + c);
+
+                Ok(())
+            }
+        }
+
+        let rng = &mut thread_rng();
+        let worker = Worker::new();
+
+        let params =
+            generate_random_parameters::<Bls12, _, _>(MySillyCircuit { a: None, b: None }, rng)
+                .unwrap();
+
+        let circuits = vec![
+            MySillyCircuit {
+                a: Some(Fr::random(rng)),
+                b: Some(Fr::random(rng))
+            };
+            3
+        ];
+        let randomness =
+            |len: usize, rng: &mut _| (0..len).map(|_| Fr::random(rng)).collect::<Vec<_>>();
+
+        // There must be an r and an s for every circuit
+        for &(r_len, s_len) in &[(2, 3), (3, 2), (4, 4), (0, 0)] {
+            match create_proof_batch_with_context::<Bls12, _, _>(
+                &ProverContext::new(),
+                &worker,
+                circuits.clone(),
+                &params,
+                randomness(r_len, rng),
+                randomness(s_len, rng),
+                false,
+            ) {
+                Err(SynthesisError::IncompatibleLengths) => (),
+                _ => panic!("mismatched randomness should be rejected"),
+            }
+        }
+
+        let proofs = create_proof_batch_with_context::<Bls12, _, _>(
+            &ProverContext::new(),
+            &worker,
+            circuits,
+            &params,
+            randomness(3, rng),
+            randomness(3, rng),
+            false,
+        )
+        .unwrap();
+        assert_eq!(proofs.len(), 3);
+    }
 }
@@ -0,0 +1,229 @@
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use groupy::CurveAffine;
+use paired::Engine;
+
+use crate::multicore::Worker;
+use crate::multiexp::{PrecomputedBases, MAX_PRECOMPUTED_MULTIPLES};
+use crate::SynthesisError;
+
+use std::cmp;
+use std::io::{self, Read, Write};
+use std::mem;
+
+use super::header::{digest_written, Digest};
+use super::mapped_params::decode_points;
+use super::{CircuitShape, ParameterSource, Parameters, VerifyingKey, READ_CHUNK_SIZE};
+
+/// The number of multiples to precompute for every base of each query,
+/// including the base itself. A query takes that many times the disk space
+/// and memory of its bases, so `1` adds no overhead but does not speed up
+/// the query either.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PrecomputationConfig {
+    pub h: usize,
+    pub l: usize,
+    pub a: usize,
+    pub b_g1: usize,
+    pub b_g2: usize,
+}
+
+impl PrecomputationConfig {
+    /// Precomputes the same number of multiples for every query.
+    pub fn uniform(multiples: usize) -> Self {
+        PrecomputationConfig {
+            h: multiples,
+            l: multiples,
+            a: multiples,
+            b_g1: multiples,
+            b_g2: multiples,
+        }
+    }
+}
+
+/// Parameters whose bases come with precomputed multiples, which cut the
+/// number of windows in every multiexp of the prover.
+#[derive(Clone)]
+pub struct PrecomputedParameters<E: Engine> {
+    pub vk: VerifyingKey<E>,
+
+    pub h: PrecomputedBases<E::G1Affine>,
+    pub l: PrecomputedBases<E::G1Affine>,
+    pub a: PrecomputedBases<E::G1Affine>,
+    pub b_g1: PrecomputedBases<E::G1Affine>,
+    pub b_g2: PrecomputedBases<E::G2Affine>,
+}
+
+impl<E: Engine> PrecomputedParameters<E> {
+    pub fn new(pool: &Worker, params: &Parameters<E>, config: PrecomputationConfig) -> Self {
+        PrecomputedParameters {
+            vk: params.vk.clone(),
+            h: PrecomputedBases::new(pool, &params.h, config.h),
+            l: PrecomputedBases::new(pool, &params.l, config.l),
+            a: PrecomputedBases::new(pool, &params.a, config.a),
+            b_g1: PrecomputedBases::new(pool, &params.b_g1, config.b_g1),
+            b_g2: PrecomputedBases::new(pool, &params.b_g2, config.b_g2),
+        }
+    }
+
+    /// Writes the precomputed multiples. The verifying key is not written,
+    /// as it is read back from the parameters, but its digest is, so that
+    /// the multiples are only read back for the parameters they were
+    /// computed from.
+    pub fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_all(&vk_digest(&self.vk))?;
+        write_bases(&mut writer, &self.h)?;
+        write_bases(&mut writer, &self.l)?;
+        write_bases(&mut writer, &self.a)?;
+        write_bases(&mut writer, &self.b_g1)?;
+        write_bases(&mut writer, &self.b_g2)?;
+
+        Ok(())
+    }
+
+    /// Reads the precomputed multiples for parameters with the given
+    /// verifying key, decoding them in parallel on the pool of `pool`.
+    /// Fails if they were computed from other parameters.
+    pub fn read<R: Read>(
+        pool: &Worker,
+        vk: VerifyingKey<E>,
+        mut reader: R,
+        checked: bool,
+    ) -> io::Result<Self> {
+        let mut digest = Digest::default();
+        reader.read_exact(&mut digest)?;
+        if digest != vk_digest(&vk) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "precomputed multiples are for other parameters",
+            ));
+        }
+
+        Ok(PrecomputedParameters {
+            vk,
+            h: read_bases(pool, &mut reader, checked, "h")?,
+            l: read_bases(pool, &mut reader, checked, "l")?,
+            a: read_bases(pool, &mut reader, checked, "a")?,
+            b_g1: read_bases(pool, &mut reader, checked, "b_g1")?,
+            b_g2: read_bases(pool, &mut reader, checked, "b_g2")?,
+        })
+    }
+
+    /// Returns the configuration the multiples were computed with.
+    pub fn config(&self) -> PrecomputationConfig {
+        PrecomputationConfig {
+            h: self.h.precomputation().multiples,
+            l: self.l.precomputation().multiples,
+            a: self.a.precomputation().multiples,
+            b_g1: self.b_g1.precomputation().multiples,
+            b_g2: self.b_g2.precomputation().multiples,
+        }
+    }
+}
+
+/// Identifies the parameters multiples are computed from, as the digest of
+/// their verifying key as written.
+fn vk_digest<E: Engine>(vk: &VerifyingKey<E>) -> Digest {
+    digest_written(|w| vk.write(w))
+}
+
+fn write_bases<G: CurveAffine, W: Write>(
+    writer: &mut W,
+    bases: &PrecomputedBases<G>,
+) -> io::Result<()> {
+    writer.write_u32::<BigEndian>(bases.precomputation().multiples as u32)?;
+    writer.write_u32::<BigEndian>(bases.len() as u32)?;
+    for g in bases.table() {
+        writer.write_all(g.into_uncompressed().as_ref())?;
+    }
+
+    Ok(())
+}
+
+/// Reads the multiples of a query a chunk at a time, like `read_query`.
+fn read_bases<G: CurveAffine, R: Read>(
+    pool: &Worker,
+    reader: &mut R,
+    checked: bool,
+    query: &str,
+) -> io::Result<PrecomputedBases<G>> {
+    let point_len = mem::size_of::<G::Uncompressed>();
+
+    // The number of multiples is checked before the points are read
+    let multiples = reader.read_u32::<BigEndian>()? as usize;
+    if !(1..=MAX_PRECOMPUTED_MULTIPLES).contains(&multiples) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "unsupported number of precomputed multiples",
+        ));
+    }
+    let len = reader.read_u32::<BigEndian>()? as usize;
+    let total = len * multiples;
+
+    let mut table = vec![];
+    let mut bytes = vec![];
+    for start in (0..total).step_by(READ_CHUNK_SIZE) {
+        let end = cmp::min(start + READ_CHUNK_SIZE, total);
+        bytes.resize((end - start) * point_len, 0);
+        reader.read_exact(&mut bytes)?;
+
+        let bytes = &bytes;
+        table.extend(decode_points::<G, _>(
+            pool,
+            start..end,
+            checked,
+            query,
+            |i| &bytes[(i - start) * point_len..(i - start + 1) * point_len],
+        )?);
+    }
+
+    PrecomputedBases::from_table(table, multiples)
+}
+
+impl<'a, E: Engine> ParameterSource<E> for &'a PrecomputedParameters<E> {
+    type G1Builder = (PrecomputedBases<E::G1Affine>, usize);
+    type G2Builder = (PrecomputedBases<E::G2Affine>, usize);
+
+    fn get_vk(&mut self, _: usize) -> Result<VerifyingKey<E>, SynthesisError> {
+        Ok(self.vk.clone())
+    }
+
+    fn get_h(&mut self, _: usize) -> Result<Self::G1Builder, SynthesisError> {
+        Ok((self.h.clone(), 0))
+    }
+
+    fn get_l(&mut self, _: usize) -> Result<Self::G1Builder, SynthesisError> {
+        Ok((self.l.clone(), 0))
+    }
+
+    fn get_a(
+        &mut self,
+        num_inputs: usize,
+        _: usize,
+    ) -> Result<(Self::G1Builder, Self::G1Builder), SynthesisError> {
+        Ok(((self.a.clone(), 0), (self.a.clone(), num_inputs)))
+    }
+
+    fn get_b_g1(
+        &mut self,
+        num_inputs: usize,
+        _: usize,
+    ) -> Result<(Self::G1Builder, Self::G1Builder), SynthesisError> {
+        Ok(((self.b_g1.clone(), 0), (self.b_g1.clone(), num_inputs)))
+    }
+
+    fn get_b_g2(
+        &mut self,
+        num_inputs: usize,
+        _: usize,
+    ) -> Result<(Self::G2Builder, Self::G2Builder), SynthesisError> {
+        Ok(((self.b_g2.clone(), 0), (self.b_g2.clone(), num_inputs)))
+    }
+
+    fn shape(&self) -> Option<CircuitShape> {
+        Some(CircuitShape {
+            constraints: self.h.len() + 1,
+            inputs: self.vk.ic.len(),
+            aux: self.l.len(),
+        })
+    }
+}
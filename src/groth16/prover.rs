@@ -1,25 +1,28 @@
+use std::cmp;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 
 use ff::{Field, PrimeField};
-use futures::Future;
 use groupy::{CurveAffine, CurveProjective};
 use paired::Engine;
 use rand_core::RngCore;
 use rayon::prelude::*;
 
-use super::{ParameterSource, Proof};
-use crate::domain::{create_fft_kernel, EvaluationDomain, Scalar};
-use crate::gpu::LockedKernel;
-use crate::multicore::Worker;
-use crate::multiexp::{create_multiexp_kernel, multiexp, DensityTracker, FullDensity};
+use super::context::PhaseProgress;
+use super::memory::{vec_bytes, MemoryMeter};
+use super::{MemoryEstimate, MemoryReport, ParameterSource, Proof, ProverContext, ProverPhase};
+use crate::domain::{create_fft_kernel_with_backend, EvaluationDomain, Scalar};
+use crate::gpu::{ComputeBackend, DefaultBackend, LockedKernel, PriorityLock};
+use crate::multicore::{CancellationToken, Worker};
+use crate::multiexp::{
+    create_multiexp_kernel_with_backend, multiexp, DensityTracker, FullDensity, SourceBuilder,
+    SparseExponents,
+};
 use crate::{
     Circuit, ConstraintSystem, Index, LinearCombination, SynthesisError, Variable, BELLMAN_VERSION,
 };
 use log::info;
 
-#[cfg(feature = "gpu")]
-use crate::gpu::PriorityLock;
-
 fn eval<E: Engine>(
     lc: &LinearCombination<E>,
     mut input_density: Option<&mut DensityTracker>,
@@ -59,6 +62,9 @@ fn eval<E: Engine>(
 }
 
 struct ProvingAssignment<E: Engine> {
+    // Checked on every allocation, to stop long syntheses early
+    cancellation: CancellationToken,
+
     // Density of queries
     a_aux_density: DensityTracker,
     b_input_density: DensityTracker,
@@ -74,6 +80,12 @@ struct ProvingAssignment<E: Engine> {
     aux_assignment: Vec<E::Fr>,
 }
 
+impl<E: Engine> ProvingAssignment<E> {
+    fn evaluations_bytes(&self) -> usize {
+        vec_bytes(&self.a) + vec_bytes(&self.b) + vec_bytes(&self.c)
+    }
+}
+
 impl<E: Engine> ConstraintSystem<E> for ProvingAssignment<E> {
     type Root = Self;
 
@@ -83,6 +95,8 @@ impl<E: Engine> ConstraintSystem<E> for ProvingAssignment<E> {
         A: FnOnce() -> AR,
         AR: Into<String>,
     {
+        self.cancellation.check()?;
+
         self.aux_assignment.push(f()?);
         self.a_aux_density.add_element();
         self.b_aux_density.add_element();
@@ -96,6 +110,8 @@ impl<E: Engine> ConstraintSystem<E> for ProvingAssignment<E> {
         A: FnOnce() -> AR,
         AR: Into<String>,
     {
+        self.cancellation.check()?;
+
         self.input_assignment.push(f()?);
         self.b_input_density.add_element();
 
@@ -162,6 +178,55 @@ impl<E: Engine> ConstraintSystem<E> for ProvingAssignment<E> {
 }
 
 pub fn create_random_proof_batch_priority<E, C, R, P: ParameterSource<E>>(
+    worker: &Worker,
+    circuits: Vec<C>,
+    params: P,
+    rng: &mut R,
+    priority: bool,
+) -> Result<Vec<Proof<E>>, SynthesisError>
+where
+    E: Engine,
+    C: Circuit<E> + Send,
+    R: RngCore,
+{
+    create_random_proof_batch_with_context::<E, C, R, P>(
+        &ProverContext::new(),
+        worker,
+        circuits,
+        params,
+        rng,
+        priority,
+    )
+}
+
+/// Creates proofs for a batch of circuits, running all CPU work on the
+/// threads of `worker`.
+pub fn create_proof_batch_priority<E, C, P: ParameterSource<E>>(
+    worker: &Worker,
+    circuits: Vec<C>,
+    params: P,
+    r_s: Vec<E::Fr>,
+    s_s: Vec<E::Fr>,
+    priority: bool,
+) -> Result<Vec<Proof<E>>, SynthesisError>
+where
+    E: Engine,
+    C: Circuit<E> + Send,
+{
+    create_proof_batch_with_context::<E, C, P>(
+        &ProverContext::new(),
+        worker,
+        circuits,
+        params,
+        r_s,
+        s_s,
+        priority,
+    )
+}
+
+pub fn create_random_proof_batch_with_context<E, C, R, P: ParameterSource<E>>(
+    context: &ProverContext,
+    worker: &Worker,
     circuits: Vec<C>,
     params: P,
     rng: &mut R,
@@ -175,10 +240,50 @@ where
     let r_s = (0..circuits.len()).map(|_| E::Fr::random(rng)).collect();
     let s_s = (0..circuits.len()).map(|_| E::Fr::random(rng)).collect();
 
-    create_proof_batch_priority::<E, C, P>(circuits, params, r_s, s_s, priority)
+    create_proof_batch_with_context::<E, C, P>(
+        context, worker, circuits, params, r_s, s_s, priority,
+    )
 }
 
-pub fn create_proof_batch_priority<E, C, P: ParameterSource<E>>(
+/// Creates proofs for a batch of circuits like `create_proof_batch_priority`,
+/// reporting progress to `context` and returning `SynthesisError::Cancelled`
+/// soon after it is cancelled. The batch is proven in parts which fit in the
+/// memory budget of `context`, which reports the memory taken once done.
+pub fn create_proof_batch_with_context<E, C, P: ParameterSource<E>>(
+    context: &ProverContext,
+    worker: &Worker,
+    circuits: Vec<C>,
+    params: P,
+    r_s: Vec<E::Fr>,
+    s_s: Vec<E::Fr>,
+    priority: bool,
+) -> Result<Vec<Proof<E>>, SynthesisError>
+where
+    E: Engine,
+    C: Circuit<E> + Send,
+{
+    create_proof_batch_with_backend::<E, C, P, _>(
+        &DefaultBackend,
+        context,
+        worker,
+        circuits,
+        params,
+        r_s,
+        s_s,
+        priority,
+    )
+}
+
+/// Creates proofs for a batch of circuits like
+/// `create_proof_batch_with_context`, offloading FFTs and multiexps to the
+/// devices of the compute backend `backend`, and falling back to the CPU
+/// whenever they fail. Fails with `IncompatibleLengths` unless there are as
+/// many `r_s` and `s_s` as circuits.
+#[allow(clippy::too_many_arguments)]
+pub fn create_proof_batch_with_backend<E, C, P, B>(
+    backend: &B,
+    context: &ProverContext,
+    worker: &Worker,
     circuits: Vec<C>,
     mut params: P,
     r_s: Vec<E::Fr>,
@@ -188,36 +293,149 @@ pub fn create_proof_batch_priority<E, C, P: ParameterSource<E>>(
 where
     E: Engine,
     C: Circuit<E> + Send,
+    P: ParameterSource<E>,
+    B: ComputeBackend<E>,
 {
     info!("Bellperson {} is being used!", BELLMAN_VERSION);
 
-    let mut provers = circuits
-        .into_par_iter()
-        .map(|circuit| -> Result<_, SynthesisError> {
-            let mut prover = ProvingAssignment {
-                a_aux_density: DensityTracker::new(),
-                b_input_density: DensityTracker::new(),
-                b_aux_density: DensityTracker::new(),
-                a: vec![],
-                b: vec![],
-                c: vec![],
-                input_assignment: vec![],
-                aux_assignment: vec![],
-            };
-
-            prover.alloc_input(|| "", || Ok(E::Fr::one()))?;
-
-            circuit.synthesize(&mut prover)?;
-
-            for i in 0..prover.input_assignment.len() {
-                prover.enforce(|| "", |lc| lc + Variable(Index::Input(i)), |lc| lc, |lc| lc);
+    if r_s.len() != circuits.len() || s_s.len() != circuits.len() {
+        return Err(SynthesisError::IncompatibleLengths);
+    }
+
+    // Multiexps check the cancellation of the worker they run on
+    let worker = &worker.with_cancellation(context.cancellation().clone());
+
+    let num_circuits = circuits.len();
+    let estimate = MemoryEstimate::for_params::<E, P>(&params);
+    let parallelism = match (context.memory_budget(), estimate) {
+        (Some(budget), Some(estimate)) => {
+            let parallelism = estimate.max_batch_size(budget);
+            if parallelism == 0 {
+                return Err(SynthesisError::MemoryBudgetExceeded {
+                    needed: estimate.per_circuit(),
+                    budget,
+                });
             }
+            parallelism
+        }
+        (Some(_), None) => 1,
+        (None, _) => num_circuits,
+    };
+    let parallelism = cmp::max(1, cmp::min(parallelism, num_circuits));
+
+    let batch = Batch {
+        context,
+        backend,
+        priority,
+        num_circuits,
+        synthesized: AtomicUsize::new(0),
+        meter: MemoryMeter::default(),
+    };
 
-            Ok(prover)
-        })
-        .collect::<Result<Vec<_>, _>>()?;
+    let mut proofs = Vec::with_capacity(num_circuits);
+    let mut circuits = circuits.into_iter();
+    let mut r_s = r_s.into_iter();
+    let mut s_s = s_s.into_iter();
+    while proofs.len() < num_circuits {
+        let part = prove_part(
+            &batch,
+            worker,
+            proofs.len(),
+            circuits.by_ref().take(parallelism).collect(),
+            &mut params,
+            r_s.by_ref().take(parallelism).collect(),
+            s_s.by_ref().take(parallelism).collect(),
+        )?;
+        proofs.extend(part);
+    }
+
+    let report = MemoryReport {
+        estimate,
+        parallelism,
+        high_water_mark: batch.meter.peak(),
+    };
+    info!("proving took {:?}", report);
+    context.set_memory_report(report);
+
+    Ok(proofs)
+}
+
+/// The state shared by the parts of a batch.
+struct Batch<'a, B> {
+    context: &'a ProverContext,
+    backend: &'a B,
+    priority: bool,
+    num_circuits: usize,
+    synthesized: AtomicUsize,
+    meter: MemoryMeter,
+}
+
+/// Returns the bytes of the query `builder` is over.
+fn query_bytes<G: CurveAffine, B: SourceBuilder<G>>(builder: &B) -> usize {
+    vec_bytes(&builder.clone().get().0)
+}
+
+/// Proves the circuits of a batch starting at its `offset`th one.
+fn prove_part<E, C, P, B>(
+    batch: &Batch<B>,
+    worker: &Worker,
+    offset: usize,
+    circuits: Vec<C>,
+    params: &mut P,
+    r_s: Vec<E::Fr>,
+    s_s: Vec<E::Fr>,
+) -> Result<Vec<Proof<E>>, SynthesisError>
+where
+    E: Engine,
+    C: Circuit<E> + Send,
+    P: ParameterSource<E>,
+    B: ComputeBackend<E>,
+{
+    let context = batch.context;
+    let backend = batch.backend;
+    let priority = batch.priority;
+    let num_circuits = batch.num_circuits;
+    let meter = &batch.meter;
+    let loads_queries = params.loads_queries();
+
+    let mut provers = worker.install(|| {
+        circuits
+            .into_par_iter()
+            .map(|circuit| -> Result<_, SynthesisError> {
+                let mut prover = ProvingAssignment {
+                    cancellation: context.cancellation().clone(),
+                    a_aux_density: DensityTracker::new(),
+                    b_input_density: DensityTracker::new(),
+                    b_aux_density: DensityTracker::new(),
+                    a: vec![],
+                    b: vec![],
+                    c: vec![],
+                    input_assignment: vec![],
+                    aux_assignment: vec![],
+                };
+
+                prover.alloc_input(|| "", || Ok(E::Fr::one()))?;
+
+                circuit.synthesize(&mut prover)?;
+
+                for i in 0..prover.input_assignment.len() {
+                    prover.enforce(|| "", |lc| lc + Variable(Index::Input(i)), |lc| lc, |lc| lc);
+                }
+
+                meter.alloc(
+                    prover.evaluations_bytes()
+                        + vec_bytes(&prover.input_assignment)
+                        + vec_bytes(&prover.aux_assignment),
+                );
+
+                let done = batch.synthesized.fetch_add(1, Ordering::SeqCst) + 1;
+                context.report(ProverPhase::Synthesis, done, num_circuits);
+
+                Ok(prover)
+            })
+            .collect::<Result<Vec<_>, _>>()
+    })?;
 
-    let worker = Worker::new();
     let input_len = provers[0].input_assignment.len();
     let vk = params.get_vk(input_len)?;
     let n = provers[0].a.len();
@@ -236,105 +454,153 @@ where
         log_d += 1;
     }
 
-    #[cfg(feature = "gpu")]
-    let prio_lock = if priority {
-        Some(PriorityLock::lock())
+    let has_devices = backend.has_devices();
+    let prio_lock = if priority && has_devices {
+        Some(PriorityLock::lock()?)
     } else {
         None
     };
 
-    let mut fft_kern = LockedKernel::new(|| create_fft_kernel::<E>(log_d), priority);
+    let create_fft_kern = || create_fft_kernel_with_backend::<E, _>(backend, log_d);
+    let mut fft_kern = if has_devices {
+        LockedKernel::new(create_fft_kern, priority)
+    } else {
+        LockedKernel::disabled(create_fft_kern)
+    };
 
     let a_s = provers
         .iter_mut()
-        .map(|prover| {
+        .enumerate()
+        .map(|(i, prover)| {
+            context.check()?;
+
+            let evaluations_bytes = prover.evaluations_bytes();
             let mut a =
                 EvaluationDomain::from_coeffs(std::mem::replace(&mut prover.a, Vec::new()))?;
             let mut b =
                 EvaluationDomain::from_coeffs(std::mem::replace(&mut prover.b, Vec::new()))?;
             let mut c =
                 EvaluationDomain::from_coeffs(std::mem::replace(&mut prover.c, Vec::new()))?;
-
-            a.ifft(&worker, fft_kern.get())?;
-            a.coset_fft(&worker, fft_kern.get())?;
-            b.ifft(&worker, fft_kern.get())?;
-            b.coset_fft(&worker, fft_kern.get())?;
-            c.ifft(&worker, fft_kern.get())?;
-            c.coset_fft(&worker, fft_kern.get())?;
-
-            a.mul_assign(&worker, &b);
+            let domains_bytes = 3 * vec_bytes(a.as_ref());
+            meter.alloc(domains_bytes);
+            meter.free(evaluations_bytes);
+
+            a.ifft(worker, fft_kern.get())?;
+            a.coset_fft(worker, fft_kern.get())?;
+            b.ifft(worker, fft_kern.get())?;
+            b.coset_fft(worker, fft_kern.get())?;
+            c.ifft(worker, fft_kern.get())?;
+            c.coset_fft(worker, fft_kern.get())?;
+
+            a.mul_assign(worker, &b);
             drop(b);
-            a.sub_assign(&worker, &c);
+            a.sub_assign(worker, &c);
             drop(c);
-            a.divide_by_z_on_coset(&worker);
-            a.icoset_fft(&worker, fft_kern.get())?;
+            a.divide_by_z_on_coset(worker);
+            a.icoset_fft(worker, fft_kern.get())?;
             let mut a = a.into_coeffs();
             let a_len = a.len() - 1;
             a.truncate(a_len);
 
-            Ok(Arc::new(
-                a.into_iter().map(|s| s.0.into_repr()).collect::<Vec<_>>(),
-            ))
+            let a = a.into_iter().map(|s| s.0.into_repr()).collect::<Vec<_>>();
+            meter.alloc(vec_bytes(&a));
+            meter.free(domains_bytes);
+
+            context.report(ProverPhase::Fft, offset + i + 1, num_circuits);
+
+            Ok(Arc::new(a))
         })
         .collect::<Result<Vec<_>, SynthesisError>>()?;
 
     drop(fft_kern);
-    let mut multiexp_kern = LockedKernel::new(|| create_multiexp_kernel::<E>(), priority);
+    let create_multiexp_kern = || create_multiexp_kernel_with_backend::<E, _>(backend);
+    let mut multiexp_kern = if has_devices {
+        LockedKernel::new(create_multiexp_kern, priority)
+    } else {
+        LockedKernel::disabled(create_multiexp_kern)
+    };
+
+    context.check()?;
 
+    let h_progress = PhaseProgress::new(context, ProverPhase::MultiexpH, offset, num_circuits);
     let h_s = a_s
         .into_iter()
         .map(|a| {
-            let h = multiexp(
-                &worker,
-                params.get_h(a.len())?,
-                FullDensity,
-                a,
-                multiexp_kern.get(),
-            );
+            let h_source = params.get_h(a.len())?;
+            if loads_queries {
+                meter.alloc(query_bytes(&h_source));
+            }
+
+            let h = multiexp(worker, h_source, FullDensity, a, multiexp_kern.get());
+            h_progress.track(&[&h]);
             Ok(h)
         })
         .collect::<Result<Vec<_>, SynthesisError>>()?;
 
-    let input_assignments = provers
-        .par_iter_mut()
-        .map(|prover| {
-            let input_assignment = std::mem::replace(&mut prover.input_assignment, Vec::new());
-            Arc::new(
-                input_assignment
+    let input_assignments = worker.install(|| {
+        provers
+            .par_iter_mut()
+            .map(|prover| {
+                let input_assignment = std::mem::replace(&mut prover.input_assignment, Vec::new());
+                let assignment_bytes = vec_bytes(&input_assignment);
+                let input_assignment = input_assignment
                     .into_iter()
                     .map(|s| s.into_repr())
-                    .collect::<Vec<_>>(),
-            )
-        })
-        .collect::<Vec<_>>();
-
-    let aux_assignments = provers
-        .par_iter_mut()
-        .map(|prover| {
-            let aux_assignment = std::mem::replace(&mut prover.aux_assignment, Vec::new());
-            Arc::new(
-                aux_assignment
+                    .collect::<Vec<_>>();
+                meter.alloc(vec_bytes(&input_assignment));
+                meter.free(assignment_bytes);
+
+                Arc::new(input_assignment)
+            })
+            .collect::<Vec<_>>()
+    });
+
+    let aux_assignments = worker.install(|| {
+        provers
+            .par_iter_mut()
+            .map(|prover| {
+                let aux_assignment = std::mem::replace(&mut prover.aux_assignment, Vec::new());
+                let assignment_bytes = vec_bytes(&aux_assignment);
+                let aux_assignment = aux_assignment
                     .into_iter()
                     .map(|s| s.into_repr())
-                    .collect::<Vec<_>>(),
-            )
-        })
-        .collect::<Vec<_>>();
+                    .collect::<Vec<_>>();
+                meter.alloc(vec_bytes(&aux_assignment));
+                meter.free(assignment_bytes);
 
+                Arc::new(aux_assignment)
+            })
+            .collect::<Vec<_>>()
+    });
+
+    context.check()?;
+
+    let l_progress = PhaseProgress::new(context, ProverPhase::MultiexpL, offset, num_circuits);
     let l_s = aux_assignments
         .iter()
         .map(|aux_assignment| {
+            let l_source = params.get_l(aux_assignment.len())?;
+            if loads_queries {
+                meter.alloc(query_bytes(&l_source));
+            }
+
             let l = multiexp(
-                &worker,
-                params.get_l(aux_assignment.len())?,
+                worker,
+                l_source,
                 FullDensity,
                 aux_assignment.clone(),
                 multiexp_kern.get(),
             );
+            l_progress.track(&[&l]);
             Ok(l)
         })
         .collect::<Result<Vec<_>, SynthesisError>>()?;
 
+    context.check()?;
+
+    let a_progress = PhaseProgress::new(context, ProverPhase::MultiexpA, offset, num_circuits);
+    let b_g1_progress = PhaseProgress::new(context, ProverPhase::MultiexpBG1, offset, num_circuits);
+    let b_g2_progress = PhaseProgress::new(context, ProverPhase::MultiexpBG2, offset, num_circuits);
     let inputs = provers
         .into_iter()
         .zip(input_assignments.iter())
@@ -344,63 +610,89 @@ where
 
             let (a_inputs_source, a_aux_source) =
                 params.get_a(input_assignment.len(), a_aux_density_total)?;
+            if loads_queries {
+                meter.alloc(query_bytes(&a_inputs_source));
+            }
 
             let a_inputs = multiexp(
-                &worker,
+                worker,
                 a_inputs_source,
                 FullDensity,
                 input_assignment.clone(),
                 multiexp_kern.get(),
             );
 
+            // The exponents of the sparse queries are shared by the
+            // multiexps over the B query in G1 and G2. Only a GPU kernel
+            // needs them compacted, which copies them.
+            let compact = multiexp_kern.get().is_some();
+            let a_aux_exps = SparseExponents::new(aux_assignment, prover.a_aux_density, compact);
+            let b_input_exps =
+                SparseExponents::new(input_assignment, prover.b_input_density, compact);
+            let b_aux_exps = SparseExponents::new(aux_assignment, prover.b_aux_density, compact);
+            if compact {
+                meter.alloc(
+                    vec_bytes(&a_aux_exps.exponents())
+                        + vec_bytes(&b_input_exps.exponents())
+                        + vec_bytes(&b_aux_exps.exponents()),
+                );
+            }
+
             let a_aux = multiexp(
-                &worker,
+                worker,
                 a_aux_source,
-                Arc::new(prover.a_aux_density),
-                aux_assignment.clone(),
+                a_aux_exps.density(),
+                a_aux_exps.exponents(),
                 multiexp_kern.get(),
             );
+            a_progress.track(&[&a_inputs, &a_aux]);
 
-            let b_input_density = Arc::new(prover.b_input_density);
-            let b_input_density_total = b_input_density.get_total_density();
-            let b_aux_density = Arc::new(prover.b_aux_density);
-            let b_aux_density_total = b_aux_density.get_total_density();
+            let b_input_density_total = b_input_exps.len();
+            let b_aux_density_total = b_aux_exps.len();
 
             let (b_g1_inputs_source, b_g1_aux_source) =
                 params.get_b_g1(b_input_density_total, b_aux_density_total)?;
+            if loads_queries {
+                meter.alloc(query_bytes(&b_g1_inputs_source));
+            }
 
             let b_g1_inputs = multiexp(
-                &worker,
+                worker,
                 b_g1_inputs_source,
-                b_input_density.clone(),
-                input_assignment.clone(),
+                b_input_exps.density(),
+                b_input_exps.exponents(),
                 multiexp_kern.get(),
             );
             let b_g1_aux = multiexp(
-                &worker,
+                worker,
                 b_g1_aux_source,
-                b_aux_density.clone(),
-                aux_assignment.clone(),
+                b_aux_exps.density(),
+                b_aux_exps.exponents(),
                 multiexp_kern.get(),
             );
+            b_g1_progress.track(&[&b_g1_inputs, &b_g1_aux]);
 
             let (b_g2_inputs_source, b_g2_aux_source) =
                 params.get_b_g2(b_input_density_total, b_aux_density_total)?;
+            if loads_queries {
+                meter.alloc(query_bytes(&b_g2_inputs_source));
+            }
 
             let b_g2_inputs = multiexp(
-                &worker,
+                worker,
                 b_g2_inputs_source,
-                b_input_density,
-                input_assignment.clone(),
+                b_input_exps.density(),
+                b_input_exps.exponents(),
                 multiexp_kern.get(),
             );
             let b_g2_aux = multiexp(
-                &worker,
+                worker,
                 b_g2_aux_source,
-                b_aux_density,
-                aux_assignment.clone(),
+                b_aux_exps.density(),
+                b_aux_exps.exponents(),
                 multiexp_kern.get(),
             );
+            b_g2_progress.track(&[&b_g2_inputs, &b_g2_aux]);
 
             Ok((
                 a_inputs,
@@ -415,7 +707,6 @@ where
 
     drop(multiexp_kern);
 
-    #[cfg(feature = "gpu")]
     drop(prio_lock);
 
     let proofs = h_s
@@ -474,5 +765,8 @@ where
         )
         .collect::<Result<Vec<_>, SynthesisError>>()?;
 
+    // Every buffer of the part is dropped by now
+    meter.free_all();
+
     Ok(proofs)
 }
@@ -0,0 +1,351 @@
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use groupy::CurveAffine;
+use memmap::{Mmap, MmapOptions};
+use paired::Engine;
+
+use crate::multicore::Worker;
+use crate::SynthesisError;
+
+use std::cmp;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Read, Write};
+use std::marker::PhantomData;
+use std::mem;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use super::header::{check_digest, digest, engine_id, Digest};
+use super::mapped_params::decode_points;
+use super::{CircuitShape, ParameterSource, Parameters, VerifyingKey};
+
+/// The bytes shard manifests start with.
+const MANIFEST_MAGIC: [u8; 8] = *b"\x89BELLSHD";
+
+const MANIFEST_VERSION: u32 = 1;
+
+/// A file holding a run of consecutive points of a query, uncompressed and
+/// without a length.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Shard {
+    pub path: PathBuf,
+    /// The number of points in the shard.
+    pub len: usize,
+    /// The digest of the contents of the shard.
+    pub digest: Digest,
+}
+
+/// Parameters whose queries are split across shard files, possibly on
+/// several volumes, as described by a manifest. Like `MappedParameters`,
+/// only the verifying key is kept in memory, and a query is read from its
+/// shards whenever the prover asks for it.
+#[derive(Clone)]
+pub struct ShardedParameters<E: Engine> {
+    pub vk: VerifyingKey<E>,
+
+    pub h: Vec<Shard>,
+    pub l: Vec<Shard>,
+    pub a: Vec<Shard>,
+    pub b_g1: Vec<Shard>,
+    pub b_g2: Vec<Shard>,
+
+    /// Whether the points are checked to be in the subgroup, and the
+    /// shards against their digests, when they are read.
+    pub checked: bool,
+
+    // The pool queries are decoded on, shared by clones.
+    worker: Worker,
+
+    _e: PhantomData<E>,
+}
+
+impl<E: Engine> ShardedParameters<E> {
+    /// Opens the parameters described by the manifest at `manifest`. Shard
+    /// paths which are relative are relative to the manifest's directory.
+    pub fn open(manifest: &Path, checked: bool) -> io::Result<Self> {
+        Self::open_with_worker(manifest, checked, &Worker::new())
+    }
+
+    /// Like `open`, but the queries are decoded on the pool of `worker`.
+    pub fn open_with_worker(manifest: &Path, checked: bool, worker: &Worker) -> io::Result<Self> {
+        let dir = manifest.parent().unwrap_or_else(|| Path::new(""));
+
+        Self::read_manifest_with_worker(File::open(manifest)?, dir, checked, worker)
+    }
+
+    /// Reads a manifest, resolving relative shard paths against `dir`.
+    pub fn read_manifest<R: Read>(reader: R, dir: &Path, checked: bool) -> io::Result<Self> {
+        Self::read_manifest_with_worker(reader, dir, checked, &Worker::new())
+    }
+
+    /// Like `read_manifest`, but the queries are decoded on the pool of
+    /// `worker`.
+    pub fn read_manifest_with_worker<R: Read>(
+        mut reader: R,
+        dir: &Path,
+        checked: bool,
+        worker: &Worker,
+    ) -> io::Result<Self> {
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic)?;
+        if magic != MANIFEST_MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not a shard manifest",
+            ));
+        }
+
+        let version = reader.read_u32::<BigEndian>()?;
+        if version == 0 || version > MANIFEST_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unsupported shard manifest version {}", version),
+            ));
+        }
+
+        let mut engine = [0u8; 32];
+        reader.read_exact(&mut engine)?;
+        if engine != engine_id::<E>() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "shard manifest is for another engine",
+            ));
+        }
+
+        let vk = VerifyingKey::<E>::read(&mut reader)?;
+
+        let mut read_shards = || -> io::Result<Vec<Shard>> {
+            let count = reader.read_u32::<BigEndian>()?;
+            let mut shards = vec![];
+            for _ in 0..count {
+                let path_len = reader.read_u32::<BigEndian>()? as usize;
+                let mut path = vec![];
+                (&mut reader).take(path_len as u64).read_to_end(&mut path)?;
+                let path = String::from_utf8(path)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+                let len = reader.read_u32::<BigEndian>()? as usize;
+                let mut digest = [0u8; 32];
+                reader.read_exact(&mut digest)?;
+
+                shards.push(Shard {
+                    path: dir.join(path),
+                    len,
+                    digest,
+                });
+            }
+
+            Ok(shards)
+        };
+
+        Ok(ShardedParameters {
+            vk,
+            h: read_shards()?,
+            l: read_shards()?,
+            a: read_shards()?,
+            b_g1: read_shards()?,
+            b_g2: read_shards()?,
+            checked,
+            worker: worker.clone(),
+            _e: PhantomData,
+        })
+    }
+
+    /// Writes the manifest describing the shards, with their paths as they
+    /// are.
+    pub fn write_manifest<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_all(&MANIFEST_MAGIC)?;
+        writer.write_u32::<BigEndian>(MANIFEST_VERSION)?;
+        writer.write_all(&engine_id::<E>())?;
+        self.vk.write(&mut writer)?;
+
+        for shards in &[&self.h, &self.l, &self.a, &self.b_g1, &self.b_g2] {
+            writer.write_u32::<BigEndian>(shards.len() as u32)?;
+            for shard in shards.iter() {
+                let path = shard.path.to_str().ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::InvalidInput, "shard path is not unicode")
+                })?;
+                writer.write_u32::<BigEndian>(path.len() as u32)?;
+                writer.write_all(path.as_bytes())?;
+                writer.write_u32::<BigEndian>(shard.len as u32)?;
+                writer.write_all(&shard.digest)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Splits the parameter file at `param_file` into shards of at most
+/// `max_shard_size` bytes, though always of at least one point, and writes
+/// the manifest describing them to `manifest`, which is then opened. The
+/// shards are written next to the manifest, named after it, and can be moved
+/// elsewhere as long as the manifest is updated.
+pub fn split_parameters<E: Engine>(
+    param_file: &Path,
+    manifest: &Path,
+    max_shard_size: usize,
+) -> io::Result<ShardedParameters<E>> {
+    let worker = Worker::new();
+    let mapped = Parameters::<E>::build_mapped_parameters_with_worker(
+        param_file.to_path_buf(),
+        true,
+        &worker,
+    )?;
+    let file = File::open(param_file)?;
+    // Safety: this operation is safe, because we are
+    // intentionally memory mapping this file.
+    let mmap = unsafe { MmapOptions::new().map(&file)? };
+
+    let dir = manifest.parent().unwrap_or_else(|| Path::new(""));
+    let stem = manifest
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "invalid manifest path"))?;
+
+    let write_shards = |query: &str, ranges: &[Range<usize>], point_len: usize| {
+        let points_per_shard = cmp::max(1, max_shard_size / point_len);
+
+        ranges
+            .chunks(points_per_shard)
+            .enumerate()
+            .map(|(i, ranges)| -> io::Result<Shard> {
+                // The points of a query are consecutive in the file
+                let bytes = &mmap[ranges[0].start..ranges[ranges.len() - 1].end];
+                let name = format!("{}.{}.{}", stem, query, i);
+                fs::write(dir.join(&name), bytes)?;
+
+                Ok(Shard {
+                    path: PathBuf::from(name),
+                    len: ranges.len(),
+                    digest: digest(bytes),
+                })
+            })
+            .collect::<io::Result<Vec<_>>>()
+    };
+
+    let g1_len = mem::size_of::<<E::G1Affine as CurveAffine>::Uncompressed>();
+    let g2_len = mem::size_of::<<E::G2Affine as CurveAffine>::Uncompressed>();
+
+    let sharded = ShardedParameters::<E> {
+        vk: mapped.vk.clone(),
+        h: write_shards("h", &mapped.h, g1_len)?,
+        l: write_shards("l", &mapped.l, g1_len)?,
+        a: write_shards("a", &mapped.a, g1_len)?,
+        b_g1: write_shards("b_g1", &mapped.b_g1, g1_len)?,
+        b_g2: write_shards("b_g2", &mapped.b_g2, g2_len)?,
+        checked: false,
+        worker: worker.clone(),
+        _e: PhantomData,
+    };
+
+    let mut writer = BufWriter::new(File::create(manifest)?);
+    sharded.write_manifest(&mut writer)?;
+    writer.flush()?;
+
+    ShardedParameters::open_with_worker(manifest, false, &worker)
+}
+
+/// Reads a query from its shards, in order.
+fn read_shards<G: CurveAffine>(
+    worker: &Worker,
+    shards: &[Shard],
+    checked: bool,
+    query: &str,
+) -> io::Result<Vec<G>> {
+    let point_len = mem::size_of::<G::Uncompressed>();
+
+    let mut points = Vec::with_capacity(shards.iter().map(|shard| shard.len).sum());
+    for shard in shards.iter().filter(|shard| shard.len > 0) {
+        let file = File::open(&shard.path)?;
+        // Safety: this operation is safe, because we are
+        // intentionally memory mapping this file.
+        let mmap: Mmap = unsafe { MmapOptions::new().map(&file)? };
+
+        if mmap.len() != shard.len * point_len {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("shard {} has the wrong length", shard.path.display()),
+            ));
+        }
+        if checked {
+            check_digest(Some(&shard.digest), &digest(&mmap))?;
+        }
+
+        let first = points.len();
+        points.extend(decode_points::<G, _>(
+            worker,
+            first..first + shard.len,
+            checked,
+            query,
+            |i| &mmap[(i - first) * point_len..(i - first + 1) * point_len],
+        )?);
+    }
+
+    Ok(points)
+}
+
+impl<'a, E: Engine> ParameterSource<E> for &'a ShardedParameters<E> {
+    type G1Builder = (Arc<Vec<E::G1Affine>>, usize);
+    type G2Builder = (Arc<Vec<E::G2Affine>>, usize);
+
+    fn get_vk(&mut self, _: usize) -> Result<VerifyingKey<E>, SynthesisError> {
+        Ok(self.vk.clone())
+    }
+
+    fn get_h(&mut self, _num_h: usize) -> Result<Self::G1Builder, SynthesisError> {
+        let h = read_shards(&self.worker, &self.h, self.checked, "h")?;
+
+        Ok((Arc::new(h), 0))
+    }
+
+    fn get_l(&mut self, _num_l: usize) -> Result<Self::G1Builder, SynthesisError> {
+        let l = read_shards(&self.worker, &self.l, self.checked, "l")?;
+
+        Ok((Arc::new(l), 0))
+    }
+
+    fn get_a(
+        &mut self,
+        num_inputs: usize,
+        _num_a: usize,
+    ) -> Result<(Self::G1Builder, Self::G1Builder), SynthesisError> {
+        let a = Arc::new(read_shards(&self.worker, &self.a, self.checked, "a")?);
+
+        Ok(((a.clone(), 0), (a, num_inputs)))
+    }
+
+    fn get_b_g1(
+        &mut self,
+        num_inputs: usize,
+        _num_b_g1: usize,
+    ) -> Result<(Self::G1Builder, Self::G1Builder), SynthesisError> {
+        let b_g1 = Arc::new(read_shards(&self.worker, &self.b_g1, self.checked, "b_g1")?);
+
+        Ok(((b_g1.clone(), 0), (b_g1, num_inputs)))
+    }
+
+    fn get_b_g2(
+        &mut self,
+        num_inputs: usize,
+        _num_b_g2: usize,
+    ) -> Result<(Self::G2Builder, Self::G2Builder), SynthesisError> {
+        let b_g2 = Arc::new(read_shards(&self.worker, &self.b_g2, self.checked, "b_g2")?);
+
+        Ok(((b_g2.clone(), 0), (b_g2, num_inputs)))
+    }
+
+    fn shape(&self) -> Option<CircuitShape> {
+        let len = |shards: &[Shard]| shards.iter().map(|shard| shard.len).sum::<usize>();
+
+        Some(CircuitShape {
+            constraints: len(&self.h) + 1,
+            inputs: self.vk.ic.len(),
+            aux: len(&self.l),
+        })
+    }
+
+    fn loads_queries(&self) -> bool {
+        true
+    }
+}
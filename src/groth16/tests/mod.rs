@@ -9,6 +9,7 @@ use std::marker::PhantomData;
 use super::{
     create_proof, create_proof_batch, generate_parameters, prepare_verifying_key, verify_proof,
 };
+use crate::multicore::Worker;
 use crate::{Circuit, ConstraintSystem, SynthesisError};
 
 #[derive(Clone)]
@@ -107,7 +108,7 @@ fn test_xordemo() {
             _marker: PhantomData,
         };
 
-        generate_parameters(c, g1, g2, alpha, beta, gamma, delta, tau).unwrap()
+        generate_parameters(&Worker::new(), c, g1, g2, alpha, beta, gamma, delta, tau).unwrap()
     };
 
     // This will synthesize the constraint system:
@@ -400,7 +401,7 @@ fn test_create_batch_single() {
             _marker: PhantomData,
         };
 
-        generate_parameters(c, g1, g2, alpha, beta, gamma, delta, tau).unwrap()
+        generate_parameters(&Worker::new(), c, g1, g2, alpha, beta, gamma, delta, tau).unwrap()
     };
 
     let pvk = prepare_verifying_key(&params.vk);
@@ -4,6 +4,7 @@ use paired::{Engine, PairingCurveAffine};
 use rayon::prelude::*;
 
 use super::{BatchPreparedVerifyingKey, PreparedVerifyingKey, Proof, VerifyingKey};
+use crate::multicore::Worker;
 use crate::SynthesisError;
 
 pub fn prepare_verifying_key<E: Engine>(vk: &VerifyingKey<E>) -> PreparedVerifyingKey<E> {
@@ -67,12 +68,43 @@ pub fn verify_proof<'a, E: Engine>(
 }
 
 /// Randomized batch verification - see Appendix B.2 in Zcash spec
+///
+/// The proofs are processed in parallel on rayon's global pool; use
+/// `verify_proofs_batch_with_worker` to run on the pool of a worker.
 pub fn verify_proofs_batch<'a, E: Engine, R: rand::RngCore>(
     pvk: &'a BatchPreparedVerifyingKey<E>,
     rng: &mut R,
     proofs: &[&Proof<E>],
     public_inputs: &[Vec<E::Fr>],
 ) -> Result<bool, SynthesisError>
+where
+    <<E as ff::ScalarEngine>::Fr as ff::PrimeField>::Repr: From<<E as ff::ScalarEngine>::Fr>,
+{
+    verify_proofs_batch_inner(None, pvk, rng, proofs, public_inputs)
+}
+
+/// Randomized batch verification like `verify_proofs_batch`, processing
+/// the proofs in parallel on the pool of `worker`.
+pub fn verify_proofs_batch_with_worker<E: Engine, R: rand::RngCore>(
+    worker: &Worker,
+    pvk: &BatchPreparedVerifyingKey<E>,
+    rng: &mut R,
+    proofs: &[&Proof<E>],
+    public_inputs: &[Vec<E::Fr>],
+) -> Result<bool, SynthesisError>
+where
+    <<E as ff::ScalarEngine>::Fr as ff::PrimeField>::Repr: From<<E as ff::ScalarEngine>::Fr>,
+{
+    verify_proofs_batch_inner(Some(worker), pvk, rng, proofs, public_inputs)
+}
+
+fn verify_proofs_batch_inner<E: Engine, R: rand::RngCore>(
+    worker: Option<&Worker>,
+    pvk: &BatchPreparedVerifyingKey<E>,
+    rng: &mut R,
+    proofs: &[&Proof<E>],
+    public_inputs: &[Vec<E::Fr>],
+) -> Result<bool, SynthesisError>
 where
     <<E as ff::ScalarEngine>::Fr as ff::PrimeField>::Repr: From<<E as ff::ScalarEngine>::Fr>,
 {
@@ -106,19 +138,21 @@ where
     }
 
     // create corresponding scalars for public input vk elements
-    let pi_scalars: Vec<_> = (0..pi_num)
-        .into_par_iter()
-        .map(|i| {
-            let mut pi = E::Fr::zero();
-            for j in 0..proof_num {
-                // z_j * a_j,i
-                let mut tmp = r[j];
-                tmp.mul_assign(&public_inputs[j][i]);
-                pi.add_assign(&tmp);
-            }
-            pi
-        })
-        .collect();
+    let pi_scalars: Vec<_> = install(worker, || {
+        (0..pi_num)
+            .into_par_iter()
+            .map(|i| {
+                let mut pi = E::Fr::zero();
+                for j in 0..proof_num {
+                    // z_j * a_j,i
+                    let mut tmp = r[j];
+                    tmp.mul_assign(&public_inputs[j][i]);
+                    pi.add_assign(&tmp);
+                }
+                pi
+            })
+            .collect()
+    });
 
     // create group element corresponding to public input combination
     // This roughly corresponds to Accum_Gamma in spec
@@ -142,23 +176,24 @@ where
     }
 
     // This corresponds to Accum_AB
-    let ml = r
-        .par_iter()
-        .zip(proofs.par_iter())
-        .map(|(rand_coeff, proof)| {
-            // [z_j] pi_j,A
-            let mut tmp: E::G1 = proof.a.into();
-            tmp.mul_assign(*rand_coeff);
-            let g1 = tmp.into_affine().prepare();
-
-            // -pi_j,B
-            let mut tmp: E::G2 = proof.b.into();
-            tmp.negate();
-            let g2 = tmp.into_affine().prepare();
-
-            (g1, g2)
-        })
-        .collect::<Vec<_>>();
+    let ml = install(worker, || {
+        r.par_iter()
+            .zip(proofs.par_iter())
+            .map(|(rand_coeff, proof)| {
+                // [z_j] pi_j,A
+                let mut tmp: E::G1 = proof.a.into();
+                tmp.mul_assign(*rand_coeff);
+                let g1 = tmp.into_affine().prepare();
+
+                // -pi_j,B
+                let mut tmp: E::G2 = proof.b.into();
+                tmp.negate();
+                let g2 = tmp.into_affine().prepare();
+
+                (g1, g2)
+            })
+            .collect::<Vec<_>>()
+    });
     let mut parts = ml.iter().map(|(a, b)| (a, b)).collect::<Vec<_>>();
 
     // MillerLoop(Accum_Delta)
@@ -172,3 +207,16 @@ where
     let res = E::miller_loop(&parts);
     Ok(E::final_exponentiation(&res).unwrap() == acc_y)
 }
+
+/// Runs `op` on the pool of `worker`, if any, so that the parallel
+/// iterators within it use that pool rather than rayon's global one.
+fn install<OP, T>(worker: Option<&Worker>, op: OP) -> T
+where
+    OP: FnOnce() -> T + Send,
+    T: Send,
+{
+    match worker {
+        Some(worker) => worker.install(op),
+        None => op(),
+    }
+}
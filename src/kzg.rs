@@ -0,0 +1,615 @@
+//! [KZG] polynomial commitments.
+//!
+//! A polynomial is committed to by evaluating it at a secret point `tau` in
+//! the exponent, using a structured reference string holding the powers of
+//! `tau` in both groups. An opening at a point is a commitment to the
+//! quotient of the polynomial minus its value by the linear factor vanishing
+//! at the point, which the verifier checks with a pairing. Openings at several
+//! points at once divide by the polynomial vanishing at all of them instead.
+//!
+//! [KZG]: https://www.iacr.org/archive/asiacrypt2010/6477178/6477178.pdf
+
+use std::cmp;
+use std::io::{self, Read, Write};
+use std::sync::Arc;
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use ff::{Field, PrimeField};
+use groupy::{CurveAffine, CurveProjective, EncodedPoint, Wnaf};
+use paired::{Engine, PairingCurveAffine};
+use rand_core::RngCore;
+
+use crate::domain::{vanishing_polynomial, EvaluationDomain, Scalar};
+use crate::multicore::Worker;
+use crate::multiexp::{multiexp, FullDensity};
+use crate::SynthesisError;
+
+/// The structured reference string for committing to polynomials up to a
+/// maximum degree, and opening them at up to a maximum number of points at
+/// once.
+#[derive(Clone)]
+pub struct Srs<E: Engine> {
+    // Powers of tau in G1, up to the maximum degree inclusive.
+    pub g1_powers: Arc<Vec<E::G1Affine>>,
+
+    // Powers of tau in G2, up to the maximum number of points inclusive.
+    pub g2_powers: Arc<Vec<E::G2Affine>>,
+}
+
+impl<E: Engine> PartialEq for Srs<E> {
+    fn eq(&self, other: &Self) -> bool {
+        self.g1_powers == other.g1_powers && self.g2_powers == other.g2_powers
+    }
+}
+
+impl<E: Engine> Srs<E> {
+    /// Creates a reference string, given some toxic waste.
+    pub fn generate(
+        worker: &Worker,
+        max_degree: usize,
+        max_points: usize,
+        g1: E::G1,
+        g2: E::G2,
+        tau: E::Fr,
+    ) -> Result<Self, SynthesisError> {
+        if tau.is_zero() || g1.is_zero() || g2.is_zero() {
+            return Err(SynthesisError::UnexpectedIdentity);
+        }
+
+        Ok(Srs {
+            g1_powers: Arc::new(powers_of_tau(worker, g1, &tau, max_degree + 1)),
+            g2_powers: Arc::new(powers_of_tau(worker, g2, &tau, cmp::max(max_points, 1) + 1)),
+        })
+    }
+
+    /// Creates a random reference string.
+    pub fn generate_random<R: RngCore>(
+        worker: &Worker,
+        max_degree: usize,
+        max_points: usize,
+        rng: &mut R,
+    ) -> Result<Self, SynthesisError> {
+        let g1 = E::G1::random(rng);
+        let g2 = E::G2::random(rng);
+        let tau = E::Fr::random(rng);
+
+        Self::generate(worker, max_degree, max_points, g1, g2, tau)
+    }
+
+    /// The maximum degree of the polynomials that can be committed to.
+    pub fn max_degree(&self) -> usize {
+        self.g1_powers.len() - 1
+    }
+
+    /// The maximum number of points a polynomial can be opened at at once.
+    pub fn max_points(&self) -> usize {
+        self.g2_powers.len() - 1
+    }
+
+    pub fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_u32::<BigEndian>(self.g1_powers.len() as u32)?;
+        for g in &self.g1_powers[..] {
+            writer.write_all(g.into_uncompressed().as_ref())?;
+        }
+
+        writer.write_u32::<BigEndian>(self.g2_powers.len() as u32)?;
+        for g in &self.g2_powers[..] {
+            writer.write_all(g.into_uncompressed().as_ref())?;
+        }
+
+        Ok(())
+    }
+
+    pub fn read<R: Read>(mut reader: R, checked: bool) -> io::Result<Self> {
+        let g1_powers = read_powers::<E::G1Affine, _>(&mut reader, checked)?;
+        let g2_powers = read_powers::<E::G2Affine, _>(&mut reader, checked)?;
+
+        if g1_powers.is_empty() || g2_powers.len() < 2 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not enough powers of tau",
+            ));
+        }
+
+        Ok(Srs {
+            g1_powers: Arc::new(g1_powers),
+            g2_powers: Arc::new(g2_powers),
+        })
+    }
+}
+
+/// Computes `g * tau^i` for `i` below `len`.
+fn powers_of_tau<G: CurveProjective>(
+    worker: &Worker,
+    g: G,
+    tau: &G::Scalar,
+    len: usize,
+) -> Vec<G::Affine> {
+    let mut wnaf = Wnaf::new();
+    let wnaf = wnaf.base(g, len);
+
+    let mut powers = vec![G::zero(); len];
+
+    worker.scope(powers.len(), |scope, chunk| {
+        for (i, powers) in powers.chunks_mut(chunk).enumerate() {
+            let mut wnaf = wnaf.shared();
+
+            scope.spawn(move |_| {
+                let mut current_tau_power = tau.pow(&[(i * chunk) as u64]);
+
+                for p in powers.iter_mut() {
+                    *p = wnaf.scalar(current_tau_power.into_repr());
+                    current_tau_power.mul_assign(tau);
+                }
+
+                G::batch_normalization(powers);
+            });
+        }
+    });
+
+    powers.into_iter().map(|p| p.into_affine()).collect()
+}
+
+fn read_powers<G: CurveAffine, R: Read>(reader: &mut R, checked: bool) -> io::Result<Vec<G>> {
+    let len = reader.read_u32::<BigEndian>()? as usize;
+
+    let mut powers = vec![];
+    for _ in 0..len {
+        let mut repr = G::Uncompressed::empty();
+        reader.read_exact(repr.as_mut())?;
+
+        let g = if checked {
+            repr.into_affine()
+        } else {
+            repr.into_affine_unchecked()
+        }
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+        if g.is_zero() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "point at infinity",
+            ));
+        }
+
+        powers.push(g);
+    }
+
+    Ok(powers)
+}
+
+/// A commitment to a polynomial.
+#[derive(Clone, Debug)]
+pub struct Commitment<E: Engine>(pub E::G1Affine);
+
+/// A proof that a committed polynomial takes some values at some points.
+#[derive(Clone, Debug)]
+pub struct Opening<E: Engine>(pub E::G1Affine);
+
+macro_rules! impl_point_wrapper {
+    ($name:ident) => {
+        impl<E: Engine> PartialEq for $name<E> {
+            fn eq(&self, other: &Self) -> bool {
+                self.0 == other.0
+            }
+        }
+
+        impl<E: Engine> $name<E> {
+            pub fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+                writer.write_all(self.0.into_compressed().as_ref())
+            }
+
+            pub fn read<R: Read>(mut reader: R) -> io::Result<Self> {
+                let mut repr = <E::G1Affine as CurveAffine>::Compressed::empty();
+                reader.read_exact(repr.as_mut())?;
+
+                repr.into_affine()
+                    .map(Self)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+            }
+        }
+    };
+}
+
+impl_point_wrapper!(Commitment);
+impl_point_wrapper!(Opening);
+
+/// Commits to a polynomial in coefficient form.
+pub fn commit<E: Engine>(
+    worker: &Worker,
+    srs: &Srs<E>,
+    poly: &EvaluationDomain<E, Scalar<E>>,
+) -> Result<Commitment<E>, SynthesisError> {
+    Ok(Commitment(commit_coeffs(worker, srs, poly.as_ref())?))
+}
+
+fn commit_coeffs<E: Engine>(
+    worker: &Worker,
+    srs: &Srs<E>,
+    coeffs: &[Scalar<E>],
+) -> Result<E::G1Affine, SynthesisError> {
+    let len = coeffs
+        .iter()
+        .rposition(|c| !c.0.is_zero())
+        .map_or(0, |i| i + 1);
+
+    if len > srs.g1_powers.len() {
+        return Err(SynthesisError::PolynomialDegreeTooLarge);
+    }
+
+    let exponents = coeffs[..len]
+        .iter()
+        .map(|c| c.0.into_repr())
+        .collect::<Vec<_>>();
+
+    let commitment = multiexp(
+        worker,
+        (srs.g1_powers.clone(), 0),
+        FullDensity,
+        Arc::new(exponents),
+        &mut None,
+    )
+    .wait()?;
+
+    Ok(commitment.into_affine())
+}
+
+/// Opens a polynomial at `point`, returning its value there and the proof.
+pub fn open<E: Engine>(
+    worker: &Worker,
+    srs: &Srs<E>,
+    poly: &EvaluationDomain<E, Scalar<E>>,
+    point: &E::Fr,
+) -> Result<(E::Fr, Opening<E>), SynthesisError> {
+    let mut quotient = EvaluationDomain::from_coeffs(poly.as_ref().to_vec())?;
+    let value = quotient.divide_by_linear(point);
+
+    Ok((value, Opening(commit(worker, srs, &quotient)?.0)))
+}
+
+/// Opens a polynomial at every one of `points` with a single proof,
+/// returning the values at the points and the proof.
+pub fn open_batch<E: Engine>(
+    worker: &Worker,
+    srs: &Srs<E>,
+    poly: &EvaluationDomain<E, Scalar<E>>,
+    points: &[E::Fr],
+) -> Result<(Vec<E::Fr>, Opening<E>), SynthesisError> {
+    if points.len() > srs.max_points() {
+        return Err(SynthesisError::PolynomialDegreeTooLarge);
+    }
+
+    let values = points
+        .iter()
+        .map(|point| poly.evaluate(worker, point))
+        .collect::<Vec<_>>();
+
+    // Subtract the polynomial interpolating the values, which leaves a
+    // polynomial divisible by every linear factor
+    let interpolation = interpolate::<E>(worker, points, &values)?;
+
+    let mut coeffs = poly.as_ref().to_vec();
+    if coeffs.len() < interpolation.as_ref().len() {
+        coeffs.resize(interpolation.as_ref().len(), Scalar(E::Fr::zero()));
+    }
+    for (c, i) in coeffs.iter_mut().zip(interpolation.as_ref()) {
+        c.0.sub_assign(&i.0);
+    }
+
+    let mut quotient = EvaluationDomain::from_coeffs(coeffs)?;
+    for point in points {
+        quotient.divide_by_linear(point);
+    }
+
+    Ok((values, Opening(commit(worker, srs, &quotient)?.0)))
+}
+
+fn interpolate<E: Engine>(
+    worker: &Worker,
+    points: &[E::Fr],
+    values: &[E::Fr],
+) -> Result<EvaluationDomain<E, Scalar<E>>, SynthesisError> {
+    let points = points
+        .iter()
+        .cloned()
+        .zip(values.iter().cloned())
+        .collect::<Vec<_>>();
+
+    EvaluationDomain::interpolate(worker, &points)
+}
+
+/// Verifies the opening of a committed polynomial at `point` to `value`.
+pub fn verify<E: Engine>(
+    srs: &Srs<E>,
+    commitment: &Commitment<E>,
+    point: &E::Fr,
+    value: &E::Fr,
+    opening: &Opening<E>,
+) -> Result<bool, SynthesisError> {
+    // The opening is a commitment to q with p(X) - v = q(X) (X - z), which
+    // is checked at tau as
+    // e(C - v G + z Q, H) = e(Q, tau H)
+    let mut lhs = opening.0.mul(point.into_repr());
+    lhs.add_assign_mixed(&commitment.0);
+    lhs.sub_assign(&srs.g1_powers[0].mul(value.into_repr()));
+
+    Ok(check_pairings(
+        srs,
+        lhs,
+        opening.0.into_projective(),
+        srs.g2_powers[1],
+    ))
+}
+
+/// Verifies the opening of a committed polynomial at every one of `points`
+/// to the corresponding `values`. Fails with `IncompatibleLengths` if there
+/// are not as many values as points.
+pub fn verify_batch<E: Engine>(
+    worker: &Worker,
+    srs: &Srs<E>,
+    commitment: &Commitment<E>,
+    points: &[E::Fr],
+    values: &[E::Fr],
+    opening: &Opening<E>,
+) -> Result<bool, SynthesisError> {
+    if points.len() != values.len() {
+        return Err(SynthesisError::IncompatibleLengths);
+    }
+
+    if points.len() > srs.max_points() {
+        return Err(SynthesisError::PolynomialDegreeTooLarge);
+    }
+
+    // p(X) - I(X) = q(X) Z(X), with I interpolating the values and Z
+    // vanishing at the points, is checked at tau as
+    // e(C - I(tau) G, H) = e(Q, Z(tau) H)
+    let interpolation = interpolate(worker, points, values)?;
+
+    let mut lhs = commitment.0.into_projective();
+    lhs.sub_assign(&commit_coeffs(worker, srs, interpolation.as_ref())?.into_projective());
+
+    let vanishing = vanishing_polynomial::<E, _>(points);
+
+    let mut vanishing_g2 = E::G2::zero();
+    for (c, power) in vanishing.iter().zip(srs.g2_powers.iter()) {
+        vanishing_g2.add_assign(&power.mul(c.into_repr()));
+    }
+
+    Ok(check_pairings(
+        srs,
+        lhs,
+        opening.0.into_projective(),
+        vanishing_g2.into_affine(),
+    ))
+}
+
+/// Randomized batch verification of openings of committed polynomials at
+/// one point each, the `i`th commitment being opened at the `i`th point.
+///
+/// Like `verify_proofs_batch`, the checks are combined with random 128-bit
+/// coefficients, so that a single pairing check covers all of them. Fails
+/// with `IncompatibleLengths` unless there are as many points, values and
+/// openings as commitments.
+pub fn verify_openings_batch<E: Engine, R: RngCore>(
+    srs: &Srs<E>,
+    rng: &mut R,
+    commitments: &[&Commitment<E>],
+    points: &[E::Fr],
+    values: &[E::Fr],
+    openings: &[&Opening<E>],
+) -> Result<bool, SynthesisError> {
+    if points.len() != commitments.len()
+        || values.len() != commitments.len()
+        || openings.len() != commitments.len()
+    {
+        return Err(SynthesisError::IncompatibleLengths);
+    }
+
+    // sum r_i (C_i - v_i G + z_i Q_i) and sum r_i Q_i
+    let mut lhs = E::G1::zero();
+    let mut acc_opening = E::G1::zero();
+    let mut acc_value = E::Fr::zero();
+
+    for (((commitment, point), value), opening) in
+        commitments.iter().zip(points).zip(values).zip(openings)
+    {
+        let r = random_coefficient::<E, _>(rng);
+
+        let mut scaled_point = *point;
+        scaled_point.mul_assign(&r);
+        lhs.add_assign(&opening.0.mul(scaled_point.into_repr()));
+        lhs.add_assign(&commitment.0.mul(r.into_repr()));
+
+        let mut scaled_value = *value;
+        scaled_value.mul_assign(&r);
+        acc_value.add_assign(&scaled_value);
+
+        acc_opening.add_assign(&opening.0.mul(r.into_repr()));
+    }
+
+    lhs.sub_assign(&srs.g1_powers[0].mul(acc_value.into_repr()));
+
+    Ok(check_pairings(srs, lhs, acc_opening, srs.g2_powers[1]))
+}
+
+/// Checks `e(lhs, H) = e(opening, rhs)`, where `H` is the generator of the
+/// reference string, as `e(lhs, H) e(-opening, rhs) = 1` with a single final
+/// exponentiation.
+fn check_pairings<E: Engine>(
+    srs: &Srs<E>,
+    lhs: E::G1,
+    mut opening: E::G1,
+    rhs: E::G2Affine,
+) -> bool {
+    opening.negate();
+
+    E::final_exponentiation(&E::miller_loop(
+        [
+            (&lhs.into_affine().prepare(), &srs.g2_powers[0].prepare()),
+            (&opening.into_affine().prepare(), &rhs.prepare()),
+        ]
+        .iter(),
+    ))
+    .unwrap()
+        == E::Fqk::one()
+}
+
+/// Samples a random 128-bit scalar.
+fn random_coefficient<E: Engine, R: RngCore>(rng: &mut R) -> E::Fr {
+    let mut repr = E::Fr::zero().into_repr();
+    let limbs: &mut [u64] = repr.as_mut();
+    limbs[0] = rng.next_u64();
+    limbs[1] = rng.next_u64();
+
+    E::Fr::from_repr(repr).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use paired::bls12_381::{Bls12, Fr};
+    use rand::thread_rng;
+
+    fn random_poly(len: usize) -> EvaluationDomain<Bls12, Scalar<Bls12>> {
+        let rng = &mut thread_rng();
+
+        EvaluationDomain::from_coeffs((0..len).map(|_| Scalar(Fr::random(rng))).collect()).unwrap()
+    }
+
+    #[test]
+    fn srs_serialization() {
+        let rng = &mut thread_rng();
+        let worker = Worker::new();
+
+        let srs = Srs::<Bls12>::generate_random(&worker, 20, 3, rng).unwrap();
+        assert_eq!(srs.max_degree(), 20);
+        assert_eq!(srs.max_points(), 3);
+
+        let mut v = vec![];
+        srs.write(&mut v).unwrap();
+
+        assert!(srs == Srs::read(&v[..], true).unwrap());
+        assert!(srs == Srs::read(&v[..], false).unwrap());
+        assert!(Srs::<Bls12>::read(&v[..v.len() - 1], false).is_err());
+
+        let poly = random_poly(21);
+        let commitment = commit(&worker, &srs, &poly).unwrap();
+
+        let mut v = vec![];
+        commitment.write(&mut v).unwrap();
+        assert!(commitment == Commitment::read(&v[..]).unwrap());
+
+        // Polynomials beyond the maximum degree cannot be committed to
+        match commit(&worker, &srs, &random_poly(22)) {
+            Err(SynthesisError::PolynomialDegreeTooLarge) => {}
+            _ => panic!("committed to a polynomial of too large degree"),
+        }
+    }
+
+    #[test]
+    fn open_and_verify() {
+        let rng = &mut thread_rng();
+        let worker = Worker::new();
+
+        let srs = Srs::<Bls12>::generate_random(&worker, 64, 4, rng).unwrap();
+
+        for &len in &[0, 1, 2, 33, 65] {
+            let poly = random_poly(len);
+            let commitment = commit(&worker, &srs, &poly).unwrap();
+
+            let point = Fr::random(rng);
+            let (value, opening) = open(&worker, &srs, &poly, &point).unwrap();
+            assert_eq!(value, poly.evaluate(&worker, &point));
+
+            assert!(verify(&srs, &commitment, &point, &value, &opening).unwrap());
+
+            let mut wrong_value = value;
+            wrong_value.add_assign(&Fr::one());
+            assert!(!verify(&srs, &commitment, &point, &wrong_value, &opening).unwrap());
+            // Constant polynomials have the same opening at every point
+            if len > 1 {
+                assert!(!verify(&srs, &commitment, &Fr::random(rng), &value, &opening).unwrap());
+            }
+
+            for num_points in 0..=4 {
+                let points = (0..num_points).map(|_| Fr::random(rng)).collect::<Vec<_>>();
+                let (values, opening) = open_batch(&worker, &srs, &poly, &points).unwrap();
+
+                assert!(
+                    verify_batch(&worker, &srs, &commitment, &points, &values, &opening).unwrap()
+                );
+
+                if num_points > 0 {
+                    let mut wrong_values = values.clone();
+                    wrong_values[num_points - 1].add_assign(&Fr::one());
+                    assert!(!verify_batch(
+                        &worker,
+                        &srs,
+                        &commitment,
+                        &points,
+                        &wrong_values,
+                        &opening
+                    )
+                    .unwrap());
+
+                    match verify_batch(&worker, &srs, &commitment, &points, &values[1..], &opening)
+                    {
+                        Err(SynthesisError::IncompatibleLengths) => (),
+                        _ => panic!("expected the lengths to be incompatible"),
+                    }
+                }
+            }
+        }
+
+        // Openings at more points than the reference string supports
+        let points = (0..5).map(|_| Fr::random(rng)).collect::<Vec<_>>();
+        assert!(open_batch(&worker, &srs, &random_poly(10), &points).is_err());
+    }
+
+    #[test]
+    fn batch_verification() {
+        let rng = &mut thread_rng();
+        let worker = Worker::new();
+
+        let srs = Srs::<Bls12>::generate_random(&worker, 32, 1, rng).unwrap();
+
+        let claims = (0..8)
+            .map(|i| {
+                let poly = random_poly(i * 4 + 1);
+                let commitment = commit(&worker, &srs, &poly).unwrap();
+                let point = Fr::random(rng);
+                let (value, opening) = open(&worker, &srs, &poly, &point).unwrap();
+
+                (commitment, point, value, opening)
+            })
+            .collect::<Vec<_>>();
+
+        let commitments = claims.iter().map(|c| &c.0).collect::<Vec<_>>();
+        let points = claims.iter().map(|c| c.1).collect::<Vec<_>>();
+        let mut values = claims.iter().map(|c| c.2).collect::<Vec<_>>();
+        let mut openings = claims.iter().map(|c| &c.3).collect::<Vec<_>>();
+
+        assert!(
+            verify_openings_batch(&srs, rng, &commitments, &points, &values, &openings).unwrap()
+        );
+        assert!(verify_openings_batch::<Bls12, _>(&srs, rng, &[], &[], &[], &[]).unwrap());
+        match verify_openings_batch(&srs, rng, &commitments, &points[1..], &values, &openings) {
+            Err(SynthesisError::IncompatibleLengths) => (),
+            _ => panic!("expected the lengths to be incompatible"),
+        }
+
+        // A single wrong value fails the whole batch
+        values[3].add_assign(&Fr::one());
+        assert!(
+            !verify_openings_batch(&srs, rng, &commitments, &points, &values, &openings).unwrap()
+        );
+        values[3].sub_assign(&Fr::one());
+
+        // So does an opening of another commitment
+        openings[5] = &claims[6].3;
+        assert!(
+            !verify_openings_batch(&srs, rng, &commitments, &points, &values, &openings).unwrap()
+        );
+    }
+}
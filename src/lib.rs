@@ -144,6 +144,8 @@ pub mod gadgets;
 pub mod gpu;
 #[cfg(feature = "groth16")]
 pub mod groth16;
+#[cfg(feature = "kzg")]
+pub mod kzg;
 pub mod multicore;
 pub mod multiexp;
 
@@ -330,6 +332,17 @@ pub enum SynthesisError {
     /// During GPU multiexp/fft, some GPU related error happened
     #[error("encountered a GPU error: {0}")]
     GPUError(#[from] gpu::GPUError),
+    /// During proof generation, the computation was cancelled
+    #[error("computation was cancelled")]
+    Cancelled,
+    /// Arguments which go together, such as points and the values at them,
+    /// were of different lengths
+    #[error("arguments of incompatible lengths")]
+    IncompatibleLengths,
+    /// During proof generation, a single circuit needed more memory than
+    /// the budget
+    #[error("proving a circuit takes an estimated {needed} bytes of memory, over the budget of {budget}")]
+    MemoryBudgetExceeded { needed: usize, budget: usize },
 }
 
 /// Represents a constraint system which can have new variables
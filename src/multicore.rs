@@ -1,64 +1,373 @@
 //! An interface for dealing with the kinds of parallel computations involved in
-//! `bellperson`. It's currently just a thin wrapper around [`CpuPool`] and
-//! [`crossbeam`] but may be extended in the future to allow for various
-//! parallelism strategies.
+//! `bellperson`. It's currently just a thin wrapper around a [`rayon`] thread
+//! pool but may be extended in the future to allow for various parallelism
+//! strategies.
 //!
-//! [`CpuPool`]: futures_cpupool::CpuPool
+//! A [`Worker`] created with [`Worker::with_num_threads`] owns a pool of its
+//! own, so that several provers in one process can each be given a separate
+//! CPU budget, while [`Worker::new`] shares a single default pool. Work done
+//! by a worker, including rayon parallel iterators run through
+//! [`Worker::install`], stays on its pool.
+//!
+//! Computations which mostly wait on others, like creating a proof, run on a
+//! small pool of driver threads of their own through
+//! [`Worker::compute_on_thread`], which runs at most `DRIVER_THREADS` of them
+//! at a time per pool.
+//!
+//! A worker can also carry a [`CancellationToken`], which long computations
+//! run on it check between chunks of work.
+//!
+//! Computations handed to a worker return a [`WorkerFuture`], a
+//! `std::future::Future` which can be awaited, or waited on by blocking the
+//! calling thread.
+
+use std::future::Future;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::thread;
+
+use crate::SynthesisError;
+
+/// A flag shared by the computations it cancels and whoever cancels them.
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Requests every computation checking this token to stop.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Returns `SynthesisError::Cancelled` once the token is cancelled.
+    pub fn check(&self) -> Result<(), SynthesisError> {
+        if self.is_cancelled() {
+            Err(SynthesisError::Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// The result of a computation run by a [`Worker`]. A panic of the
+/// computation is resumed when its result is taken.
+pub struct WorkerFuture<T, E> {
+    shared: Arc<Shared<Result<T, E>>>,
+}
+
+struct Shared<T> {
+    state: Mutex<SharedState<T>>,
+    done: Condvar,
+}
+
+type CompletionCallback = Box<dyn FnOnce(bool) + Send>;
+
+struct SharedState<T> {
+    result: Option<thread::Result<T>>,
+    waker: Option<Waker>,
+    /// Whether the computation succeeded, once it is done.
+    success: Option<bool>,
+    callbacks: Vec<CompletionCallback>,
+}
+
+/// Hands the result of a computation to its `WorkerFuture`.
+struct Completion<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T, E> Completion<Result<T, E>> {
+    fn complete(self, mut result: thread::Result<Result<T, E>>) {
+        // Callbacks run before the result is handed over, so that whoever
+        // waits for it also waits for them
+        let success = match result {
+            Ok(Ok(_)) => true,
+            _ => false,
+        };
+        let callbacks = {
+            let mut state = self.shared.state.lock().unwrap();
+            state.success = Some(success);
+            std::mem::replace(&mut state.callbacks, Vec::new())
+        };
+        for callback in callbacks {
+            // A panicking callback is resumed like a panicking computation
+            if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(|| callback(success))) {
+                result = Err(payload);
+            }
+        }
+
+        let waker = {
+            let mut state = self.shared.state.lock().unwrap();
+            state.result = Some(result);
+            state.waker.take()
+        };
+
+        self.shared.done.notify_all();
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+fn worker_future<T, E>() -> (Completion<Result<T, E>>, WorkerFuture<T, E>) {
+    let shared = Arc::new(Shared {
+        state: Mutex::new(SharedState {
+            result: None,
+            waker: None,
+            success: None,
+            callbacks: Vec::new(),
+        }),
+        done: Condvar::new(),
+    });
+
+    (
+        Completion {
+            shared: shared.clone(),
+        },
+        WorkerFuture { shared },
+    )
+}
+
+impl<T, E> WorkerFuture<T, E> {
+    /// Returns a future which is already resolved to `result`.
+    pub fn ready(result: Result<T, E>) -> Self {
+        let (completion, future) = worker_future();
+        completion.complete(Ok(result));
+
+        future
+    }
+
+    /// Calls `callback` with whether the computation succeeded once it is
+    /// done, on the thread completing it, or right away if it is already
+    /// done.
+    pub fn on_complete<F>(&self, callback: F)
+    where
+        F: FnOnce(bool) + Send + 'static,
+    {
+        let mut state = self.shared.state.lock().unwrap();
+        match state.success {
+            Some(success) => {
+                drop(state);
+                callback(success);
+            }
+            None => state.callbacks.push(Box::new(callback)),
+        }
+    }
+
+    /// Blocks the current thread until the computation is done.
+    pub fn wait(self) -> Result<T, E> {
+        let mut state = self.shared.state.lock().unwrap();
+        loop {
+            if let Some(result) = state.result.take() {
+                return resume(result);
+            }
+            state = self.shared.done.wait(state).unwrap();
+        }
+    }
+}
+
+impl<T, E> Future for WorkerFuture<T, E> {
+    type Output = Result<T, E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let mut state = self.shared.state.lock().unwrap();
+        match state.result.take() {
+            Some(result) => Poll::Ready(resume(result)),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+fn resume<T>(result: thread::Result<T>) -> T {
+    match result {
+        Ok(result) => result,
+        Err(payload) => panic::resume_unwind(payload),
+    }
+}
+
+/// Drives `future` to completion on the current thread, for tests.
+#[cfg(test)]
+pub(crate) fn block_on<F: Future>(future: F) -> F::Output {
+    use std::task::{RawWaker, RawWakerVTable};
+
+    // A waker unparking the blocked thread, which is its data
+    fn clone_waker(data: *const ()) -> RawWaker {
+        let thread = unsafe { &*(data as *const thread::Thread) };
+        RawWaker::new(
+            Box::into_raw(Box::new(thread.clone())) as *const (),
+            &VTABLE,
+        )
+    }
+    fn wake(data: *const ()) {
+        let thread = unsafe { Box::from_raw(data as *mut thread::Thread) };
+        thread.unpark();
+    }
+    fn wake_by_ref(data: *const ()) {
+        unsafe { &*(data as *const thread::Thread) }.unpark();
+    }
+    fn drop_waker(data: *const ()) {
+        drop(unsafe { Box::from_raw(data as *mut thread::Thread) });
+    }
+    static VTABLE: RawWakerVTable = RawWakerVTable::new(clone_waker, wake, wake_by_ref, drop_waker);
+
+    let current = Box::new(thread::current());
+    let waker =
+        unsafe { Waker::from_raw(RawWaker::new(Box::into_raw(current) as *const (), &VTABLE)) };
+    let mut cx = Context::from_waker(&waker);
+
+    let mut future = Box::pin(future);
+    loop {
+        match future.as_mut().poll(&mut cx) {
+            Poll::Ready(output) => return output,
+            Poll::Pending => thread::park(),
+        }
+    }
+}
 
 #[cfg(feature = "multicore")]
 mod implementation {
-    use crossbeam::{self, thread::Scope};
-    use futures::{Future, IntoFuture, Poll};
-    use futures_cpupool::{CpuFuture, CpuPool};
+    use super::{worker_future, CancellationToken, WorkerFuture};
     use num_cpus;
+    use rayon::{Scope, ThreadPool, ThreadPoolBuilder};
     use std::env;
+    use std::panic::{self, AssertUnwindSafe};
+    use std::sync::{Arc, Mutex};
+
+    /// The number of threads driving computations started with
+    /// `Worker::compute_on_thread` on a pool. Further ones wait for a thread
+    /// to be free, so a driven computation must not wait on another one.
+    const DRIVER_THREADS: usize = 4;
+
+    lazy_static::lazy_static! {
+        /// The pool shared by every worker created with `Worker::new`,
+        /// built the first time one is.
+        static ref DEFAULT_WORKER: Worker = {
+            let cpus = if let Ok(num) = env::var("BELLMAN_NUM_CPUS") {
+                if let Ok(num) = num.parse() {
+                    num
+                } else {
+                    num_cpus::get()
+                }
+            } else {
+                num_cpus::get()
+            };
+
+            Worker::with_num_threads(cpus)
+        };
+    }
 
     #[derive(Clone)]
     pub struct Worker {
         cpus: usize,
-        pool: CpuPool,
+        pool: Arc<ThreadPool>,
+        /// The driver threads of the pool, built the first time they are
+        /// needed.
+        drivers: Arc<Mutex<Option<ThreadPool>>>,
+        cancellation: CancellationToken,
     }
 
     impl Worker {
-        // We don't expose this outside the library so that
-        // all `Worker` instances have the same number of
-        // CPUs configured.
-        pub(crate) fn new_with_cpus(cpus: usize) -> Worker {
+        /// Creates a worker with a pool of `cpus` threads.
+        pub fn with_num_threads(cpus: usize) -> Worker {
+            assert!(cpus > 0, "a worker needs at least one thread");
+
+            let pool = ThreadPoolBuilder::new()
+                .num_threads(cpus)
+                .thread_name(|i| format!("bellperson-worker-{}", i))
+                .build()
+                .expect("failed to spawn worker threads");
+
             Worker {
                 cpus,
-                pool: CpuPool::new(cpus),
+                pool: Arc::new(pool),
+                drivers: Arc::new(Mutex::new(None)),
+                cancellation: CancellationToken::new(),
             }
         }
 
+        /// Returns a worker on the default pool, which has a thread per CPU,
+        /// or as many threads as given by the `BELLMAN_NUM_CPUS` environment
+        /// variable. The pool is built once and shared by every worker
+        /// returned by `new`, so only `with_num_threads` creates threads.
         pub fn new() -> Worker {
-            let cpus = if let Ok(num) = env::var("BELLMAN_NUM_CPUS") {
-                if let Ok(num) = num.parse() {
-                    num
-                } else {
-                    num_cpus::get()
-                }
-            } else {
-                num_cpus::get()
-            };
+            DEFAULT_WORKER.with_cancellation(CancellationToken::new())
+        }
 
-            Self::new_with_cpus(cpus)
+        pub fn num_threads(&self) -> usize {
+            self.cpus
         }
 
         pub fn log_num_cpus(&self) -> u32 {
             log2_floor(self.cpus)
         }
 
-        pub fn compute<F, R>(&self, f: F) -> WorkerFuture<R::Item, R::Error>
+        /// Returns a worker on the same pool whose computations are
+        /// cancelled by `cancellation`.
+        pub fn with_cancellation(&self, cancellation: CancellationToken) -> Worker {
+            Worker {
+                cpus: self.cpus,
+                pool: self.pool.clone(),
+                drivers: self.drivers.clone(),
+                cancellation,
+            }
+        }
+
+        pub fn cancellation(&self) -> &CancellationToken {
+            &self.cancellation
+        }
+
+        pub fn compute<F, T, E>(&self, f: F) -> WorkerFuture<T, E>
         where
-            F: FnOnce() -> R + Send + 'static,
-            R: IntoFuture + 'static,
-            R::Future: Send + 'static,
-            R::Item: Send + 'static,
-            R::Error: Send + 'static,
+            F: FnOnce() -> Result<T, E> + Send + 'static,
+            T: Send + 'static,
+            E: Send + 'static,
         {
-            WorkerFuture {
-                future: self.pool.spawn_fn(f),
-            }
+            let (completion, future) = worker_future();
+
+            self.pool.spawn(move || {
+                // Hand panics to the future, like a thread's join handle
+                completion.complete(panic::catch_unwind(AssertUnwindSafe(f)));
+            });
+
+            future
+        }
+
+        /// Runs `f` on one of the driver threads of the pool rather than on
+        /// the pool itself, for computations which mostly wait on others
+        /// they run on the pool, like creating a proof. At most
+        /// `DRIVER_THREADS` such computations run at a time, and the others
+        /// are queued until a driver thread is free.
+        pub fn compute_on_thread<F, T, E>(&self, f: F) -> WorkerFuture<T, E>
+        where
+            F: FnOnce() -> Result<T, E> + Send + 'static,
+            T: Send + 'static,
+            E: Send + 'static,
+        {
+            let (completion, future) = worker_future();
+
+            let mut drivers = self.drivers.lock().unwrap();
+            let drivers = drivers.get_or_insert_with(|| {
+                ThreadPoolBuilder::new()
+                    .num_threads(DRIVER_THREADS)
+                    .thread_name(|i| format!("bellperson-driver-{}", i))
+                    .build()
+                    .expect("failed to spawn driver threads")
+            });
+            drivers.spawn(move || completion.complete(panic::catch_unwind(AssertUnwindSafe(f))));
+
+            future
         }
 
         pub fn scope<'a, F, R>(&self, elements: usize, f: F) -> R
@@ -71,22 +380,19 @@ mod implementation {
                 elements / self.cpus
             };
 
-            // TODO: Handle case where threads fail
-            crossbeam::scope(|scope| f(scope, chunk_size))
-                .expect("Threads aren't allowed to fail yet")
+            // `f` runs on the calling thread, like the spawning closure of a
+            // crossbeam scope, while what it spawns runs on the pool
+            self.pool.in_place_scope(|scope| f(scope, chunk_size))
         }
-    }
 
-    pub struct WorkerFuture<T, E> {
-        future: CpuFuture<T, E>,
-    }
-
-    impl<T: Send + 'static, E: Send + 'static> Future for WorkerFuture<T, E> {
-        type Item = T;
-        type Error = E;
-
-        fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
-            self.future.poll()
+        /// Runs `op` on the pool of this worker, so that any rayon parallel
+        /// iterators within it use the pool instead of the global one.
+        pub fn install<OP, R>(&self, op: OP) -> R
+        where
+            OP: FnOnce() -> R + Send,
+            R: Send,
+        {
+            self.pool.install(op)
         }
     }
 
@@ -113,33 +419,190 @@ mod implementation {
         assert_eq!(log2_floor(7), 2);
         assert_eq!(log2_floor(8), 3);
     }
+
+    #[test]
+    fn test_worker_threads() {
+        let worker = Worker::with_num_threads(3);
+        assert_eq!(worker.num_threads(), 3);
+        assert_eq!(worker.log_num_cpus(), 1);
+
+        // Parallel iterators run on the worker's own pool
+        assert_eq!(worker.install(rayon::current_num_threads), 3);
+
+        let sum = worker.compute(|| Ok::<_, ()>((0..100u64).sum::<u64>()));
+        let failed = worker.compute(|| Err::<u64, _>("failed"));
+        assert_eq!(sum.wait(), Ok(4950));
+        assert_eq!(failed.wait(), Err("failed"));
+
+        let mut v = vec![0usize; 100];
+        worker.scope(v.len(), |scope, chunk| {
+            for (i, v) in v.chunks_mut(chunk).enumerate() {
+                scope.spawn(move |_| {
+                    for (j, v) in v.iter_mut().enumerate() {
+                        *v = i * chunk + j;
+                    }
+                });
+            }
+        });
+        assert!(v.iter().enumerate().all(|(i, &v)| i == v));
+    }
+
+    #[test]
+    fn test_default_worker() {
+        // Workers created with `new` share the default pool
+        let a = Worker::new();
+        let b = Worker::new();
+        assert!(Arc::ptr_eq(&a.pool, &b.pool));
+        assert!(!Arc::ptr_eq(&a.pool, &Worker::with_num_threads(1).pool));
+
+        // But not their cancellation
+        a.cancellation().cancel();
+        assert!(!b.cancellation().is_cancelled());
+    }
+
+    #[test]
+    fn test_worker_cancellation() {
+        let worker = Worker::with_num_threads(2);
+        let token = CancellationToken::new();
+        let cancellable = worker.with_cancellation(token.clone());
+
+        // The cancellable worker shares the pool of the original one
+        assert_eq!(cancellable.install(rayon::current_num_threads), 2);
+
+        assert!(cancellable.cancellation().check().is_ok());
+        token.cancel();
+        assert!(cancellable.cancellation().is_cancelled());
+        assert!(!worker.cancellation().is_cancelled());
+
+        match cancellable.cancellation().check() {
+            Err(crate::SynthesisError::Cancelled) => {}
+            _ => panic!("cancelled token was not reported"),
+        }
+    }
+
+    #[test]
+    fn test_worker_future() {
+        use super::block_on;
+
+        let worker = Worker::with_num_threads(1);
+
+        let sum = worker.compute(|| Ok::<_, ()>((0..100u64).sum::<u64>()));
+        assert_eq!(block_on(sum), Ok(4950));
+
+        // A computation on a driver thread can wait on the pool, even one
+        // with a single thread
+        let pool = worker.clone();
+        let waiting = worker.compute_on_thread(move || {
+            let a = pool.compute(|| Ok::<_, ()>(1u64));
+            let b = pool.compute(|| Ok::<_, ()>(2u64));
+            Ok::<_, ()>(a.wait()? + b.wait()?)
+        });
+        assert_eq!(block_on(waiting), Ok(3));
+
+        assert_eq!(block_on(WorkerFuture::<_, ()>::ready(Ok(5))), Ok(5));
+    }
+
+    #[test]
+    fn test_driver_threads() {
+        use std::sync::Mutex;
+        use std::thread;
+        use std::time::Duration;
+
+        let worker = Worker::with_num_threads(1);
+        // The number of computations running, and the most seen at a time
+        let running = Arc::new(Mutex::new((0, 0)));
+
+        // No more than `DRIVER_THREADS` computations are driven at a time,
+        // whatever the number started
+        let futures = (0..3 * DRIVER_THREADS)
+            .map(|i| {
+                let running = running.clone();
+                worker.compute_on_thread(move || {
+                    {
+                        let mut running = running.lock().unwrap();
+                        running.0 += 1;
+                        running.1 = std::cmp::max(running.0, running.1);
+                    }
+                    thread::sleep(Duration::from_millis(10));
+                    running.lock().unwrap().0 -= 1;
+                    Ok::<_, ()>(i)
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for (i, future) in futures.into_iter().enumerate() {
+            assert_eq!(future.wait(), Ok(i));
+        }
+        assert!(running.lock().unwrap().1 <= DRIVER_THREADS);
+
+        // Workers sharing a pool share its driver threads
+        let cancellable = worker.with_cancellation(CancellationToken::new());
+        assert!(Arc::ptr_eq(&worker.drivers, &cancellable.drivers));
+    }
+
+    #[test]
+    #[should_panic(expected = "task panicked")]
+    fn test_worker_panic() {
+        let worker = Worker::with_num_threads(1);
+
+        let _ = worker
+            .compute(|| -> Result<(), ()> { panic!("task panicked") })
+            .wait();
+    }
 }
 
 #[cfg(not(feature = "multicore"))]
 mod implementation {
-    use futures::{future, Future, IntoFuture, Poll};
+    use super::{CancellationToken, WorkerFuture};
 
     #[derive(Clone)]
-    pub struct Worker;
+    pub struct Worker {
+        cancellation: CancellationToken,
+    }
 
     impl Worker {
+        pub fn with_num_threads(_: usize) -> Worker {
+            Self::new()
+        }
+
         pub fn new() -> Worker {
-            Worker
+            Worker {
+                cancellation: CancellationToken::new(),
+            }
+        }
+
+        pub fn num_threads(&self) -> usize {
+            1
         }
 
         pub fn log_num_cpus(&self) -> u32 {
             0
         }
 
-        pub fn compute<F, R>(&self, f: F) -> R::Future
+        pub fn with_cancellation(&self, cancellation: CancellationToken) -> Worker {
+            Worker { cancellation }
+        }
+
+        pub fn cancellation(&self) -> &CancellationToken {
+            &self.cancellation
+        }
+
+        pub fn compute<F, T, E>(&self, f: F) -> WorkerFuture<T, E>
         where
-            F: FnOnce() -> R + Send + 'static,
-            R: IntoFuture + 'static,
-            R::Future: Send + 'static,
-            R::Item: Send + 'static,
-            R::Error: Send + 'static,
+            F: FnOnce() -> Result<T, E> + Send + 'static,
+            T: Send + 'static,
+            E: Send + 'static,
         {
-            f().into_future()
+            WorkerFuture::ready(f())
+        }
+
+        pub fn compute_on_thread<F, T, E>(&self, f: F) -> WorkerFuture<T, E>
+        where
+            F: FnOnce() -> Result<T, E> + Send + 'static,
+            T: Send + 'static,
+            E: Send + 'static,
+        {
+            WorkerFuture::ready(f())
         }
 
         pub fn scope<F, R>(&self, elements: usize, f: F) -> R
@@ -148,18 +611,12 @@ mod implementation {
         {
             f(&DummyScope, elements)
         }
-    }
 
-    pub struct WorkerFuture<T, E> {
-        future: future::FutureResult<T, E>,
-    }
-
-    impl<T: Send + 'static, E: Send + 'static> Future for WorkerFuture<T, E> {
-        type Item = T;
-        type Error = E;
-
-        fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
-            self.future.poll()
+        pub fn install<OP, R>(&self, op: OP) -> R
+        where
+            OP: FnOnce() -> R,
+        {
+            op()
         }
     }
 
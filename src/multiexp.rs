@@ -1,22 +1,45 @@
 use bit_vec::{self, BitVec};
-use ff::{Field, PrimeField, PrimeFieldRepr, ScalarEngine};
-use futures::Future;
+use ff::{PrimeField, ScalarEngine};
 use groupy::{CurveAffine, CurveProjective};
 use log::{info, warn};
+use std::cmp;
+use std::future::Future;
 use std::io;
 use std::iter;
-use std::sync::Arc;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
 
-use super::multicore::Worker;
+use super::multicore::{Worker, WorkerFuture};
 use super::SynthesisError;
 use crate::gpu;
 
+/// The number of exponents a window of the multiexp processes between
+/// checks for cancellation.
+const CANCELLATION_INTERVAL: usize = 1 << 14;
+
 /// An object that builds a source of bases.
 pub trait SourceBuilder<G: CurveAffine>: Send + Sync + 'static + Clone {
     type Source: Source<G>;
 
     fn new(self) -> Self::Source;
     fn get(self) -> (Arc<Vec<G>>, usize);
+
+    /// Returns how the source lays out precomputed multiples of its bases,
+    /// if it provides any.
+    fn precomputation(&self) -> Option<Precomputation> {
+        None
+    }
+}
+
+/// Describes the precomputed multiples of the bases of a source: every base
+/// is followed by `multiples - 1` multiples, the `j`th of which is the base
+/// multiplied by `2^(j * stride)`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Precomputation {
+    pub multiples: usize,
+    pub stride: u32,
 }
 
 /// A source of bases, like an iterator.
@@ -27,6 +50,22 @@ pub trait Source<G: CurveAffine> {
         to: &mut <G as CurveAffine>::Projective,
     ) -> Result<(), SynthesisError>;
 
+    /// Parses the element from the source and subtracts it. Fails if the
+    /// point is at infinity.
+    ///
+    /// By default, the element is added to the negation of `to`, which is
+    /// negated back.
+    fn sub_assign_mixed(
+        &mut self,
+        to: &mut <G as CurveAffine>::Projective,
+    ) -> Result<(), SynthesisError> {
+        to.negate();
+        let result = self.add_assign_mixed(to);
+        to.negate();
+
+        result
+    }
+
     /// Skips `amt` elements from the source, avoiding deserialization.
     fn skip(&mut self, amt: usize) -> Result<(), SynthesisError>;
 }
@@ -67,6 +106,31 @@ impl<G: CurveAffine> Source<G> for (Arc<Vec<G>>, usize) {
         Ok(())
     }
 
+    fn sub_assign_mixed(
+        &mut self,
+        to: &mut <G as CurveAffine>::Projective,
+    ) -> Result<(), SynthesisError> {
+        if self.0.len() <= self.1 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "expected more bases from source",
+            )
+            .into());
+        }
+
+        if self.0[self.1].is_zero() {
+            return Err(SynthesisError::UnexpectedIdentity);
+        }
+
+        let mut base = self.0[self.1];
+        base.negate();
+        to.add_assign_mixed(&base);
+
+        self.1 += 1;
+
+        Ok(())
+    }
+
     fn skip(&mut self, amt: usize) -> Result<(), SynthesisError> {
         if self.0.len() <= self.1 {
             return Err(io::Error::new(
@@ -82,6 +146,166 @@ impl<G: CurveAffine> Source<G> for (Arc<Vec<G>>, usize) {
     }
 }
 
+/// The maximum number of precomputed multiples per base.
+pub const MAX_PRECOMPUTED_MULTIPLES: usize = 32;
+
+/// Bases together with precomputed multiples of each of them, which let
+/// the multiexp split every exponent into segments of `stride` bits and
+/// handle all segments within the same windows. This divides the number of
+/// windows, and with them the bucket passes and doublings, by about the
+/// number of multiples, at the cost of storing that many points per base.
+#[derive(Clone)]
+pub struct PrecomputedBases<G: CurveAffine> {
+    table: Arc<Vec<G>>,
+    precomputation: Precomputation,
+    /// The bases without their multiples, extracted once for consumers that
+    /// do not use them, and shared by clones.
+    stripped: Arc<Mutex<Option<Arc<Vec<G>>>>>,
+}
+
+impl<G: CurveAffine> PrecomputedBases<G> {
+    /// Computes `multiples` multiples of every base, including the base
+    /// itself.
+    pub fn new(pool: &Worker, bases: &[G], multiples: usize) -> Self {
+        assert!((1..=MAX_PRECOMPUTED_MULTIPLES).contains(&multiples));
+
+        let stride = Self::stride(multiples);
+        let mut table = vec![G::zero(); bases.len() * multiples];
+
+        if !bases.is_empty() {
+            pool.scope(bases.len(), |scope, chunk| {
+                for (bases, table) in bases.chunks(chunk).zip(table.chunks_mut(chunk * multiples)) {
+                    scope.spawn(move |_| {
+                        let mut projective = Vec::with_capacity(table.len());
+
+                        for base in bases {
+                            let mut multiple = base.into_projective();
+                            projective.push(multiple);
+
+                            for _ in 1..multiples {
+                                for _ in 0..stride {
+                                    multiple.double();
+                                }
+                                projective.push(multiple);
+                            }
+                        }
+
+                        G::Projective::batch_normalization(&mut projective);
+
+                        for (entry, multiple) in table.iter_mut().zip(projective) {
+                            *entry = multiple.into_affine();
+                        }
+                    });
+                }
+            });
+        }
+
+        PrecomputedBases {
+            table: Arc::new(table),
+            precomputation: Precomputation { multiples, stride },
+            stripped: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Wraps a table laid out as described by [`Precomputation`], e.g. one
+    /// read back from disk.
+    pub fn from_table(table: Vec<G>, multiples: usize) -> io::Result<Self> {
+        if !(1..=MAX_PRECOMPUTED_MULTIPLES).contains(&multiples) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "unsupported number of precomputed multiples",
+            ));
+        }
+
+        if table.len() % multiples != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "precomputed table has a partial entry",
+            ));
+        }
+
+        Ok(PrecomputedBases {
+            table: Arc::new(table),
+            precomputation: Precomputation {
+                multiples,
+                stride: Self::stride(multiples),
+            },
+            stripped: Arc::new(Mutex::new(None)),
+        })
+    }
+
+    /// The number of bits between consecutive multiples of a base.
+    pub fn stride(multiples: usize) -> u32 {
+        let bits = <G::Scalar as PrimeField>::NUM_BITS;
+        let multiples = multiples as u32;
+
+        (bits + multiples - 1) / multiples
+    }
+
+    pub fn precomputation(&self) -> Precomputation {
+        self.precomputation
+    }
+
+    /// The number of bases.
+    pub fn len(&self) -> usize {
+        self.table.len() / self.precomputation.multiples
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.table.is_empty()
+    }
+
+    /// The bases and their multiples, each base followed by its multiples.
+    pub fn table(&self) -> &[G] {
+        &self.table
+    }
+
+    /// Returns the bases without their multiples.
+    pub fn bases(&self) -> Vec<G> {
+        self.table
+            .iter()
+            .step_by(self.precomputation.multiples)
+            .cloned()
+            .collect()
+    }
+
+    /// Returns the bases without their multiples, which are only extracted
+    /// from the table the first time.
+    fn shared_bases(&self) -> Arc<Vec<G>> {
+        if self.precomputation.multiples == 1 {
+            return self.table.clone();
+        }
+
+        self.stripped
+            .lock()
+            .unwrap()
+            .get_or_insert_with(|| Arc::new(self.bases()))
+            .clone()
+    }
+}
+
+/// Builds a source reading precomputed bases from the base at the given
+/// index.
+impl<G: CurveAffine> SourceBuilder<G> for (PrecomputedBases<G>, usize) {
+    type Source = (Arc<Vec<G>>, usize);
+
+    fn new(self) -> (Arc<Vec<G>>, usize) {
+        let offset = self.1 * self.0.precomputation.multiples;
+
+        (self.0.table, offset)
+    }
+
+    /// Returns the bases without their multiples, for consumers that do not
+    /// use them.
+    fn get(self) -> (Arc<Vec<G>>, usize) {
+        (self.0.shared_bases(), self.1)
+    }
+
+    fn precomputation(&self) -> Option<Precomputation> {
+        Some(self.0.precomputation)
+    }
+}
+
 pub trait QueryDensity {
     /// Returns whether the base exists.
     type Iter: Iterator<Item = bool>;
@@ -111,6 +335,7 @@ impl<'a> QueryDensity for &'a FullDensity {
     }
 }
 
+#[derive(Clone)]
 pub struct DensityTracker {
     bv: BitVec,
     total_density: usize,
@@ -152,26 +377,264 @@ impl DensityTracker {
     }
 }
 
+/// The exponents of a multiexp over a query that only holds bases for some
+/// of them, as tracked by a `DensityTracker`, shared by every multiexp over
+/// the query. A GPU kernel only takes the exponents with a base, so they are
+/// compacted once for all its multiexps; the CPU skips the others through
+/// the tracker, and shares the exponents without a copy.
+#[derive(Clone)]
+pub struct SparseExponents<R> {
+    exponents: Arc<Vec<R>>,
+    density: SparseDensity,
+    len: usize,
+}
+
+impl<R: Copy> SparseExponents<R> {
+    /// Keeps only the exponents with a base if `compact` is set, and all of
+    /// `exponents` otherwise.
+    pub fn new(exponents: &Arc<Vec<R>>, density: DensityTracker, compact: bool) -> Self {
+        let len = density.get_total_density();
+        if compact {
+            SparseExponents {
+                exponents: Arc::new(compact_exponents(exponents, density.iter(), len)),
+                density: SparseDensity(None),
+                len,
+            }
+        } else {
+            SparseExponents {
+                exponents: exponents.clone(),
+                density: SparseDensity(Some(Arc::new(density))),
+                len,
+            }
+        }
+    }
+
+    /// Returns the exponents of the multiexp, in the order of the query.
+    pub fn exponents(&self) -> Arc<Vec<R>> {
+        self.exponents.clone()
+    }
+
+    /// Returns which of the exponents have a base.
+    pub fn density(&self) -> SparseDensity {
+        self.density.clone()
+    }
+
+    /// Returns whether the exponents were compacted, in which case they are
+    /// a copy of the ones they were built from.
+    pub fn is_compact(&self) -> bool {
+        self.density.0.is_none()
+    }
+
+    /// Returns the number of exponents with a base.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+/// The density of `SparseExponents`: full once they are compacted, and the
+/// tracker of the query otherwise.
+#[derive(Clone)]
+pub struct SparseDensity(Option<Arc<DensityTracker>>);
+
+impl AsRef<SparseDensity> for SparseDensity {
+    fn as_ref(&self) -> &SparseDensity {
+        self
+    }
+}
+
+impl<'a> QueryDensity for &'a SparseDensity {
+    type Iter = SparseDensityIter<'a>;
+
+    fn iter(self) -> Self::Iter {
+        SparseDensityIter(self.0.as_ref().map(|density| density.bv.iter()))
+    }
+
+    fn get_query_size(self) -> Option<usize> {
+        self.0.as_ref().map(|density| density.bv.len())
+    }
+}
+
+pub struct SparseDensityIter<'a>(Option<bit_vec::Iter<'a>>);
+
+impl<'a> Iterator for SparseDensityIter<'a> {
+    type Item = bool;
+
+    fn next(&mut self) -> Option<bool> {
+        match self.0 {
+            Some(ref mut bits) => bits.next(),
+            None => Some(true),
+        }
+    }
+}
+
+/// Keeps the `count` exponents whose base is in the query.
+fn compact_exponents<R: Copy, I: Iterator<Item = bool>>(
+    exponents: &[R],
+    density: I,
+    count: usize,
+) -> Vec<R> {
+    let mut compact = Vec::with_capacity(count);
+    compact.extend(
+        exponents
+            .iter()
+            .zip(density)
+            .filter(|&(_, density)| density)
+            .map(|(&exp, _)| exp),
+    );
+
+    compact
+}
+
+/// Returns the `len` bits of `repr` starting at bit `start`.
+fn window_bits(repr: &[u64], start: u32, len: u32) -> u64 {
+    let limb = (start / 64) as usize;
+    let bit = start % 64;
+
+    if len == 0 || limb >= repr.len() {
+        return 0;
+    }
+
+    let mut bits = repr[limb] >> bit;
+    if bit + len > 64 && limb + 1 < repr.len() {
+        bits |= repr[limb + 1] << (64 - bit);
+    }
+
+    bits & ((1 << len) - 1)
+}
+
+/// How exponents are recoded into signed digits. An exponent is split into
+/// `segments` segments of `segment_bits` bits, matching the precomputed
+/// multiples of the bases, and each segment into `windows` windows of `c`
+/// bits. Without precomputation there is a single segment covering the
+/// whole exponent.
+#[derive(Clone, Copy, Debug)]
+struct DigitLayout {
+    c: u32,
+    segment_bits: u32,
+    segments: u32,
+    windows: u32,
+}
+
+impl DigitLayout {
+    fn new<E: ScalarEngine>(c: u32, precomputation: Option<Precomputation>) -> Self {
+        let (segment_bits, segments) = match precomputation {
+            Some(p) => (p.stride, p.multiples as u32),
+            None => (E::Fr::NUM_BITS, 1),
+        };
+
+        // One more window than the unsigned windows may be needed to absorb
+        // the final carry of a segment
+        let layout = DigitLayout {
+            c,
+            segment_bits,
+            segments,
+            windows: segment_bits / c + 1,
+        };
+        assert!(layout.segments * layout.segment_bits >= E::Fr::NUM_BITS);
+        assert!(layout.segments * layout.windows < 128);
+
+        layout
+    }
+
+    /// Returns the unsigned bits of a window of a segment.
+    fn window_bits(&self, repr: &[u64], segment: u32, window: u32) -> u64 {
+        let offset = window * self.c;
+        let len = cmp::min(self.c, self.segment_bits.saturating_sub(offset));
+
+        window_bits(repr, segment * self.segment_bits + offset, len)
+    }
+
+    /// Recodes an exponent into signed `c`-bit window digits in
+    /// `(-2^(c-1), 2^(c-1)]`, returned as the carries between windows: with
+    /// the windows of all segments numbered consecutively, bit `j` is set
+    /// if window `j` receives a carry from window `j - 1`. This is enough
+    /// to recover every digit with [`DigitLayout::digit`] while storing far
+    /// less than the digits themselves. The last window of a segment holds
+    /// fewer than `c` bits and never carries, so segments stay independent.
+    fn carries(&self, repr: &[u64]) -> u128 {
+        let mut carries = 0u128;
+
+        for segment in 0..self.segments {
+            let mut carry = 0;
+
+            for window in 0..self.windows {
+                let digit = self.window_bits(repr, segment, window) + carry;
+
+                carry = if digit > 1 << (self.c - 1) { 1 } else { 0 };
+                carries |= u128::from(carry) << (segment * self.windows + window + 1);
+            }
+        }
+
+        carries
+    }
+
+    /// Returns the signed digit of a window of a segment.
+    fn digit(&self, repr: &[u64], carries: u128, segment: u32, window: u32) -> i64 {
+        let j = segment * self.windows + window;
+
+        let mut digit =
+            self.window_bits(repr, segment, window) as i64 + ((carries >> j) & 1) as i64;
+        if (carries >> (j + 1)) & 1 == 1 {
+            digit -= 1 << self.c;
+        }
+
+        digit
+    }
+}
+
+/// Computes the signed digit carries of every exponent once, so that
+/// each window of the multiexp can reuse them.
+fn compute_signed_digit_carries<E: ScalarEngine>(
+    pool: &Worker,
+    exponents: &[<E::Fr as PrimeField>::Repr],
+    layout: DigitLayout,
+) -> Vec<u128> {
+    let mut carries = vec![0u128; exponents.len()];
+
+    if exponents.is_empty() {
+        return carries;
+    }
+
+    pool.scope(exponents.len(), |scope, chunk| {
+        for (exponents, carries) in exponents.chunks(chunk).zip(carries.chunks_mut(chunk)) {
+            scope.spawn(move |_| {
+                for (exp, carries) in exponents.iter().zip(carries.iter_mut()) {
+                    *carries = layout.carries(exp.as_ref());
+                }
+            });
+        }
+    });
+
+    carries
+}
+
 fn multiexp_inner<Q, D, G, S>(
     pool: &Worker,
     bases: S,
     density_map: D,
     exponents: Arc<Vec<<<G::Engine as ScalarEngine>::Fr as PrimeField>::Repr>>,
-    mut skip: u32,
-    c: u32,
-    handle_trivial: bool,
-) -> Box<dyn Future<Item = <G as CurveAffine>::Projective, Error = SynthesisError>>
+    carries: Arc<Vec<u128>>,
+    layout: DigitLayout,
+) -> MultiexpFuture<G::Projective>
 where
     for<'a> &'a Q: QueryDensity,
     D: Send + Sync + 'static + Clone + AsRef<Q>,
     G: CurveAffine,
     S: SourceBuilder<G>,
 {
-    // Perform this region of the multiexp
-    let this = {
+    let c = layout.c;
+
+    // Every region of the multiexp is computed in parallel
+    let windows = (0..layout.windows).map(|window| {
         let bases = bases.clone();
         let exponents = exponents.clone();
+        let carries = carries.clone();
         let density_map = density_map.clone();
+        let cancellation = pool.cancellation().clone();
 
         pool.compute(move || {
             // Accumulate the result
@@ -180,30 +643,30 @@ where
             // Build a source for the bases
             let mut bases = bases.new();
 
-            // Create space for the buckets
-            let mut buckets = vec![<G as CurveAffine>::Projective::zero(); (1 << c) - 1];
-
-            let zero = <G::Engine as ScalarEngine>::Fr::zero().into_repr();
-            let one = <G::Engine as ScalarEngine>::Fr::one().into_repr();
+            // Create space for the buckets; signed digits only need one
+            // bucket per magnitude
+            let mut buckets = vec![<G as CurveAffine>::Projective::zero(); 1 << (c - 1)];
+
+            // Sort the bases into buckets; every segment of an exponent
+            // has its own precomputed multiple of the base
+            for (i, ((exp, &carries), density)) in exponents
+                .iter()
+                .zip(carries.iter())
+                .zip(density_map.as_ref().iter())
+                .enumerate()
+            {
+                if i % CANCELLATION_INTERVAL == 0 {
+                    cancellation.check()?;
+                }
 
-            // Sort the bases into buckets
-            for (&exp, density) in exponents.iter().zip(density_map.as_ref().iter()) {
                 if density {
-                    if exp == zero {
-                        bases.skip(1)?;
-                    } else if exp == one {
-                        if handle_trivial {
-                            bases.add_assign_mixed(&mut acc)?;
-                        } else {
-                            bases.skip(1)?;
-                        }
-                    } else {
-                        let mut exp = exp;
-                        exp.shr(skip);
-                        let exp = exp.as_ref()[0] % (1 << c);
+                    for segment in 0..layout.segments {
+                        let digit = layout.digit(exp.as_ref(), carries, segment, window);
 
-                        if exp != 0 {
-                            bases.add_assign_mixed(&mut buckets[(exp - 1) as usize])?;
+                        if digit > 0 {
+                            bases.add_assign_mixed(&mut buckets[(digit - 1) as usize])?;
+                        } else if digit < 0 {
+                            bases.sub_assign_mixed(&mut buckets[(-digit - 1) as usize])?;
                         } else {
                             bases.skip(1)?;
                         }
@@ -223,39 +686,116 @@ where
 
             Ok(acc)
         })
-    };
+    });
 
-    skip += c;
+    MultiexpFuture::new(windows.collect(), c)
+}
 
-    if skip >= <G::Engine as ScalarEngine>::Fr::NUM_BITS {
-        // There isn't another region.
-        Box::new(this)
-    } else {
-        // There's another region more significant. Calculate and join it with
-        // this region recursively.
-        Box::new(
-            this.join(multiexp_inner(
-                pool,
-                bases,
-                density_map,
-                exponents,
-                skip,
-                c,
-                false,
-            ))
-            .map(move |(this, mut higher)| {
-                for _ in 0..c {
-                    higher.double();
+/// The result of a multiexp, resolved once all of its windows are computed
+/// on the worker's pool.
+pub struct MultiexpFuture<G: CurveProjective> {
+    /// The sums of the windows, from the least significant one.
+    windows: Vec<WorkerFuture<G, SynthesisError>>,
+    results: Vec<Option<G>>,
+    /// The width of a window, in bits.
+    c: u32,
+}
+
+impl<G: CurveProjective> MultiexpFuture<G> {
+    fn new(windows: Vec<WorkerFuture<G, SynthesisError>>, c: u32) -> Self {
+        MultiexpFuture {
+            results: vec![None; windows.len()],
+            windows,
+            c,
+        }
+    }
+
+    fn ready(result: Result<G, SynthesisError>) -> Self {
+        Self::new(vec![WorkerFuture::ready(result)], 0)
+    }
+
+    /// Calls `callback` once every window of the multiexp is computed, on
+    /// the thread computing the last one, or right away if they all are.
+    /// It is never called if the multiexp fails.
+    pub fn on_success<F>(&self, callback: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let remaining = Arc::new(AtomicUsize::new(self.windows.len()));
+        let callback = Arc::new(Mutex::new(Some(callback)));
+
+        for window in &self.windows {
+            let remaining = remaining.clone();
+            let callback = callback.clone();
+
+            window.on_complete(move |success| {
+                if !success {
+                    callback.lock().unwrap().take();
+                } else if remaining.fetch_sub(1, Ordering::SeqCst) == 1 {
+                    if let Some(callback) = callback.lock().unwrap().take() {
+                        callback();
+                    }
                 }
+            });
+        }
+    }
 
-                higher.add_assign(&this);
+    /// Blocks the current thread until the multiexp is done.
+    pub fn wait(self) -> Result<G, SynthesisError> {
+        let mut windows = Vec::with_capacity(self.windows.len());
+        for window in self.windows {
+            windows.push(window.wait()?);
+        }
 
-                higher
-            }),
-        )
+        Ok(combine(&windows, self.c))
     }
 }
 
+// The windows and their results are never pinned
+impl<G: CurveProjective> Unpin for MultiexpFuture<G> {}
+
+impl<G: CurveProjective> Future for MultiexpFuture<G> {
+    type Output = Result<G, SynthesisError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = self.get_mut();
+
+        let mut done = true;
+        for (window, result) in this.windows.iter_mut().zip(this.results.iter_mut()) {
+            if result.is_none() {
+                match Pin::new(window).poll(cx) {
+                    Poll::Ready(Ok(sum)) => *result = Some(sum),
+                    Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                    Poll::Pending => done = false,
+                }
+            }
+        }
+
+        if done {
+            let windows = this.results.iter().map(|r| r.unwrap()).collect::<Vec<_>>();
+            Poll::Ready(Ok(combine(&windows, this.c)))
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+/// Sums the windows of a multiexp, from the least significant one, each
+/// shifted by `c` bits more than the previous one.
+fn combine<G: CurveProjective>(windows: &[G], c: u32) -> G {
+    let mut acc = G::zero();
+
+    for window in windows.iter().rev() {
+        for _ in 0..c {
+            acc.double();
+        }
+
+        acc.add_assign(window);
+    }
+
+    acc
+}
+
 /// Perform multi-exponentiation. The caller is responsible for ensuring the
 /// query size is the same as the number of exponents.
 pub fn multiexp<Q, D, G, S>(
@@ -264,7 +804,7 @@ pub fn multiexp<Q, D, G, S>(
     density_map: D,
     exponents: Arc<Vec<<<G::Engine as ScalarEngine>::Fr as PrimeField>::Repr>>,
     kern: &mut Option<gpu::MultiexpKernel<G::Engine>>,
-) -> Box<dyn Future<Item = <G as CurveAffine>::Projective, Error = SynthesisError>>
+) -> MultiexpFuture<G::Projective>
 where
     for<'a> &'a Q: QueryDensity,
     D: Send + Sync + 'static + Clone + AsRef<Q>,
@@ -272,20 +812,34 @@ where
     G::Engine: paired::Engine,
     S: SourceBuilder<G>,
 {
+    if let Err(e) = pool.cancellation().check() {
+        return MultiexpFuture::ready(Err(e));
+    }
+
     if let Some(ref mut k) = kern {
-        let mut exps = vec![exponents[0]; exponents.len()];
-        let mut n = 0;
-        for (&e, d) in exponents.iter().zip(density_map.as_ref().iter()) {
-            if d {
-                exps[n] = e;
-                n += 1;
-            }
-        }
+        // Only a density tracker leaves bases out of the query, in which
+        // case the exponents without a base are dropped
+        let exps = if density_map.as_ref().get_query_size().is_some() {
+            let n = density_map
+                .as_ref()
+                .iter()
+                .take(exponents.len())
+                .filter(|&d| d)
+                .count();
+            Arc::new(compact_exponents(
+                &exponents,
+                density_map.as_ref().iter(),
+                n,
+            ))
+        } else {
+            exponents.clone()
+        };
+        let n = exps.len();
 
         let (bss, skip) = bases.clone().get();
-        match k.multiexp(pool, bss, Arc::new(exps.clone()), skip, n) {
+        match k.multiexp(pool, bss, exps, skip, n) {
             Ok(p) => {
-                return Box::new(pool.compute(move || Ok(p)));
+                return MultiexpFuture::ready(Ok(p));
             }
             Err(e) => {
                 warn!("GPU Multiexp failed! Falling back to CPU... Error: {}", e);
@@ -293,12 +847,33 @@ where
         }
     }
 
-    let c = if exponents.len() < 32 {
+    multiexp_cpu(pool, bases, density_map, exponents)
+}
+
+fn multiexp_cpu<Q, D, G, S>(
+    pool: &Worker,
+    bases: S,
+    density_map: D,
+    exponents: Arc<Vec<<<G::Engine as ScalarEngine>::Fr as PrimeField>::Repr>>,
+) -> MultiexpFuture<G::Projective>
+where
+    for<'a> &'a Q: QueryDensity,
+    D: Send + Sync + 'static + Clone + AsRef<Q>,
+    G: CurveAffine,
+    S: SourceBuilder<G>,
+{
+    let mut c = if exponents.len() < 32 {
         3u32
     } else {
         (f64::from(exponents.len() as u32)).ln().ceil() as u32
     };
 
+    let precomputation = bases.precomputation();
+    if let Some(p) = precomputation {
+        // Wider windows than a segment only add empty buckets
+        c = cmp::min(c, p.stride + 1);
+    }
+
     if let Some(query_size) = density_map.as_ref().get_query_size() {
         // If the density map has a known query size, it should not be
         // inconsistent with the number of exponents.
@@ -306,20 +881,24 @@ where
         assert!(query_size == exponents.len());
     }
 
-    let future = multiexp_inner(pool, bases, density_map, exponents, 0, c, true);
+    let layout = DigitLayout::new::<G::Engine>(c, precomputation);
+    let carries = Arc::new(compute_signed_digit_carries::<G::Engine>(
+        pool, &exponents, layout,
+    ));
+
+    let future = multiexp_inner(pool, bases, density_map, exponents, carries, layout);
     #[cfg(feature = "gpu")]
     {
         // Do not give the control back to the caller till the
         // multiexp is done. We may want to reacquire the GPU again
         // between the multiexps.
-        let result = future.wait();
-        Box::new(pool.compute(move || result))
+        MultiexpFuture::ready(future.wait())
     }
     #[cfg(not(feature = "gpu"))]
     future
 }
 
-#[cfg(feature = "pairing")]
+#[cfg(feature = "groth16")]
 #[test]
 fn test_with_bls12() {
     fn naive_multiexp<G: CurveAffine>(
@@ -337,37 +916,349 @@ fn test_with_bls12() {
         acc
     }
 
+    use ff::Field;
     use paired::{bls12_381::Bls12, Engine};
     use rand;
 
-    const SAMPLES: usize = 1 << 14;
+    let rng = &mut rand::thread_rng();
+    let pool = Worker::new();
+
+    for &samples in &[1, 2, 31, 32, 1 << 10, 1 << 14] {
+        let mut v = (0..samples)
+            .map(|_| <Bls12 as ScalarEngine>::Fr::random(rng).into_repr())
+            .collect::<Vec<_>>();
+
+        // Exponents whose windows all carry into the next
+        let mut minus_one = <Bls12 as ScalarEngine>::Fr::one();
+        minus_one.negate();
+        v[0] = minus_one.into_repr();
+
+        let v = Arc::new(v);
+        let g = Arc::new(
+            (0..samples)
+                .map(|_| <Bls12 as Engine>::G1::random(rng).into_affine())
+                .collect::<Vec<_>>(),
+        );
+
+        let naive = naive_multiexp(g.clone(), v.clone());
+
+        let fast = multiexp(&pool, (g, 0), FullDensity, v, &mut None)
+            .wait()
+            .unwrap();
+
+        assert_eq!(naive, fast);
+    }
+}
+
+#[cfg(feature = "groth16")]
+#[test]
+fn test_sparse_exponents() {
+    use ff::Field;
+    use paired::{bls12_381::Bls12, Engine};
+    use rand::{self, Rng};
 
     let rng = &mut rand::thread_rng();
+    let pool = Worker::new();
+
+    let samples = 1 << 10;
     let v = Arc::new(
-        (0..SAMPLES)
+        (0..samples)
             .map(|_| <Bls12 as ScalarEngine>::Fr::random(rng).into_repr())
             .collect::<Vec<_>>(),
     );
+
+    let mut density = DensityTracker::new();
+    for i in 0..samples {
+        density.add_element();
+        if rng.gen() {
+            density.inc(i);
+        }
+    }
+
+    // The query only holds the bases of the dense exponents
     let g = Arc::new(
-        (0..SAMPLES)
+        (0..density.get_total_density())
             .map(|_| <Bls12 as Engine>::G1::random(rng).into_affine())
             .collect::<Vec<_>>(),
     );
 
-    let naive = naive_multiexp(g.clone(), v.clone());
+    let sparse = SparseExponents::new(&v, density.clone(), true);
+    assert!(sparse.is_compact());
+    assert_eq!(sparse.len(), density.get_total_density());
+    assert!(v
+        .iter()
+        .zip(density.bv.iter())
+        .filter(|&(_, d)| d)
+        .map(|(exp, _)| exp)
+        .eq(sparse.exponents().iter()));
+
+    // Without compacting, the exponents are shared with the assignment
+    let shared = SparseExponents::new(&v, density.clone(), false);
+    assert!(!shared.is_compact());
+    assert_eq!(shared.len(), density.get_total_density());
+    assert!(Arc::ptr_eq(&shared.exponents(), &v));
+
+    let dense = multiexp(&pool, (g.clone(), 0), Arc::new(density), v, &mut None)
+        .wait()
+        .unwrap();
+    let compacted = multiexp(
+        &pool,
+        (g.clone(), 0),
+        sparse.density(),
+        sparse.exponents(),
+        &mut None,
+    )
+    .wait()
+    .unwrap();
+    let tracked = multiexp(
+        &pool,
+        (g, 0),
+        shared.density(),
+        shared.exponents(),
+        &mut None,
+    )
+    .wait()
+    .unwrap();
+
+    assert_eq!(dense, tracked);
+    assert_eq!(dense, compacted);
+}
 
+#[cfg(feature = "groth16")]
+#[test]
+fn test_cancellation() {
+    use crate::multicore::CancellationToken;
+    use ff::Field;
+    use paired::{bls12_381::Bls12, Engine};
+    use rand;
+
+    let rng = &mut rand::thread_rng();
+
+    let token = CancellationToken::new();
+    let pool = Worker::new().with_cancellation(token.clone());
+
+    let samples = 1 << 10;
+    let v = Arc::new(
+        (0..samples)
+            .map(|_| <Bls12 as ScalarEngine>::Fr::random(rng).into_repr())
+            .collect::<Vec<_>>(),
+    );
+    let g = Arc::new(
+        (0..samples)
+            .map(|_| <Bls12 as Engine>::G1::random(rng).into_affine())
+            .collect::<Vec<_>>(),
+    );
+
+    assert!(
+        multiexp(&pool, (g.clone(), 0), FullDensity, v.clone(), &mut None)
+            .wait()
+            .is_ok()
+    );
+
+    token.cancel();
+
+    match multiexp(&pool, (g.clone(), 0), FullDensity, v.clone(), &mut None).wait() {
+        Err(SynthesisError::Cancelled) => {}
+        _ => panic!("cancelled multiexp was not stopped"),
+    }
+}
+
+#[cfg(feature = "groth16")]
+#[test]
+fn test_signed_digits() {
+    use ff::Field;
+    use paired::bls12_381::{Bls12, Fr, G1Affine};
+    use rand;
+
+    let rng = &mut rand::thread_rng();
+
+    let mut minus_one = Fr::one();
+    minus_one.negate();
+
+    let mut exponents = vec![Fr::zero(), Fr::one(), minus_one];
+    exponents.extend((0..100).map(|_| Fr::random(rng)));
+
+    let pow2 = |bits: u32| {
+        let mut pow = Fr::one();
+        for _ in 0..bits {
+            pow.double();
+        }
+        pow
+    };
+
+    for c in 3..24 {
+        for &multiples in &[None, Some(2), Some(5), Some(MAX_PRECOMPUTED_MULTIPLES)] {
+            let precomputation = multiples.map(|multiples| Precomputation {
+                multiples,
+                stride: PrecomputedBases::<G1Affine>::stride(multiples),
+            });
+            let layout = DigitLayout::new::<Bls12>(c, precomputation);
+
+            for exp in &exponents {
+                let repr = exp.into_repr();
+                let carries = layout.carries(repr.as_ref());
+
+                // Rebuild the exponent from its digits, most significant first
+                let mut acc = Fr::zero();
+                for segment in (0..layout.segments).rev() {
+                    let mut segment_acc = Fr::zero();
+                    for j in (0..layout.windows).rev() {
+                        let digit = layout.digit(repr.as_ref(), carries, segment, j);
+                        assert!(digit > -(1 << (c - 1)) && digit <= 1 << (c - 1));
+
+                        let mut magnitude = Fr::from_str(&digit.abs().to_string()).unwrap();
+                        if digit < 0 {
+                            magnitude.negate();
+                        }
+
+                        segment_acc.mul_assign(&pow2(c));
+                        segment_acc.add_assign(&magnitude);
+                    }
+
+                    acc.mul_assign(&pow2(layout.segment_bits));
+                    acc.add_assign(&segment_acc);
+                }
+
+                assert_eq!(acc, *exp);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_default_sub_assign_mixed() {
+    use paired::bls12_381::G1;
+    use rand;
+
+    // A source implementing only the required methods
+    struct Bases(Vec<<G1 as CurveProjective>::Affine>);
+
+    impl Source<<G1 as CurveProjective>::Affine> for Bases {
+        fn add_assign_mixed(&mut self, to: &mut G1) -> Result<(), SynthesisError> {
+            to.add_assign_mixed(&self.0.remove(0));
+            Ok(())
+        }
+
+        fn skip(&mut self, amt: usize) -> Result<(), SynthesisError> {
+            self.0.drain(..amt);
+            Ok(())
+        }
+    }
+
+    let rng = &mut rand::thread_rng();
+    let a = G1::random(rng);
+    let b = G1::random(rng);
+
+    let mut acc = a;
+    Bases(vec![b.into_affine()])
+        .sub_assign_mixed(&mut acc)
+        .unwrap();
+
+    let mut expected = a;
+    expected.sub_assign(&b);
+    assert_eq!(acc, expected);
+}
+
+#[cfg(feature = "groth16")]
+#[test]
+fn test_precomputed_bases() {
+    use ff::Field;
+    use paired::{bls12_381::Bls12, Engine};
+    use rand::{self, Rng};
+
+    let rng = &mut rand::thread_rng();
     let pool = Worker::new();
 
-    let fast = multiexp(&pool, (g, 0), FullDensity, v).wait().unwrap();
+    for &samples in &[1, 31, 1 << 10] {
+        let g = (0..samples + 3)
+            .map(|_| <Bls12 as Engine>::G1::random(rng).into_affine())
+            .collect::<Vec<_>>();
+
+        let mut v = (0..samples)
+            .map(|_| <Bls12 as ScalarEngine>::Fr::random(rng).into_repr())
+            .collect::<Vec<_>>();
+        let mut minus_one = <Bls12 as ScalarEngine>::Fr::one();
+        minus_one.negate();
+        v[0] = minus_one.into_repr();
+        let v = Arc::new(v);
+
+        // Only some of the exponents have a base, starting at an offset
+        let mut density = DensityTracker::new();
+        for i in 0..samples {
+            density.add_element();
+            if i == 0 || rng.gen() {
+                density.inc(i);
+            }
+        }
+        let density = Arc::new(density);
+
+        let expected = multiexp(
+            &pool,
+            (Arc::new(g.clone()), 3),
+            density.clone(),
+            v.clone(),
+            &mut None,
+        )
+        .wait()
+        .unwrap();
+
+        for &multiples in &[1, 2, 3, 8, MAX_PRECOMPUTED_MULTIPLES] {
+            let precomputed = PrecomputedBases::new(&pool, &g, multiples);
+            assert_eq!(precomputed.len(), g.len());
+            assert_eq!(precomputed.bases(), g);
+
+            let stride = precomputed.precomputation().stride;
+            assert!(stride * multiples as u32 >= 255);
 
-    assert_eq!(naive, fast);
+            let mut multiple = g[1].into_projective();
+            for _ in 0..stride {
+                multiple.double();
+            }
+            if multiples > 1 {
+                assert_eq!(precomputed.table()[multiples + 1], multiple.into_affine());
+            }
+
+            let fast = multiexp(
+                &pool,
+                (precomputed.clone(), 3),
+                density.clone(),
+                v.clone(),
+                &mut None,
+            )
+            .wait()
+            .unwrap();
+
+            assert_eq!(expected, fast);
+
+            // Consumers without precomputation share a single copy of the
+            // bases
+            let (bases, offset) = (precomputed.clone(), 3).get();
+            assert_eq!(&bases[..], &g[..]);
+            assert_eq!(offset, 3);
+            assert!(Arc::ptr_eq(&bases, &(precomputed.clone(), 0).get().0));
+
+            let reread =
+                PrecomputedBases::from_table(precomputed.table().to_vec(), multiples).unwrap();
+            assert_eq!(reread.precomputation(), precomputed.precomputation());
+        }
+    }
 }
 
 pub fn create_multiexp_kernel<E>() -> Option<gpu::MultiexpKernel<E>>
 where
     E: paired::Engine,
 {
-    match gpu::MultiexpKernel::<E>::create() {
+    create_multiexp_kernel_with_backend(&gpu::DefaultBackend)
+}
+
+/// Creates a multiexp kernel on the devices of the compute backend
+/// `backend`.
+pub fn create_multiexp_kernel_with_backend<E, B>(backend: &B) -> Option<gpu::MultiexpKernel<E>>
+where
+    E: paired::Engine,
+    B: gpu::ComputeBackend<E>,
+{
+    match backend.create_multiexp_kernel() {
         Ok(k) => {
             info!("GPU Multiexp kernel instantiated!");
             Some(k)
@@ -382,6 +1273,7 @@ where
 #[cfg(feature = "gpu")]
 #[test]
 pub fn gpu_multiexp_consistency() {
+    use ff::Field;
     use paired::bls12_381::Bls12;
     use std::time::Instant;
 
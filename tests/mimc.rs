@@ -17,8 +17,10 @@ use bellperson::{Circuit, ConstraintSystem, SynthesisError};
 // We're going to use the Groth16 proving system.
 use bellperson::groth16::{
     create_random_proof, create_random_proof_batch, generate_random_parameters,
-    prepare_batch_verifying_key, prepare_verifying_key, verify_proof, verify_proofs_batch, Proof,
+    prepare_batch_verifying_key, prepare_verifying_key, verify_proof, verify_proofs_batch,
+    verify_proofs_batch_with_worker, Proof,
 };
+use bellperson::multicore::Worker;
 
 const MIMC_ROUNDS: usize = 322;
 
@@ -276,6 +278,17 @@ fn test_mimc() {
         );
         assert!(valid, "failed batch verification");
 
+        // on the pool of a worker too
+        let worker = Worker::with_num_threads(2);
+        assert!(verify_proofs_batch_with_worker(
+            &worker,
+            &pvk,
+            &mut rand::rngs::OsRng,
+            &proofs,
+            &images
+        )
+        .unwrap());
+
         // check that invalid proofs don't validate
         let mut bad_proofs = proofs
             .iter()